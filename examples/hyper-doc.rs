@@ -17,7 +17,7 @@ use hyper::server::{Http, Request, Response, Service};
 use hyper::{header, Error, StatusCode};
 
 extern crate hyper_fs;
-use hyper_fs::{error_handler, Config, FutureObject, HyperFutureObject, StaticFs};
+use hyper_fs::{error_handler, Config, FutureObject, HyperFutureObject, Mounts};
 
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -54,12 +54,8 @@ fn main() {
 }
 
 pub struct DogeInner {
-    doc: Option<PathBuf>,  // target/doc
-    rust: Option<PathBuf>, // $HOME/.rustup/toolchains/nightly-x86_64-unknown-linux-gnu/share/doc/rust/html
-    path: PathBuf,         // default: ./
-    pool: CpuPool,
-    handle: Handle,
-    config: Arc<Config>,
+    rust: bool, // $HOME/.rustup/toolchains/nightly-x86_64-unknown-linux-gnu/share/doc/rust/html
+    mounts: Mounts<Arc<Config>>,
 }
 
 #[derive(Clone)]
@@ -85,63 +81,38 @@ fn rust() -> Option<PathBuf> {
 
 impl Doge {
     fn new(handle: Handle, pool: CpuPool, config: Config) -> Self {
-        let doc = PathBuf::from("target/doc");
-        let doc = if doc.as_path().is_dir() {
-            Some(doc)
-        } else {
-            None
-        };
+        let config = Arc::new(config);
         let path = PathBuf::from(env::args().nth(2).unwrap_or_else(|| "./".to_owned()));
+        let mut mounts = Mounts::new(handle, pool).mount("/", path, config.clone());
+
+        // target/doc
+        let doc = PathBuf::from("target/doc");
+        if doc.as_path().is_dir() {
+            mounts = mounts.mount("/doc/", doc, config.clone());
+        }
+        let rust = rust();
+        if let Some(rust) = rust.as_ref() {
+            mounts = mounts.mount("/rust/", rust, config.clone());
+        }
         let inner = DogeInner {
-            doc: doc,
-            rust: rust(),
-            path: path,
-            pool: pool,
-            handle: handle,
-            config: Arc::new(config),
+            rust: rust.is_some(),
+            mounts,
         };
         Doge {
             inner: Rc::new(inner),
         }
     }
     fn call_inner(&self, req: Request) -> FutureObject {
-        let path = req.path().to_owned();
-        // /doc
-        if self.inner.doc.is_some() && (path.starts_with("/doc/") || path.as_str() == "/doc") {
-            StaticFs::new(
-                self.inner.handle.clone(),
-                self.inner.pool.clone(),
-                "/doc/",
-                self.inner.doc.as_ref().unwrap(),
-                self.inner.config.clone(),
-            ).call(req)
-
         // /rust
-        } else if self.inner.rust.is_some() && path.as_str() == "/rust" {
+        if self.inner.rust && req.path() == "/rust" {
             Box::new(future::ok((
                 Response::new()
                     .with_status(StatusCode::MovedPermanently)
                     .with_header(header::Location::new("/rust/index.html")),
                 req,
             )))
-        } else if self.inner.rust.is_some() && path.starts_with("/rust/") {
-            StaticFs::new(
-                self.inner.handle.clone(),
-                self.inner.pool.clone(),
-                "/rust/",
-                self.inner.rust.as_ref().unwrap(),
-                self.inner.config.clone(),
-            ).call(req)
-        }
-        // path
-        else {
-            StaticFs::new(
-                self.inner.handle.clone(),
-                self.inner.pool.clone(),
-                "/",
-                self.inner.path.clone(),
-                self.inner.config.clone(),
-            ).call(req)
+        } else {
+            self.inner.mounts.call(req)
         }
     }
 }
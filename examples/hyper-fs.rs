@@ -20,12 +20,12 @@ use hyper::Error;
 use url::percent_encoding::percent_decode;
 
 extern crate hyper_fs;
-use hyper_fs::{error_handler, Config, HyperFutureObject, StaticFs};
+use hyper_fs::{error_handler, Config, ContentCache, HyperFutureObject, MetadataCache, StaticFs};
 
 use std::path::PathBuf;
-use std::time::Instant;
-use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::rc::Rc;
+use std::sync::Arc;
 use std::env;
 
 fn main() {
@@ -41,7 +41,11 @@ fn main() {
     let config = Config::new()
         .cache_secs(60)
         .follow_links(true)
-        .show_index(true); // .chunk_size(8196)
+        .show_index(true)
+        .archive(true)
+        .read_ahead(4) // .chunk_size(8196)
+        .content_cache(Arc::new(ContentCache::new(64 * 1024 * 1024, 1024 * 1024)))
+        .metadata_cache(Arc::new(MetadataCache::new(Duration::from_secs(60), 4096)));
 
     let mut core = Core::new().unwrap();
     let handle = core.handle();
@@ -58,25 +62,19 @@ fn main() {
 }
 
 struct FileServer {
-    handle: Handle,
-    path: PathBuf,
-    pool: CpuPool,
-    headers_index: Option<Headers>,
-    config: Arc<Config>,
+    fs: StaticFs<Config>,
 }
 impl FileServer {
     fn new<P: Into<PathBuf>>(handle: Handle, pool: CpuPool, path: P, config: Config) -> Self {
-        Self {
-            handle: handle,
-            path: path.into(),
-            pool: pool,
-            config: Arc::new(config),
-            headers_index: Some({
-                let mut tmp = Headers::new();
-                tmp.set(header::ContentType(mime::TEXT_HTML_UTF_8));
-                tmp
-            }),
-        }
+        let mut fs = StaticFs::new(handle, pool, "/", path, config);
+        // add `Content-Type` for index, `StaticFs` alrendy add `Content-Type` by `content_type_maker`(mime_guess crate) default.
+        // use `static_fs` or `StaticFile` or `static_file` directly if need to use custom `Content-Type` for file.
+        *fs.headers_index_mut() = Some({
+            let mut tmp = Headers::new();
+            tmp.set(header::ContentType(mime::TEXT_HTML_UTF_8));
+            tmp
+        });
+        Self { fs }
     }
 }
 
@@ -87,18 +85,7 @@ impl Service for FileServer {
     type Future = HyperFutureObject;
     fn call(&self, req: Request) -> Self::Future {
         let timer = Instant::now();
-        let mut fs = StaticFs::new(
-            self.handle.clone(),
-            self.pool.clone(),
-            "/",
-            &self.path,
-            self.config.clone(),
-        );
-        // add `Content-Type` for index, `StaticFs` alrendy add `Content-Type` by `content_type_maker`(mime_guess crate) default.
-        // use `static_fs` or `StaticFile` or `static_file` directly if need to use custom `Content-Type` for file.
-        *fs.headers_index_mut() = self.headers_index.clone();
-
-        let fs = fs.call(req);
+        let fs = self.fs.call(req);
 
         Box::new(fs.or_else(error_handler).map(move |res_req| {
             // remote addr
@@ -119,11 +106,7 @@ impl Service for FileServer {
                 ms,
                 res_req.0.status().as_u16(),
                 res_req.1.method(),
-                percent_decode(res_req.1.path().as_bytes())
-                    .decode_utf8()
-                    .unwrap()
-                    .into_owned()
-                    .to_owned()
+                percent_decode(res_req.1.path().as_bytes()).decode_utf8_lossy()
             );
             // extract response
             res_req.0
@@ -0,0 +1,188 @@
+use executor::BlockingExecutor;
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::fmt;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// The class of the blocking work, every class has its own concurrency limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkClass {
+    /// Render the index(`read_dir`).
+    Index,
+    /// Open and read the files.
+    File,
+}
+
+#[derive(Default)]
+struct Class {
+    limit: usize,
+    running: usize,
+    queue: VecDeque<Job>,
+}
+
+/// Admission control of the `BlockingExecutor`, set it by `Config::admission`.
+///
+/// At most `limit` jobs of a `WorkClass` run at the same time, the others wait in its queue,
+/// a new request is rejected(`Error::Busy`, `503` with `Retry-After`) if the queue of its class is deeper than `max_queue`.
+pub struct Admission {
+    index: Mutex<Class>,
+    file: Mutex<Class>,
+    max_queue: usize,
+    retry_after: u32,
+    rejected: AtomicUsize,
+}
+
+impl Admission {
+    /// `index_limit` and `file_limit` are the concurrency limits of the classes, less than the threads of the pool usually.
+    pub fn new(index_limit: usize, file_limit: usize, max_queue: usize) -> Self {
+        let class = |limit: usize| {
+            Mutex::new(Class {
+                limit: ::std::cmp::max(limit, 1),
+                ..Class::default()
+            })
+        };
+        Self {
+            index: class(index_limit),
+            file: class(file_limit),
+            max_queue,
+            retry_after: 1,
+            rejected: AtomicUsize::new(0),
+        }
+    }
+    /// The seconds of `Retry-After` for the rejected requests, default is 1.
+    pub fn retry_after(mut self, retry_after: u32) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+    pub fn get_retry_after(&self) -> u32 {
+        self.retry_after
+    }
+    pub fn max_queue(&self) -> usize {
+        self.max_queue
+    }
+    pub fn limit(&self, class: WorkClass) -> usize {
+        self.class(class).lock().unwrap().limit
+    }
+    /// How many jobs of the `class` are running.
+    pub fn running(&self, class: WorkClass) -> usize {
+        self.class(class).lock().unwrap().running
+    }
+    /// How many jobs of the `class` are waiting.
+    pub fn queued(&self, class: WorkClass) -> usize {
+        self.class(class).lock().unwrap().queue.len()
+    }
+    /// How many requests are rejected.
+    pub fn rejected(&self) -> usize {
+        self.rejected.load(Ordering::Relaxed)
+    }
+    fn class(&self, class: WorkClass) -> &Mutex<Class> {
+        match class {
+            WorkClass::Index => &self.index,
+            WorkClass::File => &self.file,
+        }
+    }
+    /// Whether a new request of the `class` is admitted, it counts the rejected.
+    pub(crate) fn admit(&self, class: WorkClass) -> bool {
+        if self.queued(class) <= self.max_queue {
+            return true;
+        }
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+    fn execute(this: &Arc<Self>, executor: &Arc<dyn BlockingExecutor>, class: WorkClass, job: Job) {
+        {
+            let mut state = this.class(class).lock().unwrap();
+            if state.running >= state.limit {
+                state.queue.push_back(job);
+                return;
+            }
+            state.running += 1;
+        }
+        Self::run(this.clone(), executor.clone(), class, job);
+    }
+    // run the `job`, then the next queued one in the same slot.
+    fn run(this: Arc<Self>, executor: Arc<dyn BlockingExecutor>, class: WorkClass, job: Job) {
+        let executor2 = executor.clone();
+        executor.execute(Box::new(move || {
+            job();
+            let next = {
+                let mut state = this.class(class).lock().unwrap();
+                let next = state.queue.pop_front();
+                if next.is_none() {
+                    state.running -= 1;
+                }
+                next
+            };
+            if let Some(next) = next {
+                Self::run(this, executor2, class, next);
+            }
+        }))
+    }
+}
+
+impl fmt::Debug for Admission {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Admission")
+            .field("index", &(self.running(WorkClass::Index), self.queued(WorkClass::Index)))
+            .field("file", &(self.running(WorkClass::File), self.queued(WorkClass::File)))
+            .field("max_queue", &self.max_queue)
+            .field("rejected", &self.rejected())
+            .finish()
+    }
+}
+
+/// The `BlockingExecutor` runs the jobs of a `WorkClass` under the `Admission`.
+pub(crate) struct ClassExecutor {
+    admission: Arc<Admission>,
+    executor: Arc<dyn BlockingExecutor>,
+    class: WorkClass,
+}
+
+impl ClassExecutor {
+    pub(crate) fn new(admission: Arc<Admission>, executor: Arc<dyn BlockingExecutor>, class: WorkClass) -> Self {
+        Self {
+            admission,
+            executor,
+            class,
+        }
+    }
+}
+
+impl BlockingExecutor for ClassExecutor {
+    fn execute(&self, job: Box<dyn FnOnce() + Send + 'static>) {
+        Admission::execute(&self.admission, &self.executor, self.class, job)
+    }
+}
+
+#[test]
+fn admission_test() {
+    use std::sync::mpsc;
+
+    // run the jobs later by hand
+    struct Manual(Mutex<Vec<Job>>);
+    impl BlockingExecutor for Manual {
+        fn execute(&self, job: Job) {
+            self.0.lock().unwrap().push(job)
+        }
+    }
+    let manual = Arc::new(Manual(Mutex::new(vec![])));
+    let admission = Arc::new(Admission::new(1, 1, 1));
+    let executor = ClassExecutor::new(admission.clone(), manual.clone(), WorkClass::File);
+    let (tx, rx) = mpsc::channel();
+    for i in 0..3 {
+        let tx = tx.clone();
+        executor.execute(Box::new(move || tx.send(i).unwrap()));
+    }
+    assert_eq!((admission.running(WorkClass::File), admission.queued(WorkClass::File)), (1, 2));
+    assert!(!admission.admit(WorkClass::File));
+    assert!(admission.admit(WorkClass::Index));
+    for _ in 0..3 {
+        let job = manual.0.lock().unwrap().pop().unwrap();
+        job();
+    }
+    assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+    assert_eq!((admission.running(WorkClass::File), admission.rejected()), (0, 1));
+}
@@ -0,0 +1,857 @@
+use futures::sync::mpsc::SendError;
+use futures::{future, Async, Future, Poll, Sink, Stream};
+use hyper::server::{Request, Response};
+use hyper::{header, Body as HyperBody, Chunk, Error as HyperError, Method};
+use url::form_urlencoded;
+use walkdir::{self, DirEntry, FilterEntry, WalkDir};
+
+use super::{Config, Disposition, Error, FutureObject};
+use flate2::write::GzEncoder;
+use flate2::{Compress, Compression, Crc, FlushCompress, Status};
+use disposition;
+use executor::{spawn_fn, Blocking, BlockingExecutor, Spawner};
+use date::civil;
+use static_index::is_hidden;
+
+use std::collections::VecDeque;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use std::cmp;
+
+// the bytes of a chunk of the body at least, the bytes read from a file at most
+const CHUNK_SIZE: usize = 64 * 1024;
+// the max bytes of the names selected by `POST`
+const MAX_NAMES: usize = 64 * 1024;
+// the files of it or larger are written in zip64, the deflate may be larger than the file a little
+const ZIP64_SIZE: u64 = 0xf000_0000;
+
+/// The formats of `?archive=`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Format {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl Format {
+    pub(crate) fn parse(format: &str) -> Option<Self> {
+        match format {
+            "tar" => Some(Format::Tar),
+            "tar.gz" | "tgz" => Some(Format::TarGz),
+            "zip" => Some(Format::Zip),
+            _ => None,
+        }
+    }
+    pub(crate) fn extension(&self) -> &'static str {
+        match *self {
+            Format::Tar => "tar",
+            Format::TarGz => "tar.gz",
+            Format::Zip => "zip",
+        }
+    }
+    fn mime(&self) -> &'static str {
+        match *self {
+            Format::Tar => "application/x-tar",
+            Format::TarGz => "application/gzip",
+            Format::Zip => "application/zip",
+        }
+    }
+}
+
+/// Respond the `archive` named `name`(without the extension),
+/// the body is generated by the jobs on `executor` while it is being sent.
+pub(crate) fn respond(
+    spawner: &dyn Spawner,
+    executor: Arc<dyn BlockingExecutor>,
+    archive: Archive,
+    name: &str,
+    headers: Option<header::Headers>,
+    req: Request,
+) -> FutureObject {
+    let mut res = Response::new();
+    if let Some(headers) = headers {
+        res = res.with_headers(headers);
+    }
+    let format = archive.format;
+    res.headers_mut().set_raw("Content-Type", format.mime());
+    let file_name = format!("{}.{}", name, format.extension());
+    res.headers_mut().set_raw("Content-Disposition", disposition::header(Disposition::Attachment, &file_name));
+    res.headers_mut().set(header::CacheControl(vec![header::CacheDirective::NoCache]));
+    if *req.method() != Method::Head {
+        let (sender, body) = HyperBody::pair();
+        res.set_body(body);
+        let stream = ArchiveStream::new(executor, archive);
+        spawner.spawn(Box::new(sender.send_all(stream).map(|_| ()).map_err(|_| ())));
+    }
+    Box::new(future::ok((res, req)))
+}
+
+/// The names of the entries selected by `POST`(`name=a&name=b`), they are sorted and deduplicated.
+pub(crate) fn read_names(req: &mut Request) -> Box<dyn Future<Item = Vec<String>, Error = Error>> {
+    match req.headers().get::<header::ContentType>() {
+        Some(header::ContentType(mime))
+            if mime.type_() != ::hyper::mime::APPLICATION || mime.subtype() != ::hyper::mime::WWW_FORM_URLENCODED =>
+        {
+            return Box::new(future::err(Error::MediaType))
+        }
+        _ => {}
+    }
+    let body = req.body_mut().take().unwrap_or_default();
+    let names = body.map_err(|e| Error::Io(io::Error::other(e)))
+        .fold(vec![], |mut buf, chunk| {
+            buf.extend_from_slice(&chunk);
+            if buf.len() > MAX_NAMES {
+                Err(Error::TooLarge)
+            } else {
+                Ok(buf)
+            }
+        })
+        .and_then(|buf| {
+            let mut names = form_urlencoded::parse(&buf)
+                .filter(|(k, _)| k == "name")
+                .map(|(_, v)| v.into_owned())
+                .collect::<Vec<_>>();
+            names.sort();
+            names.dedup();
+            if names.is_empty() {
+                Err(Error::Malformed)
+            } else {
+                Ok(names)
+            }
+        });
+    Box::new(names)
+}
+
+/// The roots of the `names` in `dir`, they must be the entries of it and not be hidden if `hide_entry`.
+pub(crate) fn select(dir: &Path, names: Vec<String>, config: &Config) -> Result<Vec<(PathBuf, String)>, Error> {
+    let mut roots = Vec::with_capacity(names.len());
+    for name in names {
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\\') {
+            return Err(Error::forbidden());
+        }
+        if config.get_hide_entry() && name.starts_with('.') {
+            return Err(Error::not_found());
+        }
+        let path = dir.join(&name);
+        fs::symlink_metadata(&path)?;
+        roots.push((path, name));
+    }
+    Ok(roots)
+}
+
+// the archive and its next chunk
+type Next = (Archive, Option<Vec<u8>>);
+
+/// Generate the chunks one by one, the next one is being generated while the last one is being sent.
+struct ArchiveStream {
+    pending: Option<Blocking<Next, io::Error>>,
+    executor: Arc<dyn BlockingExecutor>,
+}
+
+impl ArchiveStream {
+    fn new(executor: Arc<dyn BlockingExecutor>, archive: Archive) -> Self {
+        let mut stream = ArchiveStream {
+            pending: None,
+            executor,
+        };
+        stream.schedule(archive);
+        stream
+    }
+    fn schedule(&mut self, mut archive: Archive) {
+        self.pending = Some(spawn_fn(&*self.executor, move || {
+            let chunk = archive.next_chunk()?;
+            Ok((archive, chunk))
+        }));
+    }
+}
+
+impl Stream for ArchiveStream {
+    type Item = Result<Chunk, HyperError>;
+    type Error = SendError<Self::Item>;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let next = match self.pending.as_mut().map(|pending| pending.poll()) {
+            Some(Ok(Async::Ready(next))) => next,
+            Some(Ok(Async::NotReady)) => return Ok(Async::NotReady),
+            // end the body before the end of the archive(the `Err` panics tokio-proto as `FileChunkStream`),
+            // the client sees a broken archive instead of a wrong one.
+            Some(Err(e)) => {
+                warn!("abort the archive: {}", e);
+                self.pending = None;
+                return Ok(Async::Ready(None));
+            }
+            None => return Ok(Async::Ready(None)),
+        };
+        self.pending = None;
+        match next {
+            (archive, Some(chunk)) => {
+                self.schedule(archive);
+                Ok(Async::Ready(Some(Ok(Chunk::from(chunk)))))
+            }
+            (_, None) => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+enum Kind {
+    Dir,
+    File(u64),
+    Link(String),
+}
+
+struct Entry {
+    path: PathBuf,
+    // the path in the archive, `/` is appended to the directories
+    name: String,
+    kind: Kind,
+    mode: u32,
+    mtime: SystemTime,
+}
+
+type Walk = FilterEntry<walkdir::IntoIter, fn(&DirEntry) -> bool>;
+
+/// Walk the roots one by one.
+struct Walker {
+    roots: VecDeque<(PathBuf, String)>,
+    walk: Option<(Walk, PathBuf, String)>,
+    follow_links: bool,
+    hide_entry: bool,
+}
+
+impl Iterator for Walker {
+    type Item = Entry;
+    fn next(&mut self) -> Option<Entry> {
+        loop {
+            if self.walk.is_none() {
+                let (path, name) = self.roots.pop_front()?;
+                let filter: fn(&DirEntry) -> bool = if self.hide_entry { visible } else { all };
+                let walk = WalkDir::new(&path)
+                    .follow_links(self.follow_links)
+                    .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+                    .into_iter()
+                    .filter_entry(filter);
+                self.walk = Some((walk, path, name));
+            }
+            let next = match self.walk {
+                Some((ref mut walk, ref root, ref name)) => walk.next().map(|e| e.map(|e| entry(&e, root, name))),
+                None => None,
+            };
+            match next {
+                Some(Ok(Some(entry))) => return Some(entry),
+                Some(Ok(None)) => {}
+                Some(Err(e)) => warn!("archive: {}", e),
+                None => self.walk = None,
+            }
+        }
+    }
+}
+
+// the roots are not hidden by themselves
+fn visible(entry: &DirEntry) -> bool {
+    entry.depth() == 0 || !is_hidden(entry)
+}
+
+fn all(_: &DirEntry) -> bool {
+    true
+}
+
+// the directories, files and symlinks(not followed)
+fn entry(e: &DirEntry, root: &Path, name: &str) -> Option<Entry> {
+    let md = match e.metadata() {
+        Ok(md) => md,
+        Err(err) => {
+            warn!("archive: {}", err);
+            return None;
+        }
+    };
+    let kind = if md.is_dir() {
+        Kind::Dir
+    } else if md.is_file() {
+        Kind::File(md.len())
+    } else if md.file_type().is_symlink() {
+        Kind::Link(fs::read_link(e.path()).ok()?.to_string_lossy().into_owned())
+    } else {
+        return None;
+    };
+    let mut name = name.to_owned();
+    if let Ok(rest) = e.path().strip_prefix(root) {
+        for c in rest.components() {
+            name.push('/');
+            name.push_str(&c.as_os_str().to_string_lossy());
+        }
+    }
+    if let Kind::Dir = kind {
+        name.push('/');
+    }
+    Some(Entry {
+        path: e.path().to_owned(),
+        name,
+        kind,
+        mode: mode(&md),
+        mtime: md.modified().unwrap_or(::std::time::UNIX_EPOCH),
+    })
+}
+
+cfg_if! {
+    if #[cfg(unix)] {
+        fn mode(md: &Metadata) -> u32 {
+            use std::os::unix::fs::PermissionsExt;
+            md.permissions().mode() & 0o7777
+        }
+    } else {
+        fn mode(md: &Metadata) -> u32 {
+            if md.is_dir() { 0o755 } else { 0o644 }
+        }
+    }
+}
+
+/// The file being read into the archive.
+struct Reading {
+    file: File,
+    path: PathBuf,
+    size: u64,
+    // the bytes left of `size`
+    left: u64,
+    zip: Option<ZipEntry>,
+}
+
+/// The tar(ustar with pax headers), tar.gz or zip(deflate, zip64) of the roots.
+pub(crate) struct Archive {
+    format: Format,
+    walker: Walker,
+    // the files of zip
+    deflate: Compress,
+    gzip: Option<GzEncoder<Vec<u8>>>,
+    // the tar or zip not sent
+    buf: Vec<u8>,
+    // the bytes of tar or zip sent
+    flushed: u64,
+    scratch: Vec<u8>,
+    reading: Option<Reading>,
+    central: Vec<u8>,
+    count: u64,
+    done: bool,
+}
+
+impl Archive {
+    /// The archive of the `roots`, the paths and their names in it.
+    pub(crate) fn new(format: Format, roots: Vec<(PathBuf, String)>, config: &Config) -> Self {
+        Archive {
+            format,
+            walker: Walker {
+                roots: roots.into_iter().collect(),
+                walk: None,
+                follow_links: config.get_follow_links(),
+                hide_entry: config.get_hide_entry(),
+            },
+            deflate: Compress::new(Compression::default(), false),
+            gzip: if format == Format::TarGz {
+                Some(GzEncoder::new(Vec::new(), Compression::default()))
+            } else {
+                None
+            },
+            buf: Vec::new(),
+            flushed: 0,
+            scratch: vec![0; CHUNK_SIZE],
+            reading: None,
+            central: Vec::new(),
+            count: 0,
+            done: false,
+        }
+    }
+    /// The next chunk, `None` at the end, it blocks.
+    ///
+    /// The unreadable entries are skipped, it fails if a file fails or is shortened while being read.
+    pub(crate) fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut out = Vec::with_capacity(CHUNK_SIZE * 2);
+        while out.len() < CHUNK_SIZE && !self.done {
+            match self.reading.take() {
+                Some(reading) => self.read(reading)?,
+                None => match self.walker.next() {
+                    Some(entry) => match self.format {
+                        Format::Zip => self.zip_header(entry),
+                        _ => self.tar_header(entry),
+                    },
+                    None => {
+                        self.end();
+                        self.done = true;
+                    }
+                },
+            }
+            self.flushed += self.buf.len() as u64;
+            match self.gzip {
+                Some(ref mut gzip) => {
+                    // writing to a `Vec` does not fail
+                    gzip.write_all(&self.buf).expect("gzip");
+                    if self.done {
+                        gzip.try_finish().expect("gzip");
+                    }
+                    out.append(gzip.get_mut());
+                }
+                None => out.extend_from_slice(&self.buf),
+            }
+            self.buf.clear();
+        }
+        if out.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(out))
+        }
+    }
+    fn position(&self) -> u64 {
+        self.flushed + self.buf.len() as u64
+    }
+    fn open(entry: &Entry) -> Option<File> {
+        File::open(&entry.path)
+            .map_err(|e| warn!("archive {:?}: {}", entry.path, e))
+            .ok()
+    }
+    fn read(&mut self, mut reading: Reading) -> io::Result<()> {
+        let len = cmp::min(reading.left, CHUNK_SIZE as u64) as usize;
+        let n = read_full(&mut reading.file, &mut self.scratch[..len])
+            .map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", reading.path, e)))?;
+        if n < len {
+            let msg = format!("{:?}: shortened while being read", reading.path);
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg));
+        }
+        reading.left -= len as u64;
+        match reading.zip.take() {
+            None => {
+                self.buf.extend_from_slice(&self.scratch[..n]);
+                if reading.left > 0 {
+                    self.reading = Some(reading);
+                } else {
+                    pad(&mut self.buf, reading.size);
+                }
+            }
+            Some(mut zip) => {
+                zip.crc.update(&self.scratch[..n]);
+                zip.size += n as u64;
+                deflate(&mut self.deflate, &self.scratch[..n], FlushCompress::None, &mut self.buf);
+                if reading.left > 0 {
+                    reading.zip = Some(zip);
+                    self.reading = Some(reading);
+                } else {
+                    deflate(&mut self.deflate, &[], FlushCompress::Finish, &mut self.buf);
+                    self.deflate.reset();
+                    self.zip_end(zip);
+                }
+            }
+        }
+        Ok(())
+    }
+    fn end(&mut self) {
+        if self.format != Format::Zip {
+            self.buf.resize(self.buf.len() + 1024, 0);
+            return;
+        }
+        let (offset, size) = (self.position(), self.central.len() as u64);
+        self.buf.extend_from_slice(&self.central);
+        let max = u64::from(u32::MAX);
+        if offset >= max || size >= max || self.count >= 0xffff {
+            let end64 = self.position();
+            le32(&mut self.buf, 0x0606_4b50);
+            le64(&mut self.buf, 44);
+            le16(&mut self.buf, VERSION_MADE_BY);
+            le16(&mut self.buf, 45);
+            le32(&mut self.buf, 0);
+            le32(&mut self.buf, 0);
+            le64(&mut self.buf, self.count);
+            le64(&mut self.buf, self.count);
+            le64(&mut self.buf, size);
+            le64(&mut self.buf, offset);
+            // the locator
+            le32(&mut self.buf, 0x0706_4b50);
+            le32(&mut self.buf, 0);
+            le64(&mut self.buf, end64);
+            le32(&mut self.buf, 1);
+        }
+        let count = cmp::min(self.count, 0xffff) as u16;
+        le32(&mut self.buf, 0x0605_4b50);
+        le32(&mut self.buf, 0);
+        le16(&mut self.buf, count);
+        le16(&mut self.buf, count);
+        le32(&mut self.buf, cmp::min(size, max) as u32);
+        le32(&mut self.buf, cmp::min(offset, max) as u32);
+        le16(&mut self.buf, 0);
+    }
+}
+
+fn read_full(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match file.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(m) => n += m,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+// tar
+
+const TAR_MAX_SIZE: u64 = 0o777_7777_7777;
+
+impl Archive {
+    fn tar_header(&mut self, entry: Entry) {
+        let (flag, size, link) = match entry.kind {
+            Kind::Dir => (b'5', 0, ""),
+            Kind::File(size) => (b'0', size, ""),
+            Kind::Link(ref link) => (b'2', 0, link.as_str()),
+        };
+        let file = match entry.kind {
+            Kind::File(size) if size > 0 => match Self::open(&entry) {
+                Some(file) => Some(file),
+                None => return,
+            },
+            _ => None,
+        };
+        let mtime = entry
+            .mtime
+            .duration_since(::std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let (name, link) = (entry.name.as_bytes(), link.as_bytes());
+        // the long names and large sizes
+        let mut pax = vec![];
+        if name.len() > 100 {
+            pax_record(&mut pax, "path", name);
+        }
+        if link.len() > 100 {
+            pax_record(&mut pax, "linkpath", link);
+        }
+        if size > TAR_MAX_SIZE {
+            pax_record(&mut pax, "size", size.to_string().as_bytes());
+        }
+        if !pax.is_empty() {
+            ustar(&mut self.buf, b"././@PaxHeader", b'x', pax.len() as u64, 0o644, mtime, b"");
+            self.buf.extend_from_slice(&pax);
+            pad(&mut self.buf, pax.len() as u64);
+        }
+        let cut = |s: &[u8]| s[..cmp::min(s.len(), 100)].to_vec();
+        let ustar_size = if size > TAR_MAX_SIZE { 0 } else { size };
+        ustar(&mut self.buf, &cut(name), flag, ustar_size, entry.mode, mtime, &cut(link));
+        if let Some(file) = file {
+            self.reading = Some(Reading {
+                file,
+                path: entry.path,
+                size,
+                left: size,
+                zip: None,
+            });
+        }
+    }
+}
+
+fn ustar(out: &mut Vec<u8>, name: &[u8], flag: u8, size: u64, mode: u32, mtime: u64, link: &[u8]) {
+    let mut h = [0u8; 512];
+    h[..name.len()].copy_from_slice(name);
+    octal(&mut h[100..108], u64::from(mode));
+    octal(&mut h[108..116], 0);
+    octal(&mut h[116..124], 0);
+    octal(&mut h[124..136], size);
+    octal(&mut h[136..148], cmp::min(mtime, TAR_MAX_SIZE));
+    h[156] = flag;
+    h[157..157 + link.len()].copy_from_slice(link);
+    h[257..265].copy_from_slice(b"ustar\x0000");
+    for b in &mut h[148..156] {
+        *b = b' ';
+    }
+    let sum = h.iter().map(|&b| u64::from(b)).sum();
+    octal(&mut h[148..155], sum);
+    out.extend_from_slice(&h);
+}
+
+// the digits and a NUL
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:01$o}", value, field.len() - 1);
+    let len = field.len() - 1;
+    field[..len].copy_from_slice(&digits.as_bytes()[digits.len() - len..]);
+    field[len] = 0;
+}
+
+// `len key=value\n`, the len counts itself
+fn pax_record(out: &mut Vec<u8>, key: &str, value: &[u8]) {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    out.extend_from_slice(format!("{} {}=", len, key).as_bytes());
+    out.extend_from_slice(value);
+    out.push(b'\n');
+}
+
+// to the 512 bytes blocks
+fn pad(out: &mut Vec<u8>, size: u64) {
+    let padded = out.len() + ((512 - size % 512) % 512) as usize;
+    out.resize(padded, 0);
+}
+
+// zip
+
+const VERSION_MADE_BY: u16 = 3 << 8 | 45;
+// the sizes and crc are in the data descriptor, the name is UTF-8
+const FLAGS_DESCRIPTOR: u16 = 0x0808;
+const FLAGS: u16 = 0x0800;
+
+struct ZipEntry {
+    name: String,
+    method: u16,
+    flags: u16,
+    time: u16,
+    date: u16,
+    external: u32,
+    crc: Crc,
+    size: u64,
+    // the start of data
+    data: u64,
+    offset: u64,
+    zip64: bool,
+}
+
+impl Archive {
+    fn zip_header(&mut self, entry: Entry) {
+        let offset = self.position();
+        let (time, date) = dos_time(entry.mtime);
+        let mut zip = ZipEntry {
+            name: entry.name.clone(),
+            method: 0,
+            flags: FLAGS,
+            time,
+            date,
+            external: entry.mode << 16,
+            crc: Crc::new(),
+            size: 0,
+            data: 0,
+            offset,
+            zip64: offset >= u64::from(u32::MAX),
+        };
+        match entry.kind {
+            Kind::Dir => {
+                zip.external |= 0o040_000 << 16 | 0x10;
+                zip.local(&mut self.buf);
+                zip.data = self.position();
+                self.zip_end(zip);
+            }
+            Kind::Link(ref link) => {
+                zip.external |= 0o120_000 << 16;
+                zip.crc.update(link.as_bytes());
+                zip.size = link.len() as u64;
+                zip.local(&mut self.buf);
+                zip.data = self.position();
+                self.buf.extend_from_slice(link.as_bytes());
+                self.zip_end(zip);
+            }
+            Kind::File(size) => {
+                let file = match Self::open(&entry) {
+                    Some(file) => file,
+                    None => return,
+                };
+                zip.external |= 0o100_000 << 16;
+                zip.method = 8;
+                zip.flags = FLAGS_DESCRIPTOR;
+                zip.zip64 = zip.zip64 || size >= ZIP64_SIZE;
+                zip.local(&mut self.buf);
+                zip.data = self.position();
+                self.reading = Some(Reading {
+                    file,
+                    path: entry.path,
+                    size,
+                    left: size,
+                    zip: Some(zip),
+                });
+            }
+        }
+    }
+    // the data descriptor and central directory header
+    fn zip_end(&mut self, zip: ZipEntry) {
+        let csize = self.position() - zip.data;
+        if zip.flags == FLAGS_DESCRIPTOR {
+            le32(&mut self.buf, 0x0807_4b50);
+            le32(&mut self.buf, zip.crc.sum());
+            if zip.zip64 {
+                le64(&mut self.buf, csize);
+                le64(&mut self.buf, zip.size);
+            } else {
+                le32(&mut self.buf, csize as u32);
+                le32(&mut self.buf, zip.size as u32);
+            }
+        }
+        let out = &mut self.central;
+        le32(out, 0x0201_4b50);
+        le16(out, VERSION_MADE_BY);
+        le16(out, zip.version());
+        le16(out, zip.flags);
+        le16(out, zip.method);
+        le16(out, zip.time);
+        le16(out, zip.date);
+        le32(out, zip.crc.sum());
+        if zip.zip64 {
+            le32(out, u32::MAX);
+            le32(out, u32::MAX);
+        } else {
+            le32(out, csize as u32);
+            le32(out, zip.size as u32);
+        }
+        le16(out, zip.name.len() as u16);
+        le16(out, if zip.zip64 { 28 } else { 0 });
+        le16(out, 0);
+        le16(out, 0);
+        le16(out, 0);
+        le32(out, zip.external);
+        le32(out, if zip.zip64 { u32::MAX } else { zip.offset as u32 });
+        out.extend_from_slice(zip.name.as_bytes());
+        if zip.zip64 {
+            le16(out, 1);
+            le16(out, 24);
+            le64(out, zip.size);
+            le64(out, csize);
+            le64(out, zip.offset);
+        }
+        self.count += 1;
+    }
+}
+
+impl ZipEntry {
+    fn version(&self) -> u16 {
+        if self.zip64 {
+            45
+        } else {
+            20
+        }
+    }
+    fn local(&self, out: &mut Vec<u8>) {
+        le32(out, 0x0403_4b50);
+        le16(out, self.version());
+        le16(out, self.flags);
+        le16(out, self.method);
+        le16(out, self.time);
+        le16(out, self.date);
+        le32(out, self.crc.sum());
+        if self.zip64 {
+            le32(out, u32::MAX);
+            le32(out, u32::MAX);
+        } else {
+            le32(out, self.size as u32);
+            le32(out, self.size as u32);
+        }
+        le16(out, self.name.len() as u16);
+        le16(out, if self.zip64 { 20 } else { 0 });
+        out.extend_from_slice(self.name.as_bytes());
+        if self.zip64 {
+            le16(out, 1);
+            le16(out, 16);
+            le64(out, self.size);
+            le64(out, self.size);
+        }
+    }
+}
+
+// the MS-DOS time and date(UTC) of zip
+fn dos_time(time: SystemTime) -> (u16, u16) {
+    let (year, month, day, secs) = civil(time);
+    if year < 1980 {
+        return (0, 1 << 5 | 1);
+    }
+    let year = cmp::min(year - 1980, 127) as u16;
+    let time = (secs / 3600) << 11 | (secs % 3600 / 60) << 5 | (secs % 60 / 2);
+    (time as u16, year << 9 | (month as u16) << 5 | day as u16)
+}
+
+// the raw deflate of `data` into `out`, all of it is consumed
+fn deflate(compress: &mut Compress, mut data: &[u8], flush: FlushCompress, out: &mut Vec<u8>) {
+    loop {
+        out.reserve(data.len() / 2 + 1024);
+        let before = compress.total_in();
+        // the output is limited by the capacity of `out` only
+        let status = compress.compress_vec(data, out, flush).expect("deflate");
+        data = &data[(compress.total_in() - before) as usize..];
+        let done = match flush {
+            FlushCompress::Finish => status == Status::StreamEnd,
+            _ => data.is_empty(),
+        };
+        if done {
+            return;
+        }
+    }
+}
+
+fn le16(out: &mut Vec<u8>, v: u16) {
+    out.extend_from_slice(&[v as u8, (v >> 8) as u8]);
+}
+
+fn le32(out: &mut Vec<u8>, v: u32) {
+    le16(out, v as u16);
+    le16(out, (v >> 16) as u16);
+}
+
+fn le64(out: &mut Vec<u8>, v: u64) {
+    le32(out, v as u32);
+    le32(out, (v >> 32) as u32);
+}
+
+#[test]
+fn archive_test() {
+    let archive = Archive::new(Format::Tar, vec![(PathBuf::from("tests/index"), "index".to_owned())], &Config::new());
+    let names = archive.walker.map(|e| e.name).collect::<Vec<_>>();
+    assert_eq!(names, ["index/", "index/file"]);
+    let config = Config::new().hide_entry(true);
+    assert_eq!(select(Path::new("tests"), vec!["index".to_owned()], &config).unwrap().len(), 1);
+    for name in &["..", "index/file", ".index", "missing"] {
+        assert!(select(Path::new("tests"), vec![name.to_string()], &config).is_err());
+    }
+    let mut pax = vec![];
+    pax_record(&mut pax, "path", &[b'a'; 92]);
+    assert_eq!((pax.len(), &pax[..9]), (102, &b"102 path="[..]));
+
+    // decoded by flate2
+    use flate2::read::{DeflateDecoder, GzDecoder};
+    let content = fs::read("Cargo.toml").unwrap();
+    let all = |format| {
+        let mut archive = Archive::new(format, vec![(PathBuf::from("Cargo.toml"), "Cargo.toml".to_owned())], &config);
+        let mut out = vec![];
+        while let Some(chunk) = archive.next_chunk().unwrap() {
+            out.extend(chunk);
+        }
+        out
+    };
+    let mut tar = vec![];
+    GzDecoder::new(&all(Format::TarGz)[..]).read_to_end(&mut tar).unwrap();
+    assert_eq!(&tar[..11], b"Cargo.toml\0");
+    assert_eq!(&tar[512..512 + content.len()], &content[..]);
+    let zip = all(Format::Zip);
+    let start = 30 + zip[26] as usize + zip[28] as usize;
+    assert_eq!(&zip[30..start], b"Cargo.toml");
+    let mut decoder = DeflateDecoder::new(&zip[start..]);
+    let mut file = vec![];
+    decoder.read_to_end(&mut file).unwrap();
+    assert_eq!(file, content);
+    let mut crc = Crc::new();
+    crc.update(&content);
+    let descriptor = start + decoder.total_in() as usize;
+    assert_eq!(&zip[descriptor..descriptor + 8], &[&[0x50, 0x4b, 7, 8][..], &crc.sum().to_le_bytes()].concat()[..]);
+
+    // the file shrinks after the first chunk, it is not compressible
+    let path = ::std::env::temp_dir().join(format!("hyper-fs-archive-{}", ::std::process::id()));
+    let mut seed = 1u32;
+    let noise = (0..CHUNK_SIZE * 3)
+        .map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as u8
+        })
+        .collect::<Vec<_>>();
+    for format in &[Format::Tar, Format::Zip] {
+        fs::write(&path, &noise).unwrap();
+        let mut archive = Archive::new(*format, vec![(path.clone(), "shrinking".to_owned())], &config);
+        assert!(archive.next_chunk().unwrap().is_some());
+        File::create(&path).unwrap().set_len(CHUNK_SIZE as u64).unwrap();
+        let e = archive.next_chunk().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+    fs::remove_file(&path).ok();
+}
@@ -0,0 +1,209 @@
+use bytes::Bytes;
+use hyper::Headers;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::fs::Metadata;
+use std::fmt;
+
+struct Entry {
+    modified: SystemTime,
+    size: u64,
+    content: Bytes,
+    // made by the `headers_maker` of `StaticFile` for the content
+    headers: Option<Headers>,
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<PathBuf, Entry>,
+    // tick -> path, the least recently used is the first
+    order: BTreeMap<u64, PathBuf>,
+    tick: u64,
+    used: usize,
+}
+
+impl Lru {
+    fn touch(&mut self, path: &Path) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.entries.get_mut(path) {
+            self.order.remove(&entry.tick);
+            self.order.insert(tick, path.to_path_buf());
+            entry.tick = tick;
+        }
+    }
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.order.remove(&entry.tick);
+            self.used -= entry.content.len();
+        }
+    }
+    fn evict(&mut self) {
+        let tick = match self.order.keys().next() {
+            Some(tick) => *tick,
+            None => return,
+        };
+        let path = self.order.remove(&tick).unwrap();
+        let entry = self.entries.remove(&path).unwrap();
+        self.used -= entry.content.len();
+    }
+}
+
+/// In-memory LRU content cache for the hot small files
+///
+/// The content is keyed by path, mtime and size, and is shared as `Bytes`, set it by `Config::content_cache`.
+/// The `Headers` made by `StaticFile::headers_maker` are cached with it, so a hit does not open the file.
+pub struct ContentCache {
+    capacity: usize,
+    max_file_size: u64,
+    lru: Mutex<Lru>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl ContentCache {
+    /// `capacity` is the budget of bytes, the files larger than `max_file_size` are not cached.
+    pub fn new(capacity: usize, max_file_size: u64) -> Self {
+        Self {
+            capacity,
+            max_file_size,
+            lru: Mutex::new(Lru::default()),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    pub fn max_file_size(&self) -> u64 {
+        self.max_file_size
+    }
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+    /// Bytes being used.
+    pub fn used(&self) -> usize {
+        self.lru.lock().unwrap().used
+    }
+    /// How many files being cached.
+    pub fn len(&self) -> usize {
+        self.lru.lock().unwrap().entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn clear(&self) {
+        let mut lru = self.lru.lock().unwrap();
+        *lru = Lru::default();
+    }
+    pub(crate) fn is_cacheable(&self, size: u64) -> bool {
+        size <= self.max_file_size && size <= self.capacity as u64
+    }
+    /// Whether the content of the file is cached and fresh(with the made `Headers` if `headers`),
+    /// it does not count hits or misses.
+    pub(crate) fn is_fresh(&self, path: &Path, metadata: &Metadata, headers: bool) -> bool {
+        let modified = match metadata.modified() {
+            Ok(m) => m,
+            Err(_) => return false,
+        };
+        let lru = self.lru.lock().unwrap();
+        lru.entries
+            .get(path)
+            .map(|e| e.modified == modified && e.size == metadata.len() && (!headers || e.headers.is_some()))
+            .unwrap_or(false)
+    }
+    pub(crate) fn get(&self, path: &Path, modified: SystemTime, size: u64) -> Option<(Bytes, Option<Headers>)> {
+        let mut lru = self.lru.lock().unwrap();
+        let content = match lru.entries.get(path) {
+            Some(e) if e.modified == modified && e.size == size => Some((e.content.clone(), e.headers.clone())),
+            Some(_) => {
+                lru.remove(path);
+                None
+            }
+            None => None,
+        };
+        match content {
+            Some(content) => {
+                lru.touch(path);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(content)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+    /// Cache the `content` of the file modified at `modified`, the old one is replaced.
+    pub(crate) fn insert(&self, path: &Path, modified: SystemTime, content: Bytes, headers: Option<Headers>) {
+        if !self.is_cacheable(content.len() as u64) {
+            return;
+        }
+        let mut lru = self.lru.lock().unwrap();
+        lru.remove(path);
+        while lru.used + content.len() > self.capacity {
+            lru.evict();
+        }
+        lru.used += content.len();
+        lru.entries.insert(
+            path.to_path_buf(),
+            Entry {
+                modified,
+                size: content.len() as u64,
+                content,
+                headers,
+                tick: 0,
+            },
+        );
+        lru.touch(path);
+    }
+}
+
+impl fmt::Debug for ContentCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ContentCache")
+            .field("capacity", &self.capacity)
+            .field("max_file_size", &self.max_file_size)
+            .field("used", &self.used())
+            .field("hits", &self.hits())
+            .field("misses", &self.misses())
+            .finish()
+    }
+}
+
+#[test]
+fn content_cache_test() {
+    let cache = ContentCache::new(8, 4);
+    let md = ::std::fs::metadata("tests/index/file").unwrap();
+    let modified = md.modified().unwrap();
+    assert!(cache.get(Path::new("a"), modified, 0).is_none());
+
+    cache.insert(Path::new("a"), modified, Bytes::from("1234"), None);
+    cache.insert(Path::new("b"), modified, Bytes::from("5678"), None);
+    // `b` is the least recently used after `a` is touched, it is evicted for `c`
+    assert_eq!(cache.get(Path::new("a"), modified, 4).unwrap().0, Bytes::from("1234"));
+    cache.insert(Path::new("c"), modified, Bytes::from("9"), None);
+    assert!(cache.get(Path::new("b"), modified, 4).is_none());
+    assert_eq!((cache.len(), cache.used()), (2, 5));
+    // too large
+    cache.insert(Path::new("d"), modified, Bytes::from("12345"), None);
+    assert_eq!(cache.len(), 2);
+    // stale
+    assert!(cache.get(Path::new("a"), modified, 5).is_none());
+    assert_eq!((cache.hits(), cache.misses(), cache.used()), (1, 3, 1));
+
+    let path = Path::new("tests/index/file");
+    cache.insert(path, modified, Bytes::new(), None);
+    assert!(cache.is_fresh(path, &md, false));
+    assert!(!cache.is_fresh(path, &md, true));
+    cache.insert(path, modified, Bytes::new(), Some(Headers::new()));
+    assert!(cache.is_fresh(path, &md, true));
+}
@@ -1,3 +1,14 @@
+use admission::Admission;
+use cache::ContentCache;
+use disposition::Disposition;
+use quota::Quota;
+use webdav::DavLocks;
+use stat_cache::MetadataCache;
+use trash::Trash;
+use uring::Uring;
+
+use std::sync::Arc;
+
 ///public config for file/index/fs
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -5,8 +16,42 @@ pub struct Config {
     pub(crate) show_index: bool,   // if false/true, use StaticIndexEmpty/StaticIndex, default is false.
     pub(crate) hide_entry: bool,   // if hide, hide .xxx in index(html), dafault is false.
     pub(crate) cache_secs: u32,    // 0
+    // serve `?archive=` of the directories, default is false.
+    pub(crate) archive: bool,
+    // the `Content-Disposition` of the files by the patterns of their names, default is empty.
+    pub(crate) dispositions: Vec<(String, Disposition)>,
     // how many bytes read(poll) for every time(affect mem occupy and speed), default is 16384(16k, N times of Fsblock size(4k?8k))
     pub(crate) chunk_size: usize,
+    // start from a filesystem block and grow to chunk_size, default is false.
+    pub(crate) adaptive_chunk_size: bool,
+    // how many chunks are read ahead(in flight) for a file, the memory is bounded by read_ahead * chunk_size, default is 1.
+    pub(crate) read_ahead: usize,
+    // mmap the files not larger than it and copy the chunks out of the mapping(Linux), default is 0(disabled).
+    pub(crate) mmap: u64,
+    // how many ranges(after merged) a request may have, or the whole file is responded, default is 16.
+    pub(crate) max_ranges: usize,
+    // read the chunks by the shared io_uring instead of the BlockingExecutor(the `io-uring` feature), default is None.
+    pub(crate) io_uring: Option<Arc<Uring>>,
+    // serve the hot small files from memory, default is None.
+    pub(crate) content_cache: Option<Arc<ContentCache>>,
+    // cache the metadata of the resolved paths, default is None.
+    pub(crate) metadata_cache: Option<Arc<MetadataCache>>,
+    // accept PUT to write the files, default is false.
+    pub(crate) write: bool,
+    // DELETE and MOVE remove the non-empty directories, default is false.
+    pub(crate) recursive_delete: bool,
+    // the max bytes of a upload, default is 1G.
+    pub(crate) max_upload_size: u64,
+    // the partial files of the resumable uploads untouched for it are removed, default is 86400(1 day).
+    pub(crate) upload_expiry_secs: u32,
+    // the quotas and free space guard of the uploads, default is None.
+    pub(crate) quota: Option<Arc<Quota>>,
+    // DELETE moves into the trash, default is None.
+    pub(crate) trash: Option<Arc<Trash>>,
+    // serve WebDAV with the locks, default is None.
+    pub(crate) webdav: Option<Arc<DavLocks>>,
+    // limit the concurrent blocking work of listings and files, default is None.
+    pub(crate) admission: Option<Arc<Admission>>,
 }
 
 impl Config {
@@ -16,7 +61,24 @@ impl Config {
             show_index: false,
             hide_entry: false,
             cache_secs: 0,
+            archive: false,
+            dispositions: Vec::new(),
             chunk_size: 16_384,
+            adaptive_chunk_size: false,
+            read_ahead: 1,
+            mmap: 0,
+            max_ranges: 16,
+            io_uring: None,
+            content_cache: None,
+            metadata_cache: None,
+            write: false,
+            recursive_delete: false,
+            max_upload_size: 1 << 30,
+            upload_expiry_secs: 86_400,
+            quota: None,
+            trash: None,
+            webdav: None,
+            admission: None,
         }
     }
 }
@@ -37,10 +99,123 @@ impl Config {
         self.cache_secs = cache_secs;
         self
     }
+    /// `GET ?archive=tar|tar.gz|zip` of a listed directory downloads its tree, it is generated while being sent,
+    /// the hidden entries are skipped if `hide_entry` and the symlinks are followed if `follow_links`.
+    ///
+    /// `POST ?archive=zip` with the entries of the directory(`name=a&name=b`) downloads the selected ones,
+    /// the listing has the checkboxes for it.
+    pub fn archive(mut self, archive: bool) -> Self {
+        self.archive = archive;
+        self
+    }
+    /// The files whose names match the `pattern`(`*` and `?`, ignoring the ASCII case) are responded with the `disposition`,
+    /// the first matched rule is used, and `?download` makes it `Attachment`.
+    pub fn disposition<S: Into<String>>(mut self, pattern: S, disposition: Disposition) -> Self {
+        self.dispositions.push((pattern.into(), disposition));
+        self
+    }
     pub fn chunk_size(mut self, chunk_size: usize) -> Self {
         self.chunk_size = chunk_size;
         self
     }
+    /// The chunks start from a filesystem block(fast first byte) and double to `chunk_size` for the large files,
+    /// they are aligned to the block size reported by the metadata.
+    pub fn adaptive_chunk_size(mut self, adaptive_chunk_size: bool) -> Self {
+        self.adaptive_chunk_size = adaptive_chunk_size;
+        self
+    }
+    /// How many chunks are being read ahead for a file(at least 1), it overlaps the disk latency and network sending.
+    pub fn read_ahead(mut self, read_ahead: usize) -> Self {
+        self.read_ahead = read_ahead;
+        self
+    }
+    /// Serve the files not larger than `mmap` bytes from the memory mapping instead of `read`(Linux).
+    ///
+    /// The inode and size of a file are checked before every chunk is copied out of its mapping,
+    /// the chunk is read instead if they changed, and the body is aborted as `read` does.
+    pub fn mmap(mut self, mmap: u64) -> Self {
+        self.mmap = mmap;
+        self
+    }
+    /// The `Range` of a request is ignored(`200`) if it has more than `max_ranges` ranges after the overlapping and adjacent ones merged,
+    /// otherwise they are responded(`206`, `multipart/byteranges` if more than one).
+    pub fn max_ranges(mut self, max_ranges: usize) -> Self {
+        self.max_ranges = max_ranges;
+        self
+    }
+    /// Read the chunks by a io_uring with `entries` submission entries shared by the clones of the `Config`,
+    /// the `BlockingExecutor` is used as usual if io_uring is unavailable(Linux 5.6 is required).
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub fn io_uring(mut self, entries: u32) -> Self {
+        self.io_uring = Uring::new(entries)
+            .map_err(|e| warn!("io_uring is unavailable, fall back to the BlockingExecutor: {}", e))
+            .ok()
+            .map(Arc::new);
+        self
+    }
+    /// Serve the small files from the shared `ContentCache`, `ETag`, `304` and `Range` work as usual.
+    ///
+    /// A hit is served on the current thread only if its metadata is in the `Config::metadata_cache` too,
+    /// without the metadata cache every hit still `stat`s the file on the `BlockingExecutor`(the file is not read).
+    pub fn content_cache(mut self, content_cache: Arc<ContentCache>) -> Self {
+        self.content_cache = Some(content_cache);
+        self
+    }
+    /// Cache the `metadata()` of the files and directories by the shared `MetadataCache`,
+    /// the request does not `stat` on the reactor thread if it is cached.
+    pub fn metadata_cache(mut self, metadata_cache: Arc<MetadataCache>) -> Self {
+        self.metadata_cache = Some(metadata_cache);
+        self
+    }
+    /// Accept the writes in `StaticFs`, `If-Match`/`If-None-Match: *` are honoured:
+    ///
+    /// - `PUT` writes the body to a temp file beside the target and renames it into place.
+    /// - `POST` saves the files of a `multipart/form-data` into the directory.
+    /// - `DELETE` removes the file or empty directory, `MKCOL` creates the directory.
+    /// - `MOVE` renames to the `Destination` under the same root, `Overwrite: F` keeps the existing one.
+    /// - `PUT` with `Content-Range` appends to a resumable upload, `HEAD` reports its `Upload-Offset` until it completes.
+    ///   It completes with the chunk reaching the total, or a empty `PUT` with `Content-Range: bytes */total` if the total was `*`.
+    pub fn write(mut self, write: bool) -> Self {
+        self.write = write;
+        self
+    }
+    /// `DELETE` removes the non-empty directories recursively, so does `MOVE` for the replaced destination.
+    pub fn recursive_delete(mut self, recursive_delete: bool) -> Self {
+        self.recursive_delete = recursive_delete;
+        self
+    }
+    /// The uploads larger than it are rejected(`413`).
+    pub fn max_upload_size(mut self, max_upload_size: u64) -> Self {
+        self.max_upload_size = max_upload_size;
+        self
+    }
+    /// The partial files of the resumable uploads are removed if they are not appended for it.
+    pub fn upload_expiry_secs(mut self, upload_expiry_secs: u32) -> Self {
+        self.upload_expiry_secs = upload_expiry_secs;
+        self
+    }
+    /// Limit the bytes of the uploads under the root and by a user, and keep the free space of the filesystem.
+    pub fn quota(mut self, quota: Arc<Quota>) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+    /// `DELETE` moves the files and directories into the `Trash` instead of removing them, they can be listed and restored.
+    pub fn trash(mut self, trash: Arc<Trash>) -> Self {
+        self.trash = Some(trash);
+        self
+    }
+    /// Serve WebDAV class 1 and 2 in `StaticFs`(`OPTIONS`, `PROPFIND`, `PROPPATCH`, `COPY`, `LOCK` and `UNLOCK`),
+    /// the writes need `write` too, and the clients expect `recursive_delete`.
+    pub fn webdav(mut self, webdav: bool) -> Self {
+        self.webdav = if webdav { Some(Arc::new(DavLocks::new())) } else { None };
+        self
+    }
+    /// Run the listings and file reads of `StaticFs` under the shared `Admission`,
+    /// a burst of large downloads does not starve the listings, and the requests are rejected if the queue is too deep.
+    pub fn admission(mut self, admission: Arc<Admission>) -> Self {
+        self.admission = Some(admission);
+        self
+    }
 }
 impl Config {
     pub fn get_follow_links(&self) -> bool {
@@ -55,9 +230,61 @@ impl Config {
     pub fn get_cache_secs(&self) -> &u32 {
         &self.cache_secs
     }
+    pub fn get_archive(&self) -> bool {
+        self.archive
+    }
+    pub fn get_dispositions(&self) -> &[(String, Disposition)] {
+        &self.dispositions
+    }
     pub fn get_chunk_size(&self) -> &usize {
         &self.chunk_size
     }
+    pub fn get_adaptive_chunk_size(&self) -> bool {
+        self.adaptive_chunk_size
+    }
+    pub fn get_read_ahead(&self) -> &usize {
+        &self.read_ahead
+    }
+    pub fn get_mmap(&self) -> u64 {
+        self.mmap
+    }
+    pub fn get_max_ranges(&self) -> usize {
+        self.max_ranges
+    }
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub fn get_io_uring(&self) -> Option<&Arc<Uring>> {
+        self.io_uring.as_ref()
+    }
+    pub fn get_content_cache(&self) -> Option<&Arc<ContentCache>> {
+        self.content_cache.as_ref()
+    }
+    pub fn get_metadata_cache(&self) -> Option<&Arc<MetadataCache>> {
+        self.metadata_cache.as_ref()
+    }
+    pub fn get_write(&self) -> bool {
+        self.write
+    }
+    pub fn get_recursive_delete(&self) -> bool {
+        self.recursive_delete
+    }
+    pub fn get_max_upload_size(&self) -> u64 {
+        self.max_upload_size
+    }
+    pub fn get_upload_expiry_secs(&self) -> u32 {
+        self.upload_expiry_secs
+    }
+    pub fn get_quota(&self) -> Option<&Arc<Quota>> {
+        self.quota.as_ref()
+    }
+    pub fn get_trash(&self) -> Option<&Arc<Trash>> {
+        self.trash.as_ref()
+    }
+    pub fn get_webdav(&self) -> Option<&Arc<DavLocks>> {
+        self.webdav.as_ref()
+    }
+    pub fn get_admission(&self) -> Option<&Arc<Admission>> {
+        self.admission.as_ref()
+    }
 }
 
 impl Config {
@@ -73,9 +300,61 @@ impl Config {
     pub fn set_cache_secs(&mut self, cache_secs: u32) {
         self.cache_secs = cache_secs;
     }
+    pub fn set_archive(&mut self, archive: bool) {
+        self.archive = archive;
+    }
+    pub fn set_dispositions(&mut self, dispositions: Vec<(String, Disposition)>) {
+        self.dispositions = dispositions;
+    }
     pub fn set_chunk_size(&mut self, chunk_size: usize) {
         self.chunk_size = chunk_size
     }
+    pub fn set_adaptive_chunk_size(&mut self, adaptive_chunk_size: bool) {
+        self.adaptive_chunk_size = adaptive_chunk_size;
+    }
+    pub fn set_read_ahead(&mut self, read_ahead: usize) {
+        self.read_ahead = read_ahead;
+    }
+    pub fn set_mmap(&mut self, mmap: u64) {
+        self.mmap = mmap;
+    }
+    pub fn set_max_ranges(&mut self, max_ranges: usize) {
+        self.max_ranges = max_ranges;
+    }
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub fn set_io_uring(&mut self, io_uring: Option<Arc<Uring>>) {
+        self.io_uring = io_uring;
+    }
+    pub fn set_content_cache(&mut self, content_cache: Option<Arc<ContentCache>>) {
+        self.content_cache = content_cache;
+    }
+    pub fn set_metadata_cache(&mut self, metadata_cache: Option<Arc<MetadataCache>>) {
+        self.metadata_cache = metadata_cache;
+    }
+    pub fn set_write(&mut self, write: bool) {
+        self.write = write;
+    }
+    pub fn set_recursive_delete(&mut self, recursive_delete: bool) {
+        self.recursive_delete = recursive_delete;
+    }
+    pub fn set_max_upload_size(&mut self, max_upload_size: u64) {
+        self.max_upload_size = max_upload_size;
+    }
+    pub fn set_upload_expiry_secs(&mut self, upload_expiry_secs: u32) {
+        self.upload_expiry_secs = upload_expiry_secs;
+    }
+    pub fn set_quota(&mut self, quota: Option<Arc<Quota>>) {
+        self.quota = quota;
+    }
+    pub fn set_trash(&mut self, trash: Option<Arc<Trash>>) {
+        self.trash = trash;
+    }
+    pub fn set_webdav(&mut self, webdav: Option<Arc<DavLocks>>) {
+        self.webdav = webdav;
+    }
+    pub fn set_admission(&mut self, admission: Option<Arc<Admission>>) {
+        self.admission = admission;
+    }
 }
 
 impl Default for Config {
@@ -0,0 +1,41 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The `time` as `1970-01-01T00:00:00Z`.
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    let (year, month, day, rem) = civil(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// The UTC year, month, day and the seconds of the day of `time`.
+pub(crate) fn civil(time: SystemTime) -> (i64, i64, i64, u64) {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+    // the civil date of the days since 1970-01-01
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day, rem)
+}
+
+#[test]
+fn date_test() {
+    use std::time::Duration;
+
+    assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+    assert_eq!(rfc3339(UNIX_EPOCH + Duration::from_secs(951_868_799)), "2000-02-29T23:59:59Z");
+    assert_eq!(civil(UNIX_EPOCH + Duration::from_secs(86_400 * 365 + 61)), (1971, 1, 1, 61));
+}
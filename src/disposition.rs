@@ -0,0 +1,93 @@
+use url::form_urlencoded;
+
+use super::Config;
+
+use std::path::Path;
+
+/// The `Content-Disposition` of the files, set it by `Config::disposition`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Disposition {
+    /// Rendered by the browser.
+    Inline,
+    /// Saved as a file.
+    Attachment,
+}
+
+impl Disposition {
+    /// The disposition of the file `path`: `Attachment` if the query has `download`,
+    /// or the first rule of `Config::disposition` matching its name.
+    pub(crate) fn of(query: Option<&str>, path: &Path, config: &Config) -> Option<Self> {
+        let download = query
+            .map(|query| form_urlencoded::parse(query.as_bytes()).any(|(k, _)| k == "download"))
+            .unwrap_or(false);
+        if download {
+            return Some(Disposition::Attachment);
+        }
+        let name = path.file_name()?.to_string_lossy();
+        config
+            .get_dispositions()
+            .iter()
+            .find(|&(pattern, _)| matches(pattern.as_bytes(), name.as_bytes()))
+            .map(|&(_, disposition)| disposition)
+    }
+    fn as_str(&self) -> &'static str {
+        match *self {
+            Disposition::Inline => "inline",
+            Disposition::Attachment => "attachment",
+        }
+    }
+}
+
+/// The value of `Content-Disposition` for the file `name`, the name which is not plain ASCII
+/// is encoded in `filename*`(RFC 5987), and `filename` keeps a ASCII one for the old clients.
+pub(crate) fn header(disposition: Disposition, name: &str) -> String {
+    let ascii = name.chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' && c != '%' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    let mut value = format!("{}; filename=\"{}\"", disposition.as_str(), ascii);
+    if ascii != name {
+        value.push_str("; filename*=UTF-8''");
+        for b in name.bytes() {
+            match b {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => value.push(b as char),
+                b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => value.push(b as char),
+                _ => value.push_str(&format!("%{:02X}", b)),
+            }
+        }
+    }
+    value
+}
+
+// `*` matches any bytes and `?` matches one, ignoring the ASCII case
+fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(&b'*'), _) => matches(&pattern[1..], name) || (!name.is_empty() && matches(pattern, &name[1..])),
+        (Some(&b'?'), Some(_)) => matches(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p.eq_ignore_ascii_case(n) => matches(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+#[test]
+fn disposition_test() {
+    assert_eq!(header(Disposition::Inline, "a b.pdf"), "inline; filename=\"a b.pdf\"");
+    assert_eq!(
+        header(Disposition::Attachment, "résumé \"1\".txt"),
+        "attachment; filename=\"r_sum_ _1_.txt\"; filename*=UTF-8''r%C3%A9sum%C3%A9%20%221%22.txt"
+    );
+
+    let config = Config::new()
+        .disposition("*.PDF", Disposition::Inline)
+        .disposition("*", Disposition::Attachment);
+    let of = |query, path| Disposition::of(query, Path::new(path), &config);
+    assert_eq!(of(None, "d/a.pdf"), Some(Disposition::Inline));
+    assert_eq!(of(Some("download"), "d/a.pdf"), Some(Disposition::Attachment));
+    assert_eq!(of(None, "d/a.pdf.exe"), Some(Disposition::Attachment));
+    assert!(matches(b"log-????.txt", b"log-2018.txt"));
+    assert!(!matches(b"log-????.txt", b"log-18.txt"));
+    assert_eq!(Disposition::of(None, Path::new("a"), &Config::new()), None);
+}
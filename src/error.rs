@@ -1,7 +1,8 @@
 use hyper::server::{Request, Response};
-use hyper::{Error as HyperError, StatusCode};
+use hyper::{header, Error as HyperError, StatusCode};
 
 use std::io::{self, ErrorKind as IoErrorKind};
+use std::time::Duration;
 
 /// `Error` wrapped.
 #[derive(Debug)]
@@ -14,6 +15,22 @@ pub enum Error {
     Typo,
     /// `StaticFs`'s base url is not a prefix of `Request`'s path.
     Route,
+    /// The queue of `Admission` is full, retry after the seconds.
+    Busy(u32),
+    /// `If-Match`/`If-None-Match` of the write failed.
+    Precondition,
+    /// The uploaded body is larger than `Config::max_upload_size`.
+    TooLarge,
+    /// The target of the write conflicts with the tree(the parent does not exist, it is a directory...).
+    Conflict,
+    /// The request of the write is malformed: invalid `multipart/form-data`, `Destination`...
+    Malformed,
+    /// The `Content-Type` of the `POST` is not `multipart/form-data`.
+    MediaType,
+    /// The resource is locked by WebDAV, the lock token is not submitted.
+    Locked,
+    /// The upload is over the `Quota` or the free space.
+    Storage,
 }
 
 impl Error {
@@ -21,11 +38,15 @@ impl Error {
     pub fn not_found() -> Self {
         Error::Io(io::Error::from(IoErrorKind::NotFound))
     }
+    /// fast creat `Error::Io(io::Error::from(IoErrorKind::PermissionDenied))`
+    pub fn forbidden() -> Self {
+        Error::Io(io::Error::from(IoErrorKind::PermissionDenied))
+    }
 }
 
-impl Into<Error> for io::Error {
-    fn into(self) -> Error {
-        Error::Io(self)
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
     }
 }
 
@@ -33,8 +54,13 @@ impl Into<Error> for io::Error {
 pub fn error_handler(err_req: (Error, Request)) -> Result<(Response, Request), HyperError> {
     use Error::*;
     let (err, req) = err_req;
+    let mut res = Response::new();
+    if let Busy(secs) = err {
+        res.headers_mut()
+            .set(header::RetryAfter::Delay(Duration::from_secs(u64::from(secs))));
+    }
     Ok((
-        Response::new().with_status(match err {
+        res.with_status(match err {
             Io(i) => match i.kind() {
                 IoErrorKind::NotFound => StatusCode::NotFound,
                 IoErrorKind::PermissionDenied => StatusCode::Forbidden,
@@ -42,6 +68,14 @@ pub fn error_handler(err_req: (Error, Request)) -> Result<(Response, Request), H
             },
             Method => StatusCode::MethodNotAllowed,
             Typo | Route => StatusCode::InternalServerError,
+            Busy(_) => StatusCode::ServiceUnavailable,
+            Precondition => StatusCode::PreconditionFailed,
+            TooLarge => StatusCode::PayloadTooLarge,
+            Conflict => StatusCode::Conflict,
+            Malformed => StatusCode::BadRequest,
+            MediaType => StatusCode::UnsupportedMediaType,
+            Locked => StatusCode::Locked,
+            Storage => StatusCode::InsufficientStorage,
         }),
         req,
     ))
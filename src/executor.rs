@@ -0,0 +1,196 @@
+use futures::{Async, Future, Poll};
+use futures::sync::oneshot::{self, Receiver, Sender};
+use futures_cpupool::CpuPool;
+use tokio_core::reactor::{Handle, Remote};
+use hyper::server::Request;
+use hyper::Error as HyperError;
+
+use super::Error;
+
+use std::io;
+
+/// Executor of the blocking filesystem work(`stat`, `read`, `read_dir`...)
+///
+/// `CpuPool` is the default, you can implement it for your own thread pool.
+pub trait BlockingExecutor: Send + Sync + 'static {
+    fn execute(&self, job: Box<dyn FnOnce() + Send + 'static>);
+}
+
+/// Spawner of the future which pumps the body of `Response`
+///
+/// `Handle` is the default, use `Remote` or `CpuPool` in multi-threaded runtimes.
+pub trait Spawner: 'static {
+    fn spawn(&self, future: Box<dyn Future<Item = (), Error = ()> + Send + 'static>);
+}
+
+impl BlockingExecutor for CpuPool {
+    fn execute(&self, job: Box<dyn FnOnce() + Send + 'static>) {
+        self.spawn_fn(move || {
+            job();
+            Ok::<(), ()>(())
+        }).forget()
+    }
+}
+
+impl Spawner for Handle {
+    fn spawn(&self, future: Box<dyn Future<Item = (), Error = ()> + Send + 'static>) {
+        Handle::spawn(self, future)
+    }
+}
+
+impl Spawner for Remote {
+    fn spawn(&self, future: Box<dyn Future<Item = (), Error = ()> + Send + 'static>) {
+        Remote::spawn(self, move |_| future)
+    }
+}
+
+impl Spawner for CpuPool {
+    fn spawn(&self, future: Box<dyn Future<Item = (), Error = ()> + Send + 'static>) {
+        CpuPool::spawn(self, future).forget()
+    }
+}
+
+/// The result of the blocking work running on `BlockingExecutor`
+pub struct Blocking<T, E> {
+    rx: Receiver<Result<T, E>>,
+}
+
+impl<T, E> Blocking<T, E> {
+    /// The work is done already(on the current thread).
+    pub fn done(result: Result<T, E>) -> Self {
+        let (tx, rx) = oneshot::channel();
+        tx.send(result).ok();
+        Blocking { rx }
+    }
+    /// The `Sender` completes it.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub(crate) fn pair() -> (oneshot::Sender<Result<T, E>>, Self) {
+        let (tx, rx) = oneshot::channel();
+        (tx, Blocking { rx })
+    }
+}
+
+impl<T, E: Cancel> Future for Blocking<T, E> {
+    type Item = T;
+    type Error = E;
+    fn poll(&mut self) -> Poll<T, E> {
+        match self.rx.poll() {
+            Ok(Async::Ready(Ok(t))) => Ok(Async::Ready(t)),
+            Ok(Async::Ready(Err(e))) => Err(e),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => {
+                error!("BlockingExecutor dropped the job");
+                Err(E::canceled())
+            }
+        }
+    }
+}
+
+/// The error of the job dropped by `BlockingExecutor` before it completes(shutdown, panic...).
+pub trait Cancel {
+    fn canceled() -> Self;
+}
+
+impl Cancel for io::Error {
+    fn canceled() -> Self {
+        io::Error::other("BlockingExecutor dropped the job")
+    }
+}
+
+// 500
+impl Cancel for Error {
+    fn canceled() -> Self {
+        Error::Io(io::Error::canceled())
+    }
+}
+
+impl Cancel for HyperError {
+    fn canceled() -> Self {
+        HyperError::Io(io::Error::canceled())
+    }
+}
+
+// `spawn_request` gives the `Request` back if the job is dropped before it runs,
+// the job panicked with the `Request` is propagated like `CpuPool`
+impl Cancel for (Error, Request) {
+    fn canceled() -> Self {
+        panic!("the job of the Request panicked")
+    }
+}
+
+impl Cancel for () {
+    fn canceled() -> Self {}
+}
+
+/// Run `f` on the `executor` like `CpuPool::spawn_fn`.
+pub fn spawn_fn<B, F, T, E>(executor: &B, f: F) -> Blocking<T, E>
+where
+    B: BlockingExecutor + ?Sized,
+    F: FnOnce() -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    executor.execute(Box::new(move || {
+        // the receiver may be dropped(canceled)
+        tx.send(f()).ok();
+    }));
+    Blocking { rx }
+}
+
+/// Run `f` with the `req` on the `executor`, the `req` is returned with the error if the job is dropped before it runs.
+pub(crate) fn spawn_request<B, F, T>(executor: &B, req: Request, f: F) -> Blocking<T, (Error, Request)>
+where
+    B: BlockingExecutor + ?Sized,
+    F: FnOnce(Request) -> Result<T, (Error, Request)> + Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    let mut job = RequestJob {
+        tx: Some(tx),
+        req: Some(req),
+    };
+    executor.execute(Box::new(move || {
+        let req = job.req.take().expect("RequestJob runs twice");
+        let result = f(req);
+        if let Some(tx) = job.tx.take() {
+            tx.send(result).ok();
+        }
+    }));
+    Blocking { rx }
+}
+
+struct RequestJob<T> {
+    tx: Option<Sender<Result<T, (Error, Request)>>>,
+    req: Option<Request>,
+}
+
+impl<T> Drop for RequestJob<T> {
+    // dropped by the `BlockingExecutor` without running
+    fn drop(&mut self) {
+        if let (Some(tx), Some(req)) = (self.tx.take(), self.req.take()) {
+            tx.send(Err((Error::canceled(), req))).ok();
+        }
+    }
+}
+
+#[test]
+fn executor_test() {
+    struct Dropping;
+    impl BlockingExecutor for Dropping {
+        fn execute(&self, _job: Box<dyn FnOnce() + Send + 'static>) {}
+    }
+    let pool = CpuPool::new(1);
+    assert_eq!(spawn_fn(&pool, || Ok::<_, ()>(1)).wait(), Ok(1));
+    // canceled, not panicked
+    match spawn_fn(&Dropping, || Ok::<_, Error>(1)).wait() {
+        Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::Other),
+        other => panic!("{:?}", other),
+    }
+    // the request is given back
+    let req = Request::new(::hyper::Method::Get, "/sent".parse().unwrap());
+    match spawn_request(&Dropping, req, Ok::<Request, _>).wait() {
+        Err((Error::Io(_), req)) => assert_eq!(req.path(), "/sent"),
+        other => panic!("{:?}", other.map(|req| req.path().to_owned())),
+    }
+}
@@ -0,0 +1,570 @@
+/*!
+Framework-agnostic core on the [`http`](https://docs.rs/http) crate types.
+
+The resolution, conditional(`304`), range and listing logic is here:
+It consumes the `http::Request` and produces the `http::Response` headers with a [`Body`](enum.Body.html) description,
+the server should send the `Body` after the headers by itself(by the zero-copy [`FileBody::send_to`](../sendfile/index.html) on Linux).
+
+`StaticFs`, `StaticFile` and `StaticIndex` are thin adapters of it for hyper.
+*/
+use http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use http::{Method, Request, Response, StatusCode};
+use httpdate::{fmt_http_date, parse_http_date};
+use url::percent_encoding::percent_decode;
+use bytes::Bytes;
+use hyper;
+
+use super::{Config, Disposition, Error};
+use disposition;
+use static_fs::router;
+use static_index::render_html;
+use stat_cache;
+
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::time::{self, SystemTime};
+
+/// The body should be sent after the headers of `Response`.
+#[derive(Debug, Clone)]
+pub enum Body {
+    /// No body: `HEAD`, `304`, `301`...
+    Empty,
+    /// Bytes in memory, such as the html of index.
+    Bytes(Bytes),
+    /// Content of the file.
+    File(FileBody),
+}
+
+/// The file and the byte ranges of it need to be sent.
+#[derive(Debug, Clone)]
+pub struct FileBody {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+    ranges: Option<Vec<(u64, u64)>>,
+    delimiters: Option<Vec<Bytes>>,
+}
+
+impl FileBody {
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+    /// The size of file when the `Response` is generated.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+    /// The mtime of file when the `Response` is generated.
+    pub fn modified(&self) -> SystemTime {
+        self.modified
+    }
+    /// `None` means the whole file, or the inclusive ranges(`(first, last)`) of it.
+    pub fn ranges(&self) -> Option<&[(u64, u64)]> {
+        self.ranges.as_deref()
+    }
+    /// The `multipart/byteranges` delimiters of the multiple ranges: the one before each range and the closing one after them.
+    pub fn delimiters(&self) -> Option<&[Bytes]> {
+        self.delimiters.as_deref()
+    }
+    /// How many bytes will be sent.
+    pub fn len(&self) -> u64 {
+        let delimiters = self.delimiters
+            .as_ref()
+            .map(|ds| ds.iter().fold(0, |len, d| len + d.len() as u64))
+            .unwrap_or(0);
+        match self.ranges {
+            Some(ref rs) => rs.iter().fold(delimiters, |len, &(a, b)| len + b - a + 1),
+            None => self.size,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Resolve the `Request` for the `root` mounted at `base`, like `StaticFs`.
+pub fn resolve<B>(req: &Request<B>, base: &str, root: &Path, config: &Config) -> Result<Response<Body>, Error> {
+    check_method(req)?;
+    let req_path_dec = percent_decode(req.uri().path().as_bytes())
+        .decode_utf8()
+        .map_err(|_| Error::not_found())?
+        .into_owned();
+    let (req_path, fspath) = router(&req_path_dec, base, &root.to_path_buf())?;
+
+    let metadata = stat_cache::metadata(config, &fspath, config.get_follow_links())?;
+    if metadata.is_file() {
+        let mut res = file(req, &fspath, config)?;
+        set_content_type(&mut res, &fspath);
+        Ok(res)
+    } else if metadata.is_dir() {
+        index(req, &req_path, &fspath, config)
+    } else {
+        Err(Error::Typo)
+    }
+}
+
+fn set_content_type(res: &mut Response<Body>, path: &Path) {
+    if res.status() == StatusCode::OK {
+        if let Some(mime) = guess_type(path) {
+            insert(res.headers_mut(), header::CONTENT_TYPE, mime);
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(feature = "default")] {
+        fn guess_type(path: &Path) -> Option<String> {
+            Some(::mime_guess::from_path(path).first_or_octet_stream().to_string())
+        }
+    } else {
+        fn guess_type(_path: &Path) -> Option<String> {
+            None
+        }
+    }
+}
+
+/// Respond the file like `StaticFile`.
+pub fn file<B>(req: &Request<B>, path: &Path, config: &Config) -> Result<Response<Body>, Error> {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    set_cache_control(&mut headers, config);
+
+    // method error
+    check_method(req)?;
+    // io error
+    let metadata = stat_cache::metadata(config, path, true)?;
+    if !metadata.is_file() {
+        return Err(Error::Typo);
+    }
+
+    //301, redirect
+    // https://rust-lang.org/logo.ico///?labels=E-easy&state=open
+    // http://0.0.0.0:8000///
+    let req_path = req.uri().path();
+    if req_path.len() != 1 && req_path.ends_with('/') {
+        let mut new_path = req_path.to_owned();
+        while new_path.ends_with('/') {
+            new_path.pop();
+        }
+        if new_path.is_empty() {
+            new_path.push('/');
+        }
+        return Ok(redirect(req, new_path, headers));
+    }
+
+    let size = metadata.len();
+    let (last_modified, etag) = last_modified_etag(&metadata)?;
+    insert(&mut headers, header::LAST_MODIFIED, fmt_http_date(last_modified));
+    insert(&mut headers, header::ETAG, &etag);
+    set_content_disposition(&mut headers, req, path, config);
+    let body = FileBody {
+        path: path.to_path_buf(),
+        size,
+        modified: metadata.modified()?,
+        ranges: None,
+        delimiters: None,
+    };
+
+    // Range
+    let range = req.headers()
+        .get(header::RANGE)
+        .and_then(|r| r.to_str().ok())
+        .and_then(parse_range);
+    if let Some(ranges) = range {
+        if let Some(res) = range_response(req, &ranges, &body, &last_modified, &etag, headers.clone(), config) {
+            return Ok(res);
+        }
+    } else if not_modified(req, &etag, config) {
+        return Ok(response(StatusCode::NOT_MODIFIED, headers, Body::Empty));
+    }
+
+    // 200
+    insert(&mut headers, header::CONTENT_LENGTH, size.to_string());
+    let body = if req.method() == Method::GET {
+        Body::File(body)
+    } else {
+        Body::Empty
+    };
+    Ok(response(StatusCode::OK, headers, body))
+}
+
+/// Respond the index like `StaticIndex`, `title` is the decoded path of `Request` usually.
+pub fn index<B>(req: &Request<B>, title: &str, path: &Path, config: &Config) -> Result<Response<Body>, Error> {
+    let mut headers = HeaderMap::new();
+    set_cache_control(&mut headers, config);
+
+    // method error
+    check_method(req)?;
+    // 301
+    let req_path = req.uri().path();
+    if !req_path.ends_with('/') {
+        let mut new_path = req_path.to_owned();
+        new_path.push('/');
+        return Ok(redirect(req, new_path, headers));
+    }
+    if !config.get_show_index() {
+        fs::read_dir(path)?;
+        return Ok(response(StatusCode::OK, headers, Body::Empty));
+    }
+    // HTTP Last-Modified
+    let metadata = stat_cache::metadata(config, path, true)?;
+    let (last_modified, etag) = last_modified_etag(&metadata)?;
+    if not_modified(req, &etag, config) {
+        return Ok(response(StatusCode::NOT_MODIFIED, headers, Body::Empty));
+    }
+
+    // io error
+    let html = render_html(title, &path.to_path_buf(), req_path, config)?;
+
+    // response Header
+    insert(&mut headers, header::CONTENT_LENGTH, html.len().to_string());
+    insert(&mut headers, header::LAST_MODIFIED, fmt_http_date(last_modified));
+    insert(&mut headers, header::ETAG, &etag);
+
+    let body = if req.method() == Method::GET {
+        Body::Bytes(Bytes::from(html))
+    } else {
+        Body::Empty
+    };
+    Ok(response(StatusCode::OK, headers, body))
+}
+
+fn check_method<B>(req: &Request<B>) -> Result<(), Error> {
+    match *req.method() {
+        Method::HEAD | Method::GET => Ok(()),
+        _ => Err(Error::Method),
+    }
+}
+
+fn set_cache_control(headers: &mut HeaderMap, config: &Config) {
+    if *config.get_cache_secs() != 0 {
+        insert(headers, header::CACHE_CONTROL, format!("public, max-age={}", config.get_cache_secs()));
+    }
+}
+
+fn set_content_disposition<B>(headers: &mut HeaderMap, req: &Request<B>, path: &Path, config: &Config) {
+    if let Some(disposition) = Disposition::of(req.uri().query(), path, config) {
+        let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        insert(headers, header::CONTENT_DISPOSITION, disposition::header(disposition, &name));
+    }
+}
+
+// the value is omitted if it is not visible ASCII
+fn insert<S: AsRef<str>>(headers: &mut HeaderMap, name: HeaderName, s: S) {
+    match HeaderValue::from_str(s.as_ref()) {
+        Ok(value) => {
+            headers.insert(name, value);
+        }
+        Err(_) => warn!("invalid value of {}: {:?}", name, s.as_ref()),
+    }
+}
+
+fn response(status: StatusCode, headers: HeaderMap, body: Body) -> Response<Body> {
+    let mut res = Response::new(body);
+    *res.status_mut() = status;
+    *res.headers_mut() = headers;
+    res
+}
+
+fn redirect<B>(req: &Request<B>, mut new_path: String, mut headers: HeaderMap) -> Response<Body> {
+    if let Some(query) = req.uri().query() {
+        new_path.push('?');
+        new_path.push_str(query);
+    }
+    insert(&mut headers, header::LOCATION, new_path);
+    response(StatusCode::MOVED_PERMANENTLY, headers, Body::Empty)
+}
+
+// the seconds truncated `Last-Modified` and weak `ETag`
+pub(crate) fn last_modified_etag(metadata: &Metadata) -> Result<(SystemTime, String), Error> {
+    let last_modified = metadata.modified()?;
+    let delta_modified = last_modified
+        .duration_since(time::UNIX_EPOCH)
+        .expect("SystemTime::duration_since(UNIX_EPOCH) failed");
+    let etag = format!(
+        "W/\"{:x}-{:x}.{:x}\"",
+        metadata.len(),
+        delta_modified.as_secs(),
+        delta_modified.subsec_nanos()
+    );
+    let last_modified = last_modified - time::Duration::new(0, delta_modified.subsec_nanos());
+    Ok((last_modified, etag))
+}
+
+fn not_modified<B>(req: &Request<B>, etag: &str, config: &Config) -> bool {
+    if *config.get_cache_secs() == 0 {
+        return false;
+    }
+    req.headers()
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|e| e.trim() == etag)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ByteRangeSpec {
+    // bytes=0-499
+    FromTo(u64, u64),
+    // bytes=500-
+    AllFrom(u64),
+    // bytes=-500
+    Last(u64),
+}
+
+impl ByteRangeSpec {
+    fn to_satisfiable_range(&self, size: u64) -> Option<(u64, u64)> {
+        if size == 0 {
+            return None;
+        }
+        match *self {
+            ByteRangeSpec::FromTo(from, to) if from < size && from <= to => Some((from, ::std::cmp::min(to, size - 1))),
+            ByteRangeSpec::AllFrom(from) if from < size => Some((from, size - 1)),
+            ByteRangeSpec::Last(last) if last > 0 => Some((size.saturating_sub(last), size - 1)),
+            _ => None,
+        }
+    }
+}
+
+// `None` if not a valid `bytes` Range, the invalid specs are ignored as hyper does.
+fn parse_range(s: &str) -> Option<Vec<ByteRangeSpec>> {
+    let mut iter = s.splitn(2, '=');
+    let specs = match (iter.next().map(|u| u.trim()), iter.next()) {
+        (Some("bytes"), Some(specs)) => specs,
+        _ => return None,
+    };
+    let ranges = specs
+        .split(',')
+        .map(|spec| spec.trim())
+        .filter(|spec| !spec.is_empty())
+        .filter_map(|spec| {
+            let mut parts = spec.splitn(2, '-');
+            match (parts.next(), parts.next()) {
+                (Some(""), Some(last)) => last.parse().ok().map(ByteRangeSpec::Last),
+                (Some(from), Some("")) => from.parse().ok().map(ByteRangeSpec::AllFrom),
+                (Some(from), Some(to)) => match (from.parse(), to.parse()) {
+                    (Ok(from), Ok(to)) if from <= to => Some(ByteRangeSpec::FromTo(from, to)),
+                    _ => None,
+                },
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+    if ranges.is_empty() {
+        None
+    } else {
+        Some(ranges)
+    }
+}
+
+// `None` means ignore the Range and respond 200.
+fn range_response<B>(
+    req: &Request<B>,
+    ranges: &[ByteRangeSpec],
+    body: &FileBody,
+    last_modified: &SystemTime,
+    etag: &str,
+    headers: HeaderMap,
+    config: &Config,
+) -> Option<Response<Body>> {
+    let valid_ranges: Vec<_> = ranges
+        .iter()
+        .filter_map(|r| r.to_satisfiable_range(body.size))
+        .collect();
+    // the unsatisfiable ranges are dropped, `416` only if none is left
+    let satisfiable = !valid_ranges.is_empty();
+    let valid_ranges = coalesce(valid_ranges);
+    // the whole file is cheaper than too many ranges or the multiple ranges cover most of it
+    if valid_ranges.len() > 1 {
+        let len = valid_ranges.iter().fold(0, |len, &(a, b)| len + b - a + 1);
+        if valid_ranges.len() > config.get_max_ranges() || len * 10 >= body.size * 9 {
+            return None;
+        }
+    }
+
+    let not_modified = req.headers()
+        .get(header::IF_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim())
+        .map(|v| {
+            if v.starts_with('"') || v.starts_with("W/") {
+                v == etag
+            } else {
+                parse_http_date(v)
+                    .map(|d| *last_modified <= d)
+                    .unwrap_or(false)
+            }
+        });
+    match not_modified {
+        Some(not_modified) => match (not_modified, satisfiable) {
+            (true, true) => Some(build_range_response(req, valid_ranges, body, headers)),
+            (true, false) => if *config.get_cache_secs() > 0 {
+                Some(response(StatusCode::NOT_MODIFIED, headers, Body::Empty))
+            } else {
+                None
+            },
+            // 200
+            (false, _) => None,
+        },
+        None => if !satisfiable {
+            let mut headers = headers;
+            insert(&mut headers, header::CONTENT_RANGE, format!("bytes */{}", body.size));
+            Some(response(StatusCode::RANGE_NOT_SATISFIABLE, headers, Body::Empty))
+        } else {
+            Some(build_range_response(req, valid_ranges, body, headers))
+        },
+    }
+}
+
+// sort and merge the overlapping or adjacent ranges.
+fn coalesce(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (a, b) in ranges {
+        match merged.last_mut() {
+            Some(last) if a <= last.1 + 1 => last.1 = ::std::cmp::max(last.1, b),
+            _ => merged.push((a, b)),
+        }
+    }
+    merged
+}
+
+// a single range, or the multiple ranges as `multipart/byteranges`.
+fn build_range_response<B>(req: &Request<B>, valid_ranges: Vec<(u64, u64)>, body: &FileBody, mut headers: HeaderMap) -> Response<Body> {
+    let body = if valid_ranges.len() == 1 {
+        // accept-ranges: bytes
+        // content-range: bytes 2001-4285/4286
+        let (a, b) = valid_ranges[0];
+        insert(&mut headers, header::CONTENT_RANGE, format!("bytes {}-{}/{}", a, b, body.size));
+        FileBody {
+            ranges: Some(valid_ranges),
+            ..body.clone()
+        }
+    } else {
+        let boundary = boundary();
+        let content_type = guess_type(&body.path);
+        let delimiters = valid_ranges
+            .iter()
+            .enumerate()
+            .map(|(i, &(a, b))| {
+                let mut delimiter = format!("{}--{}\r\n", if i == 0 { "" } else { "\r\n" }, boundary);
+                if let Some(ref content_type) = content_type {
+                    delimiter.push_str(&format!("Content-Type: {}\r\n", content_type));
+                }
+                delimiter.push_str(&format!("Content-Range: bytes {}-{}/{}\r\n\r\n", a, b, body.size));
+                Bytes::from(delimiter)
+            })
+            .chain(Some(Bytes::from(format!("\r\n--{}--\r\n", boundary))))
+            .collect();
+        insert(&mut headers, header::CONTENT_TYPE, format!("multipart/byteranges; boundary={}", boundary));
+        FileBody {
+            ranges: Some(valid_ranges),
+            delimiters: Some(delimiters),
+            ..body.clone()
+        }
+    };
+    insert(&mut headers, header::CONTENT_LENGTH, body.len().to_string());
+
+    let body = if req.method() == Method::GET {
+        Body::File(body)
+    } else {
+        Body::Empty
+    };
+    response(StatusCode::PARTIAL_CONTENT, headers, body)
+}
+
+// the random boundary of `multipart/byteranges`
+fn boundary() -> String {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let random = || RandomState::new().build_hasher().finish();
+    format!("{:016x}{:016x}", random(), random())
+}
+
+/// hyper `Request` -> `http::Request<()>`
+pub(crate) fn from_hyper(req: &hyper::Request) -> Request<()> {
+    let mut parts = Request::new(());
+    *parts.method_mut() = req.method().clone().into();
+    *parts.uri_mut() = req.uri().clone().into();
+    *parts.version_mut() = req.version().into();
+    *parts.headers_mut() = req.headers().clone().into();
+    parts
+}
+
+/// `http::Response` -> hyper `Response` and the `Body`, `headers` is the init `Headers` being covered.
+pub(crate) fn into_hyper(res: Response<Body>, headers: Option<hyper::Headers>) -> (hyper::Response, Body) {
+    let (parts, body) = res.into_parts();
+    let mut headers = headers.unwrap_or_default();
+    headers.extend(hyper::Headers::from(parts.headers).iter());
+    let res = hyper::Response::new()
+        .with_status(parts.status.into())
+        .with_headers(headers);
+    (res, body)
+}
+
+#[test]
+fn parse_range_test() {
+    use self::ByteRangeSpec::*;
+    assert_eq!(parse_range("bytes=0-499"), Some(vec![FromTo(0, 499)]));
+    assert_eq!(parse_range("bytes=500-, -500"), Some(vec![AllFrom(500), Last(500)]));
+    assert_eq!(parse_range("bytes=9-1,x-"), None);
+    assert_eq!(parse_range("items=0-1"), None);
+
+    assert_eq!(FromTo(0, 499).to_satisfiable_range(100), Some((0, 99)));
+    assert_eq!(AllFrom(100).to_satisfiable_range(100), None);
+    assert_eq!(Last(500).to_satisfiable_range(100), Some((0, 99)));
+    assert_eq!(Last(0).to_satisfiable_range(100), None);
+
+    assert_eq!(coalesce(vec![(5, 9), (0, 1), (2, 3), (8, 20), (30, 40)]), vec![(0, 3), (5, 20), (30, 40)]);
+}
+
+#[test]
+fn range_response_test() {
+    let size = fs::metadata("Cargo.toml").unwrap().len();
+    let config = Config::new().max_ranges(2);
+    let get_res = |range: String| {
+        let req = Request::builder()
+            .uri("/Cargo.toml")
+            .header(header::RANGE, range)
+            .body(())
+            .unwrap();
+        let res = file(&req, Path::new("Cargo.toml"), &config).unwrap();
+        let content_range = res.headers()
+            .get(header::CONTENT_RANGE)
+            .map(|v| v.to_str().unwrap().to_owned());
+        (res.status(), content_range, res)
+    };
+    let get = |range: String| {
+        let (status, content_range, _) = get_res(range);
+        (status, content_range)
+    };
+    assert_eq!(
+        get(format!("bytes={}-", size)),
+        (StatusCode::RANGE_NOT_SATISFIABLE, Some(format!("bytes */{}", size)))
+    );
+    assert_eq!(get("bytes=2-5".to_owned()), (StatusCode::PARTIAL_CONTENT, Some(format!("bytes 2-5/{}", size))));
+    // the unsatisfiable one is dropped, the others are sent as `multipart/byteranges`
+    let (status, content_range, res) = get_res(format!("bytes={}-,10-11,0-1", size));
+    assert_eq!((status, content_range), (StatusCode::PARTIAL_CONTENT, None));
+    let content_type = res.headers()[header::CONTENT_TYPE].to_str().unwrap().to_owned();
+    let boundary = content_type.trim_start_matches("multipart/byteranges; boundary=");
+    assert_ne!(boundary, content_type);
+    let body = match res.into_body() {
+        Body::File(body) => body,
+        body => panic!("{:?}", body),
+    };
+    assert_eq!(body.ranges(), Some(&[(0, 1), (10, 11)][..]));
+    let delimiters = body.delimiters().unwrap();
+    assert_eq!(
+        delimiters[1],
+        format!("\r\n--{}\r\nContent-Type: text/x-toml\r\nContent-Range: bytes 10-11/{}\r\n\r\n", boundary, size)
+    );
+    assert_eq!(delimiters[2], format!("\r\n--{}--\r\n", boundary));
+    let delimiters_len = delimiters.iter().map(|d| d.len() as u64).sum::<u64>();
+    assert_eq!((delimiters.len(), body.len()), (3, delimiters_len + 4));
+    // more than `max_ranges`
+    assert_eq!(get("bytes=0-1,10-11,20-21".to_owned()), (StatusCode::OK, None));
+    // most of the file
+    assert_eq!(get(format!("bytes=0-{},{}-", size * 9 / 10, size - 2)), (StatusCode::OK, None));
+}
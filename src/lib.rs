@@ -38,13 +38,24 @@ or
 |Get/Head                  | yes |
 |Not Modified(304)         | yes |
 |File Range(bytes)         | yes |
+|Mount Table               | yes |
+|Virtual Hosting           | yes |
+|WebDAV(class 1, 2)        | yes |
+|Archive(tar, tar.gz, zip) | yes |
 */
 extern crate bytes;
 #[macro_use]
 extern crate cfg_if;
+extern crate flate2;
 extern crate futures;
 extern crate futures_cpupool;
+extern crate http;
+extern crate httpdate;
 extern crate hyper;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+extern crate io_uring;
+#[cfg(target_os = "linux")]
+extern crate libc;
 #[macro_use]
 extern crate log;
 extern crate tokio_core;
@@ -61,13 +72,42 @@ pub type FutureObject = Box<Future<Item = (Response, Request), Error = (Error, R
 pub type HyperFutureObject = Box<Future<Item = Response, Error = HyperError>>;
 // #[doc(hidden)]
 
+pub(crate) mod admission;
+pub(crate) mod archive;
+pub(crate) mod cache;
 pub(crate) mod config;
+pub(crate) mod date;
+pub(crate) mod disposition;
 pub(crate) mod error;
+pub(crate) mod executor;
+pub mod http_core;
+pub(crate) mod mmap;
+pub(crate) mod multipart;
+pub(crate) mod ops;
+pub(crate) mod quota;
+#[cfg(target_os = "linux")]
+pub mod sendfile;
+pub(crate) mod stat_cache;
 pub(crate) mod static_file;
 pub(crate) mod static_index;
-
+pub(crate) mod trash;
+pub(crate) mod upload;
+pub(crate) mod uring;
+pub(crate) mod webdav;
+pub(crate) mod xml;
+
+pub use admission::{Admission, WorkClass};
+pub use cache::ContentCache;
 pub use config::Config;
+pub use disposition::Disposition;
 pub use error::{error_handler, Error};
+pub use executor::{spawn_fn, Blocking, BlockingExecutor, Cancel, Spawner};
+pub use quota::Quota;
+pub use stat_cache::MetadataCache;
+pub use trash::{Trash, TrashItem};
+pub use webdav::DavLocks;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub use uring::Uring;
 pub use static_index::StaticIndex;
 pub use static_file::StaticFile;
 
@@ -76,7 +116,11 @@ cfg_if! {
     extern crate mime_guess;
     pub(crate) mod content_type;
     pub(crate) mod static_fs;
+    pub(crate) mod mounts;
+    pub(crate) mod vhost;
     pub use static_fs::StaticFs;
+    pub use mounts::Mounts;
+    pub use vhost::VirtualHosts;
     pub use content_type::maker as content_type_maker;
   }
 }
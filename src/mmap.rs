@@ -0,0 +1,124 @@
+/*!
+Read-only memory mapping of the small files for `Config::mmap`.
+
+A response maps its file once, the jobs of its chunks share the mapping and it is unmapped after the last one.
+
+A mapped file which is truncated underneath raises `SIGBUS` when the lost pages are touched,
+so the inode and size are checked by `fstat` before every slice is copied, and the chunk is read instead if they changed.
+*/
+use hyper::Chunk;
+
+use std::fs::File;
+use std::io;
+use std::time::SystemTime;
+
+cfg_if! {
+    if #[cfg(target_os = "linux")] {
+        use libc;
+
+        use std::os::unix::fs::MetadataExt;
+        use std::os::unix::io::AsRawFd;
+        use std::{ptr, slice};
+
+        pub(crate) struct Mmap {
+            ptr: *mut libc::c_void,
+            len: usize,
+            ino: u64,
+        }
+
+        // the mapping is read only
+        unsafe impl Send for Mmap {}
+        unsafe impl Sync for Mmap {}
+
+        impl Mmap {
+            /// Map the whole `file` if it is still `size` bytes modified at `modified`.
+            pub fn new(file: &File, size: u64, modified: SystemTime) -> io::Result<Self> {
+                let md = file.metadata()?;
+                // modified after the `Response` generated
+                if size == 0 || md.len() != size || md.modified()? != modified {
+                    return Err(io::Error::other("file changed"));
+                }
+                let ptr = unsafe {
+                    libc::mmap(ptr::null_mut(), size as usize, libc::PROT_READ, libc::MAP_SHARED, file.as_raw_fd(), 0)
+                };
+                if ptr == libc::MAP_FAILED {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(Mmap {
+                    ptr,
+                    len: size as usize,
+                    ino: md.ino(),
+                })
+            }
+            /// Copy `len` bytes at `offset` of the mapped `file`, `None` if it is not the one mapped any more.
+            pub fn chunk(&self, file: &File, offset: u64, len: usize) -> Option<Chunk> {
+                let offset = offset as usize;
+                if offset.checked_add(len)? > self.len {
+                    return None;
+                }
+                match file.metadata() {
+                    Ok(ref md) if md.len() as usize == self.len && md.ino() == self.ino => {}
+                    _ => return None,
+                }
+                let mapped = unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) };
+                Some(Chunk::from(mapped[offset..offset + len].to_vec()))
+            }
+        }
+
+        impl Drop for Mmap {
+            fn drop(&mut self) {
+                unsafe { libc::munmap(self.ptr, self.len) };
+            }
+        }
+    } else {
+        pub(crate) struct Mmap;
+
+        impl Mmap {
+            pub fn new(_file: &File, _size: u64, _modified: SystemTime) -> io::Result<Self> {
+                Err(io::Error::other("mmap is Linux only"))
+            }
+            pub fn chunk(&self, _file: &File, _offset: u64, _len: usize) -> Option<Chunk> {
+                None
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn mmap_test() {
+    use futures::{Future, Stream};
+    use futures_cpupool::CpuPool;
+    use hyper::{Method, Request};
+    use std::{env, fs, process};
+    use super::{Config, StaticFile};
+
+    let content = fs::read("Cargo.toml").unwrap();
+    let file = File::open("Cargo.toml").unwrap();
+    let md = file.metadata().unwrap();
+    let mmap = Mmap::new(&file, md.len(), md.modified().unwrap()).unwrap();
+    assert!(Mmap::new(&file, md.len() + 1, md.modified().unwrap()).is_err());
+    assert_eq!(&*mmap.chunk(&file, 1, 10).unwrap(), &content[1..11]);
+    assert!(mmap.chunk(&file, 1, content.len()).is_none());
+
+    // the truncated file is not touched through the mapping
+    let path = env::temp_dir().join(format!("hyper-fs-mmap-{}", process::id()));
+    fs::write(&path, vec![1; 3 * 4096]).unwrap();
+    let file = fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+    let md = file.metadata().unwrap();
+    let mmap = Mmap::new(&file, md.len(), md.modified().unwrap()).unwrap();
+    file.set_len(4096).unwrap();
+    assert!(mmap.chunk(&file, 2 * 4096, 10).is_none());
+    fs::remove_file(&path).ok();
+
+    // the whole body in small chunks out of the mapping
+    let pool = CpuPool::new(1);
+    let config = Config::new().mmap(1 << 20).chunk_size(100);
+    let req = Request::new(Method::Get, "/Cargo.toml".parse().unwrap());
+    let (res, _) = StaticFile::new(pool.clone(), pool.clone(), "Cargo.toml", config)
+        .call(&pool, req)
+        .wait()
+        .unwrap();
+    let body = res.body().concat2().wait().unwrap();
+    assert_eq!(&*body, &content[..]);
+}
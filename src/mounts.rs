@@ -0,0 +1,193 @@
+use url::percent_encoding::{percent_decode, percent_encode_byte};
+use hyper::server::{Request, Response, Service};
+use hyper::{header, Error as HyperError, Method};
+use futures::{future, Future};
+
+use super::{error_handler, Config, Error, FutureObject, HyperFutureObject};
+use super::StaticFs;
+use executor::{BlockingExecutor, Spawner};
+use static_fs::components;
+use static_index::escape;
+
+use std::cmp::Reverse;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+struct Mount<C> {
+    components: Vec<String>,
+    fs: StaticFs<C>,
+}
+
+/// Mount Table: route many url prefixes to different roots
+///
+/// The longest prefix matched by the request's path wins,
+/// and a listing of mount points is rendered at `/` if no root is mounted there.
+pub struct Mounts<C> {
+    spawner: Arc<dyn Spawner>,
+    executor: Arc<dyn BlockingExecutor>,
+    mounts: Vec<Mount<C>>,
+    headers_file: Option<header::Headers>,
+    headers_index: Option<header::Headers>,
+}
+
+impl<C> Mounts<C>
+where
+    C: AsRef<Config> + Clone + Send + 'static,
+{
+    /// `spawner` pumps the body(`Handle` usually), `executor` runs the blocking filesystem work(`CpuPool` usually).
+    pub fn new<S: Spawner, B: BlockingExecutor>(spawner: S, executor: B) -> Self {
+        Self {
+            spawner: Arc::new(spawner),
+            executor: Arc::new(executor),
+            mounts: Vec::new(),
+            headers_file: None,
+            headers_index: None,
+        }
+    }
+    /// mount the `path` at `url`, the old one will be replaced if `url` already mounted.
+    pub fn mount<U, P>(mut self, url: U, path: P, config: C) -> Self
+    where
+        U: Into<String>,
+        P: Into<PathBuf>,
+    {
+        let url = url.into();
+        let components = url.split('/')
+            .filter(|c| !c.is_empty())
+            .map(|c| c.to_owned())
+            .collect::<Vec<_>>();
+        let fs = StaticFs::with_executors(self.spawner.clone(), self.executor.clone(), url, path, config);
+
+        self.mounts.retain(|m| m.components != components);
+        self.mounts.push(Mount {
+            components,
+            fs,
+        });
+        // longest prefix first
+        self.mounts.sort_by_key(|m| Reverse(m.components.len()));
+        self
+    }
+    /// the init `Headers` of file for all the mounts.
+    pub fn headers_file_mut(&mut self) -> &mut Option<header::Headers> {
+        &mut self.headers_file
+    }
+    /// the init `Headers` of index for all the mounts and the listing of mount points.
+    pub fn headers_index_mut(&mut self) -> &mut Option<header::Headers> {
+        &mut self.headers_index
+    }
+    pub fn call(&self, req: Request) -> FutureObject {
+        let req_path_dec = match percent_decode(req.path().as_bytes()).decode_utf8() {
+            Ok(path) => path.into_owned(),
+            Err(_) => return Box::new(future::err((Error::not_found(), req))),
+        };
+        let components = components(&req_path_dec);
+
+        let mount = self.find(&components);
+        debug!("{:?} -> {:?}", req_path_dec, mount.map(|m| &m.components));
+
+        match mount {
+            Some(m) => m.fs.serve(req, self.headers_file.as_ref(), self.headers_index.as_ref()),
+            None if components.is_empty() => self.listing(req),
+            None => Box::new(future::err((Error::not_found(), req))),
+        }
+    }
+    // the longest prefix of `components`
+    fn find(&self, components: &[&str]) -> Option<&Mount<C>> {
+        self.mounts.iter().find(|m| {
+            m.components.len() <= components.len() && m.components.iter().zip(components.iter()).all(|(a, b)| a == b)
+        })
+    }
+    fn listing(&self, req: Request) -> FutureObject {
+        match *req.method() {
+            Method::Head | Method::Get => {}
+            _ => return Box::new(future::err((Error::Method, req))),
+        }
+        let html = render_html(self.mounts.iter().rev().map(|m| &m.components));
+
+        let mut headers = self.headers_index
+            .clone()
+            .unwrap_or_default();
+        headers.set(header::ContentLength(html.len() as u64));
+        headers.set(header::ContentType(::hyper::mime::TEXT_HTML_UTF_8));
+        let mut res = Response::new().with_headers(headers);
+        if *req.method() == Method::Get {
+            res.set_body(html);
+        }
+        Box::new(future::ok((res, req)))
+    }
+}
+
+impl<C> Service for Mounts<C>
+where
+    C: AsRef<Config> + Clone + Send + 'static,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = HyperError;
+    type Future = HyperFutureObject;
+    fn call(&self, req: Request) -> Self::Future {
+        Box::new(
+            Mounts::call(self, req)
+                .or_else(error_handler)
+                .map(|res_req| res_req.0),
+        )
+    }
+}
+
+fn render_html<'a, I>(mounts: I) -> String
+where
+    I: Iterator<Item = &'a Vec<String>>,
+{
+    let mut html = "
+<!DOCTYPE HTML>
+<html><head><meta http-equiv=\"Content-Type\" content=\"text/html; charset=utf-8\">
+<title>Index listing for /</title>
+</head><body><h1>Index listing for  <a href=\"/\">/</a></h1><hr><ul>"
+        .to_owned();
+    for components in mounts {
+        let (mut name, mut name_enc) = (String::new(), String::new());
+        for c in components {
+            name.push('/');
+            name.push_str(c);
+            name_enc.push('/');
+            name_enc.extend(c.bytes().map(percent_encode_byte));
+        }
+        name.push('/');
+        name_enc.push('/');
+        html.push_str(&format!("<li><a href=\"{}\">{}</a></li>", name_enc, escape(&name)));
+    }
+    html.push_str("</ul><hr></body></html>");
+    html
+}
+
+#[test]
+fn mounts_test() {
+    use futures_cpupool::CpuPool;
+
+    let pool = CpuPool::new(1);
+    let mounts = Mounts::new(pool.clone(), pool.clone())
+        .mount("/", "tests", Config::new())
+        .mount("/a/", "tests/index", Config::new())
+        .mount("/a/b", "tests/index", Config::new())
+        .mount("/<\"&>/", "tests", Config::new());
+    let found = |path: &str| mounts.find(&components(path)).map(|m| m.components.join("/"));
+    // the longest prefix wins, `/` matches the others
+    assert_eq!(found("/a/b/c").unwrap(), "a/b");
+    assert_eq!(found("/a/bc").unwrap(), "a");
+    assert_eq!(found("/a").unwrap(), "a");
+    assert_eq!(found("/ab").unwrap(), "");
+    assert_eq!(found("/").unwrap(), "");
+
+    let html = render_html(mounts.mounts.iter().rev().map(|m| &m.components));
+    assert!(html.contains("<li><a href=\"/%61/%62/\">/a/b/</a></li>"));
+    assert!(html.contains("<li><a href=\"/%3C%22%26%3E/\">/&lt;&quot;&amp;&gt;/</a></li>"));
+
+    // the listing of the mounts without `/`
+    let listing = Mounts::new(pool.clone(), pool).mount("/a/", "tests/index", Config::new());
+    let req = Request::new(Method::Get, "/".parse().unwrap());
+    let (res, _) = listing.call(req).wait().unwrap();
+    assert_eq!(res.headers().get(), Some(&header::ContentType(::hyper::mime::TEXT_HTML_UTF_8)));
+
+    let req = Request::new(Method::Get, "/%FF".parse().unwrap());
+    let (res, _) = mounts.call(req).or_else(error_handler).wait().unwrap();
+    assert_eq!(res.status(), ::hyper::StatusCode::NotFound);
+}
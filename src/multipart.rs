@@ -0,0 +1,254 @@
+use url::percent_encoding::percent_decode;
+
+use super::Error;
+
+// the headers of a part larger than it are malformed
+const MAX_HEADERS: usize = 16 * 1024;
+
+/// The events of `Parser::feed`.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Event<'a> {
+    /// A part starts, with the `filename` of its `Content-Disposition`.
+    Begin(Option<String>),
+    Data(&'a [u8]),
+    End,
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    Preamble,
+    Delimiter,
+    Headers,
+    Data,
+    Done,
+}
+
+/// The streaming parser of `multipart/form-data`, it buffers at most a delimiter or the headers of a part.
+pub(crate) struct Parser {
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    state: State,
+}
+
+impl Parser {
+    pub(crate) fn new(boundary: &str) -> Self {
+        Self {
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            // the first delimiter is not preceded by CRLF
+            buf: b"\r\n".to_vec(),
+            state: State::Preamble,
+        }
+    }
+    pub(crate) fn feed<F>(&mut self, data: &[u8], mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(Event) -> Result<(), Error>,
+    {
+        self.buf.extend_from_slice(data);
+        loop {
+            match self.state {
+                State::Preamble | State::Data => {
+                    let data = self.state == State::Data;
+                    match find(&self.buf, &self.delimiter) {
+                        Some(idx) => {
+                            if data {
+                                f(Event::Data(&self.buf[..idx]))?;
+                                f(Event::End)?;
+                            }
+                            let len = idx + self.delimiter.len();
+                            self.buf.drain(..len);
+                            self.state = State::Delimiter;
+                        }
+                        None => {
+                            // keep the tail, it may be the head of the delimiter
+                            let keep = ::std::cmp::min(self.buf.len(), self.delimiter.len() - 1);
+                            let len = self.buf.len() - keep;
+                            if data && len > 0 {
+                                f(Event::Data(&self.buf[..len]))?;
+                            }
+                            self.buf.drain(..len);
+                            return Ok(());
+                        }
+                    }
+                }
+                State::Delimiter => {
+                    if self.buf.len() < 2 {
+                        return Ok(());
+                    }
+                    if self.buf.starts_with(b"--") {
+                        self.state = State::Done;
+                    } else if self.buf.starts_with(b"\r\n") {
+                        self.buf.drain(..2);
+                        self.state = State::Headers;
+                    } else {
+                        return Err(Error::Malformed);
+                    }
+                }
+                State::Headers => {
+                    let len = if self.buf.starts_with(b"\r\n") {
+                        Some(0)
+                    } else {
+                        find(&self.buf, b"\r\n\r\n").map(|idx| idx + 2)
+                    };
+                    let len = match len {
+                        Some(len) => len,
+                        None if self.buf.len() > MAX_HEADERS => return Err(Error::Malformed),
+                        None => return Ok(()),
+                    };
+                    let filename = {
+                        let headers = String::from_utf8_lossy(&self.buf[..len]);
+                        headers
+                            .split("\r\n")
+                            .filter_map(|line| {
+                                let mut kv = line.splitn(2, ':');
+                                match (kv.next(), kv.next()) {
+                                    (Some(k), Some(v)) if k.trim().eq_ignore_ascii_case("Content-Disposition") => Some(v),
+                                    _ => None,
+                                }
+                            })
+                            .next()
+                            .and_then(filename)
+                    };
+                    self.buf.drain(..len + 2);
+                    self.state = State::Data;
+                    f(Event::Begin(filename))?;
+                }
+                State::Done => {
+                    self.buf.clear();
+                    return Ok(());
+                }
+            }
+        }
+    }
+    /// The body ends, it is malformed if the close delimiter is not seen.
+    pub(crate) fn finish(&self) -> Result<(), Error> {
+        match self.state {
+            State::Done => Ok(()),
+            _ => Err(Error::Malformed),
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if haystack.len() < needle.len() {
+        return None;
+    }
+    (0..haystack.len() - needle.len() + 1).find(|&idx| &haystack[idx..idx + needle.len()] == needle)
+}
+
+// `filename*=UTF-8''xx` or `filename="xx"` of the `Content-Disposition`
+fn filename(disposition: &str) -> Option<String> {
+    let mut plain = None;
+    for param in split_params(disposition) {
+        let mut kv = param.splitn(2, '=');
+        let (k, v) = match (kv.next(), kv.next()) {
+            (Some(k), Some(v)) => (k.trim(), v.trim()),
+            _ => continue,
+        };
+        if k.eq_ignore_ascii_case("filename*") {
+            let mut parts = v.splitn(3, '\'');
+            if let (Some(charset), Some(_), Some(value)) = (parts.next(), parts.next(), parts.next()) {
+                if charset.eq_ignore_ascii_case("UTF-8") {
+                    if let Ok(name) = percent_decode(value.as_bytes()).decode_utf8() {
+                        return Some(name.into_owned());
+                    }
+                }
+            }
+        } else if k.eq_ignore_ascii_case("filename") {
+            plain = Some(unquote(v));
+        }
+    }
+    plain
+}
+
+// split by the `;` out of the quotes
+fn split_params(s: &str) -> Vec<&str> {
+    let (mut params, mut start, mut quoted, mut escaped) = (vec![], 0, false, false);
+    for (idx, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                params.push(&s[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    params.push(&s[start..]);
+    params
+}
+
+fn unquote(v: &str) -> String {
+    if v.len() >= 2 && v.starts_with('"') && v.ends_with('"') {
+        let mut out = String::with_capacity(v.len());
+        let mut chars = v[1..v.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => out.extend(chars.next()),
+                c => out.push(c),
+            }
+        }
+        // the browsers escape `"` as `%22`
+        out.replace("%22", "\"")
+    } else {
+        v.to_owned()
+    }
+}
+
+/// The file name of a part without the directories and control characters, `.` is not leading(the hidden and temp files).
+pub(crate) fn sanitize(filename: &str) -> Option<String> {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or("");
+    let name = name.chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>();
+    let name = name.trim().chars().skip_while(|c| *c == '.').collect::<String>();
+    let name = name.trim();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_owned())
+    }
+}
+
+#[test]
+fn multipart_test() {
+    let body = b"preamble\r\n--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue\r\n\
+--xyz\r\nContent-Disposition: form-data; name=\"file\"; filename=\"../a;b\\\".txt\"\r\nContent-Type: text/plain\r\n\r\n\
+line\r\n--xy\r\n\r\n--xyz--\r\nepilogue";
+    // every split of the body
+    for step in 1..body.len() + 1 {
+        let mut parser = Parser::new("xyz");
+        let mut events: Vec<(Option<Option<String>>, Vec<u8>)> = vec![];
+        for chunk in body.chunks(step) {
+            parser
+                .feed(chunk, |e| {
+                    match e {
+                        Event::Data(d) => match events.last_mut() {
+                            Some(&mut (None, ref mut data)) => data.extend_from_slice(d),
+                            _ => events.push((None, d.to_vec())),
+                        },
+                        Event::Begin(name) => events.push((Some(name), vec![])),
+                        Event::End => events.push((None, vec![])),
+                    }
+                    Ok(())
+                })
+                .unwrap();
+        }
+        parser.finish().unwrap();
+        let events = events.into_iter().filter(|e| e != &(None, vec![])).collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![
+                (Some(None), vec![]),
+                (None, b"value".to_vec()),
+                (Some(Some("../a;b\".txt".to_owned())), vec![]),
+                (None, b"line\r\n--xy\r\n".to_vec()),
+            ]
+        );
+    }
+    assert!(Parser::new("xyz").finish().is_err());
+    assert_eq!(filename(" form-data; filename*=UTF-8''%E4%BD%A0.txt; filename=\"a.txt\""), Some("你.txt".to_owned()));
+    assert_eq!(sanitize("C:\\tmp\\..\\.a\u{7}b.txt"), Some("ab.txt".to_owned()));
+    assert_eq!(sanitize("dir/.."), None);
+}
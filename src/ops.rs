@@ -0,0 +1,232 @@
+use hyper::server::{Request, Response};
+use hyper::{header, StatusCode, Uri};
+use url::percent_encoding::percent_decode;
+
+use super::{Config, Error, FutureObject};
+use executor::{spawn_fn, BlockingExecutor};
+use upload::Condition;
+use quota;
+use stat_cache;
+use static_fs::confine;
+
+use futures::Future;
+use walkdir::WalkDir;
+
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::io;
+
+/// `DELETE` the file or directory, a non-empty directory is removed only if `Config::recursive_delete`,
+/// they are moved into the `Config::trash` with the decoded `href` if it is set.
+#[allow(clippy::result_large_err)]
+pub(crate) fn delete<C>(executor: Arc<dyn BlockingExecutor>, root: PathBuf, href: String, path: PathBuf, config: C, req: Request) -> FutureObject
+where
+    C: AsRef<Config> + Send + 'static,
+{
+    let condition = Condition::from_request(&req);
+    let recursive = config.as_ref().get_recursive_delete();
+    let hide_entry = config.as_ref().get_hide_entry();
+    let quota = config.as_ref().get_quota().cloned();
+    let trash = config.as_ref().get_trash().cloned();
+    let path2 = path.clone();
+    let delete = spawn_fn(&*executor, move || {
+        confine(&root, &path2, hide_entry)?;
+        let metadata = fs::symlink_metadata(&path2)?;
+        condition.check_metadata(Some(&metadata))?;
+        let size = quota.as_ref().map(|_| quota::tree_size(&path2)).unwrap_or(0);
+        match trash {
+            Some(_) if metadata.is_dir() && !recursive && fs::read_dir(&path2)?.next().is_some() => {
+                return Err(Error::Conflict)
+            }
+            Some(trash) => {
+                let id = trash.put(&root, &path2, &href)?;
+                debug!("trash {:?}: {}", path2, id);
+            }
+            None => remove(&path2, &metadata, recursive)?,
+        }
+        if let Some(quota) = quota {
+            quota.remove(&path2, size);
+        }
+        Ok(())
+    });
+    Box::new(delete.then(move |result| match result {
+        Ok(_) => {
+            debug!("DELETE {:?}", path);
+            stat_cache::changed(config.as_ref(), &path);
+            Ok((Response::new().with_status(StatusCode::NoContent), req))
+        }
+        Err(e) => Err((e, req)),
+    }))
+}
+
+/// Create the directory `path`(`MKCOL`), its parent must exist.
+#[allow(clippy::result_large_err)]
+pub(crate) fn mkcol<C>(executor: Arc<dyn BlockingExecutor>, root: PathBuf, path: PathBuf, config: C, req: Request) -> FutureObject
+where
+    C: AsRef<Config> + Send + 'static,
+{
+    let hide_entry = config.as_ref().get_hide_entry();
+    let path2 = path.clone();
+    let mkcol = spawn_fn(&*executor, move || {
+        confine(&root, &path2, hide_entry)?;
+        fs::create_dir(&path2).map_err(|e| match e.kind() {
+            // only the unmapped URL can be created
+            io::ErrorKind::AlreadyExists => Error::Method,
+            io::ErrorKind::NotFound => Error::Conflict,
+            _ => e.into(),
+        })
+    });
+    Box::new(mkcol.then(move |result| match result {
+        Ok(_) => {
+            debug!("MKCOL {:?}", path);
+            stat_cache::changed(config.as_ref(), &path);
+            let res = Response::new()
+                .with_status(StatusCode::Created)
+                .with_header(header::ContentLength(0));
+            Ok((res, req))
+        }
+        Err(e) => Err((e, req)),
+    }))
+}
+
+/// The decoded path of the `Destination` header.
+pub(crate) fn destination(req: &Request) -> Result<String, Error> {
+    let raw = req.headers()
+        .get_raw("Destination")
+        .and_then(|raw| raw.one())
+        .and_then(|line| ::std::str::from_utf8(line).ok())
+        .ok_or(Error::Malformed)?;
+    decode_href(raw)
+}
+
+/// The decoded path of a absolute URI or path.
+pub(crate) fn decode_href(href: &str) -> Result<String, Error> {
+    let uri = href.trim().parse::<Uri>().map_err(|_| Error::Malformed)?;
+    percent_decode(uri.path().as_bytes())
+        .decode_utf8()
+        .map(|path| path.into_owned())
+        .map_err(|_| Error::Malformed)
+}
+
+/// `Overwrite: F` forbids replacing the existing destination.
+pub(crate) fn overwrite(req: &Request) -> bool {
+    req.headers()
+        .get_raw("Overwrite")
+        .and_then(|raw| raw.one())
+        .map(|v| v != b"F" && v != b"f")
+        .unwrap_or(true)
+}
+
+/// Rename the `from` to `to`(`MOVE`), the existing `to` is replaced if `overwrite`.
+#[allow(clippy::result_large_err)]
+pub(crate) fn moves<C>(executor: Arc<dyn BlockingExecutor>, root: PathBuf, from: PathBuf, to: PathBuf, config: C, req: Request) -> FutureObject
+where
+    C: AsRef<Config> + Send + 'static,
+{
+    let condition = Condition::from_request(&req);
+    let overwrite = overwrite(&req);
+    let recursive = config.as_ref().get_recursive_delete();
+    let hide_entry = config.as_ref().get_hide_entry();
+    let quota = config.as_ref().get_quota().cloned();
+    let (from2, to2) = (from.clone(), to.clone());
+    let moves = spawn_fn(&*executor, move || {
+        confine(&root, &from2, hide_entry)?;
+        confine(&root, &to2, hide_entry)?;
+        let metadata = fs::symlink_metadata(&from2)?;
+        condition.check_metadata(Some(&metadata))?;
+        let replaced = quota.as_ref().map(|_| quota::tree_size(&to2)).unwrap_or(0);
+        let existed = prepare_destination(&from2, &metadata, &to2, overwrite, recursive)?;
+        fs::rename(&from2, &to2).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::Conflict,
+            _ => e.into(),
+        })?;
+        if let Some(quota) = quota {
+            quota.remove(&to2, replaced);
+            quota.rename(&from2, &to2);
+        }
+        Ok(existed)
+    });
+    Box::new(moves.then(move |result| match result {
+        Ok(existed) => {
+            debug!("MOVE {:?} -> {:?}", from, to);
+            stat_cache::changed(config.as_ref(), &from);
+            stat_cache::changed(config.as_ref(), &to);
+            Ok((created_or_replaced(existed), req))
+        }
+        Err(e) => Err((e, req)),
+    }))
+}
+
+pub(crate) fn created_or_replaced(existed: bool) -> Response {
+    if existed {
+        Response::new().with_status(StatusCode::NoContent)
+    } else {
+        Response::new()
+            .with_status(StatusCode::Created)
+            .with_header(header::ContentLength(0))
+    }
+}
+
+/// Check the destination `to` of `from`, the existing one is removed if it can not be replaced by `rename`,
+/// return whether it exists.
+pub(crate) fn prepare_destination(from: &Path, metadata: &Metadata, to: &Path, overwrite: bool, recursive: bool) -> Result<bool, Error> {
+    if to.starts_with(from) {
+        // into itself
+        return Err(Error::forbidden());
+    }
+    match fs::symlink_metadata(to) {
+        Ok(_) if !overwrite => Err(Error::Precondition),
+        Ok(ref md) if md.is_dir() || metadata.is_dir() => remove(to, md, recursive).map(|_| true),
+        // `rename` replaces the file atomically
+        Ok(_) => Ok(true),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Remove the file, symlink or directory(recursively if `recursive`) at `path`.
+pub(crate) fn remove(path: &Path, metadata: &Metadata, recursive: bool) -> Result<(), Error> {
+    if !metadata.is_dir() {
+        return fs::remove_file(path).map_err(Into::into);
+    }
+    if recursive {
+        return fs::remove_dir_all(path).map_err(Into::into);
+    }
+    fs::remove_dir(path).map_err(|e| match fs::read_dir(path).map(|mut rd| rd.next().is_some()) {
+        // not empty
+        Ok(true) => Error::Conflict,
+        _ => e.into(),
+    })
+}
+
+/// Copy the tree of `from` to `to`, the members are copied if `infinite`.
+pub(crate) fn copy_tree(from: &Path, to: &Path, infinite: bool, follow_links: bool) -> io::Result<()> {
+    // walkdir follows the root, the link itself is copied as its members are
+    if !follow_links && fs::symlink_metadata(from)?.file_type().is_symlink() {
+        #[cfg(unix)]
+        ::std::os::unix::fs::symlink(fs::read_link(from)?, to)?;
+        return Ok(());
+    }
+    let walker = WalkDir::new(from)
+        .follow_links(follow_links)
+        .max_depth(if infinite { usize::MAX } else { 0 });
+    for entry in walker {
+        let entry = entry?;
+        let target = match entry.path().strip_prefix(from) {
+            Ok(rel) if rel.as_os_str().is_empty() => to.to_path_buf(),
+            Ok(rel) => to.join(rel),
+            Err(_) => continue,
+        };
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            fs::create_dir(&target)?;
+        } else if file_type.is_file() {
+            fs::copy(entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            #[cfg(unix)]
+            ::std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+        }
+    }
+    Ok(())
+}
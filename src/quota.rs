@@ -0,0 +1,386 @@
+use hyper::server::Request;
+use walkdir::WalkDir;
+
+use super::{Config, Error};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::fmt;
+
+#[derive(Default)]
+struct State {
+    // the bytes of the files under the root, `None` before it is scanned
+    used: Option<u64>,
+    users: HashMap<String, u64>,
+    // the bytes reserved by the uploads in progress, of all and of the users
+    reserved: u64,
+    users_reserved: HashMap<String, u64>,
+    // the uploader and bytes of the uploaded
+    owners: HashMap<PathBuf, (String, u64)>,
+}
+
+/// The byte quotas and free space guard of the uploads, set it by `Config::quota` for a root.
+///
+/// The usage of the root is scanned at the first upload and counted by the writes after, `scan` it again if the tree is changed by others.
+/// The usage of a user is the bytes uploaded by it and not removed, it is counted in memory since the start.
+/// The uploads over the quotas or the free space are rejected(`Error::Storage`, `507`) before they are written,
+/// the bytes of a replaced file are released after the upload is completed.
+pub struct Quota {
+    limit: Option<u64>,
+    user_limit: Option<u64>,
+    min_free: u64,
+    user: Option<fn(&Request) -> Option<String>>,
+    state: Mutex<State>,
+    rejected: AtomicUsize,
+}
+
+impl Quota {
+    pub fn new() -> Self {
+        Self {
+            limit: None,
+            user_limit: None,
+            min_free: 0,
+            user: None,
+            state: Mutex::new(State::default()),
+            rejected: AtomicUsize::new(0),
+        }
+    }
+    /// The max bytes of the files under the root.
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+    /// The max bytes uploaded by a user, see `user`.
+    pub fn user_limit(mut self, user_limit: u64) -> Self {
+        self.user_limit = Some(user_limit);
+        self
+    }
+    /// The bytes of the filesystem kept free(`statvfs` on Linux), default is 0.
+    pub fn min_free(mut self, min_free: u64) -> Self {
+        self.min_free = min_free;
+        self
+    }
+    /// Who uploads the request(the user authenticated by the proxy usually), the requests of `None` are not limited by `user_limit`.
+    pub fn user(mut self, user: fn(&Request) -> Option<String>) -> Self {
+        self.user = Some(user);
+        self
+    }
+    pub fn get_limit(&self) -> Option<u64> {
+        self.limit
+    }
+    pub fn get_user_limit(&self) -> Option<u64> {
+        self.user_limit
+    }
+    pub fn get_min_free(&self) -> u64 {
+        self.min_free
+    }
+    /// The bytes of the files under the root, `None` before it is scanned.
+    pub fn used(&self) -> Option<u64> {
+        self.state.lock().unwrap().used
+    }
+    /// The bytes uploaded by the `user`.
+    pub fn user_used(&self, user: &str) -> u64 {
+        self.state.lock().unwrap().users.get(user).cloned().unwrap_or(0)
+    }
+    /// The bytes uploaded by every user.
+    pub fn users(&self) -> Vec<(String, u64)> {
+        let mut users = self.state
+            .lock()
+            .unwrap()
+            .users
+            .iter()
+            .map(|(k, v)| (k.clone(), *v))
+            .collect::<Vec<_>>();
+        users.sort();
+        users
+    }
+    /// How many uploads are rejected.
+    pub fn rejected(&self) -> usize {
+        self.rejected.load(Ordering::Relaxed)
+    }
+    /// Walk the `root` for its usage, it blocks.
+    pub fn scan(&self, root: &Path) -> u64 {
+        let used = tree_size(root);
+        self.state.lock().unwrap().used = Some(used);
+        used
+    }
+    pub(crate) fn user_of(&self, req: &Request) -> Option<String> {
+        self.user.and_then(|user| user(req))
+    }
+    /// Reserve `bytes` for the upload of the `user` under the `root`, `free` is the bytes can be used of the filesystem.
+    ///
+    /// It scans the `root` if it is not scanned, the others wait for it, so the tree is walked once.
+    /// A upload without the room(even 0 bytes) is rejected.
+    pub(crate) fn reserve(&self, root: &Path, user: Option<&str>, bytes: u64, free: Option<u64>) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        if self.limit.is_some() && state.used.is_none() {
+            state.used = Some(tree_size(root));
+        }
+        let reserved = state.reserved;
+        let mut room = self.limit
+            .map(|limit| limit.saturating_sub(state.used.unwrap_or(0) + reserved))
+            .unwrap_or(u64::MAX);
+        if let (Some(limit), Some(user)) = (self.user_limit, user) {
+            let used = state.users.get(user).cloned().unwrap_or(0) + state.users_reserved.get(user).cloned().unwrap_or(0);
+            room = ::std::cmp::min(room, limit.saturating_sub(used));
+        }
+        if let Some(free) = free {
+            room = ::std::cmp::min(room, free.saturating_sub(self.min_free).saturating_sub(reserved));
+        }
+        if room == 0 || bytes > room {
+            drop(state);
+            return self.reject();
+        }
+        state.reserved += bytes;
+        if let Some(user) = user {
+            *state.users_reserved.entry(user.to_owned()).or_insert(0) += bytes;
+        }
+        Ok(())
+    }
+    /// Release the `bytes` reserved by the `user`.
+    pub(crate) fn release(&self, user: Option<&str>, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.reserved = state.reserved.saturating_sub(bytes);
+        if let Some(reserved) = user.and_then(|user| state.users_reserved.get_mut(user)) {
+            *reserved = reserved.saturating_sub(bytes);
+        }
+    }
+    /// Reject the upload, it counts the rejected.
+    pub(crate) fn reject<T>(&self) -> Result<T, Error> {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        Err(Error::Storage)
+    }
+    /// The `size` bytes are written to `path` by the `user`, they replace the `replaced` bytes.
+    pub(crate) fn add(&self, path: &Path, user: Option<&str>, size: u64, replaced: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(used) = state.used.as_mut() {
+            *used = (*used + size).saturating_sub(replaced);
+        }
+        if let Some((owner, bytes)) = state.owners.remove(path) {
+            state.uncharge(&owner, bytes);
+        }
+        if let Some(user) = user {
+            *state.users.entry(user.to_owned()).or_insert(0) += size;
+            state.owners.insert(path.to_owned(), (user.to_owned(), size));
+        }
+    }
+    /// The tree of `path` with `size` bytes is removed.
+    pub(crate) fn remove(&self, path: &Path, size: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(used) = state.used.as_mut() {
+            *used = used.saturating_sub(size);
+        }
+        let removed = state
+            .owners
+            .keys()
+            .filter(|p| p.starts_with(path))
+            .cloned()
+            .collect::<Vec<_>>();
+        for p in removed {
+            if let Some((owner, bytes)) = state.owners.remove(&p) {
+                state.uncharge(&owner, bytes);
+            }
+        }
+    }
+    /// The tree of `from` is renamed to `to`, the uploads keep their uploaders.
+    pub(crate) fn rename(&self, from: &Path, to: &Path) {
+        let mut state = self.state.lock().unwrap();
+        let moved = state
+            .owners
+            .keys()
+            .filter(|p| p.starts_with(from))
+            .cloned()
+            .collect::<Vec<_>>();
+        for p in moved {
+            if let Some(owner) = state.owners.remove(&p) {
+                let rest = p.strip_prefix(from).map(|rest| rest.to_owned()).unwrap_or_default();
+                state.owners.insert(to.join(rest), owner);
+            }
+        }
+    }
+}
+
+impl State {
+    fn uncharge(&mut self, user: &str, bytes: u64) {
+        if let Some(used) = self.users.get_mut(user) {
+            *used = used.saturating_sub(bytes);
+        }
+    }
+}
+
+impl Default for Quota {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Quota {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Quota")
+            .field("limit", &self.limit)
+            .field("user_limit", &self.user_limit)
+            .field("min_free", &self.min_free)
+            .field("used", &self.used())
+            .field("rejected", &self.rejected())
+            .finish()
+    }
+}
+
+/// The uploads of a request charged to the `Config::quota`.
+#[derive(Debug, Clone)]
+pub(crate) struct Charge {
+    quota: Option<Arc<Quota>>,
+    user: Option<String>,
+    // the bytes can be used of the filesystem when it is reserved
+    free: u64,
+    // shared by the uploads of the request, the rest is released after the last one is dropped
+    reservation: Option<Arc<Reservation>>,
+}
+
+#[derive(Debug)]
+struct Reservation {
+    quota: Arc<Quota>,
+    user: Option<String>,
+    root: PathBuf,
+    bytes: Mutex<u64>,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let bytes = *self.bytes.lock().unwrap();
+        self.quota.release(self.user.as_deref(), bytes);
+    }
+}
+
+impl Charge {
+    pub(crate) fn new(config: &Config, req: &Request) -> Self {
+        let quota = config.get_quota().cloned();
+        Charge {
+            user: quota.as_ref().and_then(|quota| quota.user_of(req)),
+            quota,
+            free: u64::MAX,
+            reservation: None,
+        }
+    }
+    /// Reserve the `incoming` bytes(0 if unknown) for the upload into `dir` under the `root`, it blocks.
+    pub(crate) fn reserve(&mut self, root: &Path, dir: &Path, incoming: Option<u64>) -> Result<(), Error> {
+        if let Some(ref quota) = self.quota {
+            let free = free_space(dir);
+            let bytes = incoming.unwrap_or(0);
+            quota.reserve(root, self.user.as_deref(), bytes, free)?;
+            self.free = free.map(|free| free.saturating_sub(quota.min_free)).unwrap_or(u64::MAX);
+            self.reservation = Some(Arc::new(Reservation {
+                quota: quota.clone(),
+                user: self.user.clone(),
+                root: root.to_owned(),
+                bytes: Mutex::new(bytes),
+            }));
+        }
+        Ok(())
+    }
+    /// Check the `written` bytes of the request, the reservation grows with them.
+    pub(crate) fn check(&self, written: u64) -> Result<(), Error> {
+        let (quota, reservation) = match (self.quota.as_ref(), self.reservation.as_ref()) {
+            (Some(quota), Some(reservation)) => (quota, reservation),
+            _ => return Ok(()),
+        };
+        if written > self.free {
+            return quota.reject();
+        }
+        let mut bytes = reservation.bytes.lock().unwrap();
+        if written > *bytes {
+            quota.reserve(&reservation.root, self.user.as_deref(), written - *bytes, None)?;
+            *bytes = written;
+        }
+        Ok(())
+    }
+    /// The `size` bytes are written to `path`, they are not reserved any more.
+    pub(crate) fn add(&self, path: &Path, size: u64, replaced: u64) {
+        if let (Some(quota), Some(reservation)) = (self.quota.as_ref(), self.reservation.as_ref()) {
+            let user = self.user.as_deref();
+            quota.add(path, user, size, replaced);
+            let mut bytes = reservation.bytes.lock().unwrap();
+            let released = ::std::cmp::min(*bytes, size);
+            *bytes -= released;
+            quota.release(user, released);
+        }
+    }
+}
+
+/// The bytes of the files under `path`, the symlinks are not followed.
+pub(crate) fn tree_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.metadata().ok())
+        .filter(|md| md.is_file())
+        .map(|md| md.len())
+        .sum()
+}
+
+cfg_if! {
+    if #[cfg(target_os = "linux")] {
+        /// The bytes available to the unprivileged users of the filesystem of `path`.
+        // the types of the fields differ by the targets
+        #[allow(clippy::useless_conversion)]
+        pub(crate) fn free_space(path: &Path) -> Option<u64> {
+            use std::ffi::CString;
+            use std::os::unix::ffi::OsStrExt;
+
+            let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+            let mut stat: ::libc::statvfs = unsafe { ::std::mem::zeroed() };
+            if unsafe { ::libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+                return None;
+            }
+            Some(u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize)))
+        }
+    } else {
+        pub(crate) fn free_space(_path: &Path) -> Option<u64> {
+            None
+        }
+    }
+}
+
+#[test]
+fn quota_test() {
+    let quota = Quota::new().limit(100).user_limit(30);
+    quota.state.lock().unwrap().used = Some(50);
+    let dir = Path::new("tests/index");
+    assert!(quota.reserve(dir, None, 51, None).is_err());
+    assert!(quota.reserve(dir, Some("u"), 31, None).is_err());
+    assert_eq!(quota.rejected(), 2);
+    // reserved under the lock, the concurrent uploads do not share the room
+    quota.reserve(dir, Some("u"), 30, None).unwrap();
+    assert!(quota.reserve(dir, Some("v"), 21, None).is_err());
+    quota.release(Some("u"), 30);
+    quota.reserve(dir, Some("v"), 21, None).unwrap();
+    quota.release(Some("v"), 21);
+
+    quota.add(&dir.join("a"), Some("u"), 20, 0);
+    quota.add(&dir.join("d/b"), Some("u"), 10, 0);
+    assert_eq!(quota.used(), Some(80));
+    assert_eq!(quota.user_used("u"), 30);
+    assert!(quota.reserve(dir, Some("u"), 0, None).is_err());
+    assert_eq!(quota.rejected(), 4);
+
+    // replaced by others
+    quota.add(&dir.join("a"), Some("v"), 5, 20);
+    assert_eq!((quota.used(), quota.user_used("u"), quota.user_used("v")), (Some(65), 10, 5));
+    quota.rename(&dir.join("d"), &dir.join("e"));
+    quota.remove(&dir.join("e"), 10);
+    assert_eq!(quota.users(), vec![("u".to_owned(), 0), ("v".to_owned(), 5)]);
+    assert_eq!(quota.used(), Some(55));
+    assert_eq!(quota.state.lock().unwrap().reserved, 0);
+
+    // the reservation of a failed upload is released when it is dropped
+    let config = Config::new().quota(Arc::new(Quota::new().limit(10)));
+    let req = Request::new(::hyper::Method::Put, "/a".parse().unwrap());
+    let mut charge = Charge::new(&config, &req);
+    charge.reserve(dir, dir, Some(4)).unwrap();
+    charge.check(6).unwrap();
+    assert_eq!(config.get_quota().unwrap().state.lock().unwrap().reserved, 6);
+    drop(charge);
+    assert_eq!(config.get_quota().unwrap().state.lock().unwrap().reserved, 0);
+}
@@ -0,0 +1,188 @@
+/*!
+Zero-copy `sendfile(2)` of the `FileBody` for the servers which own the connections(Linux).
+
+hyper(0.11) does not hand the socket out after the headers are written, so `StaticFile` always streams the chunks.
+A server on `http_core` writes the headers by itself, then sends the `Body::File` by `FileBody::send_to` on a plain TCP connection,
+or by `FileBody::write_to` on TLS or into a compressor.
+
+The file must be the one the `Response` is generated for, it fails if the file changes or shrinks while being sent,
+the connection should be closed then(as `StaticFile` aborts the body).
+*/
+use libc;
+
+use http_core::FileBody;
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::cmp;
+
+// the max count of bytes Linux transfers once
+const MAX_COUNT: u64 = 0x7fff_f000;
+// the buffer of `write_to`
+const BUF_SIZE: usize = 64 * 1024;
+
+/// Send at most `count` bytes of the `file` at `offset` to the `socket` once, `offset` is advanced by the bytes sent.
+///
+/// Call it when the nonblocking `socket` is writable, `WouldBlock` is returned if not.
+pub fn sendfile<S, F>(socket: &S, file: &F, offset: &mut u64, count: u64) -> io::Result<usize>
+where
+    S: AsRawFd + ?Sized,
+    F: AsRawFd + ?Sized,
+{
+    let mut off = *offset as libc::off_t;
+    let count = cmp::min(count, MAX_COUNT) as usize;
+    let sent = unsafe { libc::sendfile(socket.as_raw_fd(), file.as_raw_fd(), &mut off, count) };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    *offset = off as u64;
+    Ok(sent as usize)
+}
+
+impl FileBody {
+    /// Send the body to the blocking `socket` by `sendfile(2)` after the headers are written, return the bytes sent.
+    ///
+    /// The `multipart/byteranges` delimiters are written between the ranges,
+    /// and it falls back to `write_to` if the `socket` does not support `sendfile(2)`.
+    pub fn send_to<S: AsRawFd + Write + ?Sized>(&self, socket: &mut S) -> io::Result<u64> {
+        let file = self.open()?;
+        self.send_parts(socket, &file, send_range)
+    }
+    /// Write the body to the `writer` by `read`, for TLS or the compressed bodies, return the bytes written.
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<u64> {
+        let file = self.open()?;
+        self.send_parts(writer, &file, copy_range)
+    }
+    // the file if it is not changed after the `Response` generated
+    fn open(&self) -> io::Result<File> {
+        let file = File::open(self.path())?;
+        self.verify(&file)?;
+        Ok(file)
+    }
+    fn verify(&self, file: &File) -> io::Result<()> {
+        let md = file.metadata()?;
+        if md.len() != self.size() || md.modified()? != self.modified() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file changed while sending"));
+        }
+        Ok(())
+    }
+    // the delimiters and the ranges in order, `range` sends `len` bytes at `offset`
+    fn send_parts<W, F>(&self, writer: &mut W, file: &File, mut range: F) -> io::Result<u64>
+    where
+        W: Write + ?Sized,
+        F: FnMut(&mut W, &File, u64, u64) -> io::Result<()>,
+    {
+        let ranges = match self.ranges() {
+            Some(ranges) => ranges.to_vec(),
+            None if self.size() > 0 => vec![(0, self.size() - 1)],
+            None => vec![],
+        };
+        let delimiters = self.delimiters().unwrap_or(&[]);
+        for (i, &(first, last)) in ranges.iter().enumerate() {
+            if let Some(delimiter) = delimiters.get(i) {
+                writer.write_all(delimiter)?;
+            }
+            range(writer, file, first, last - first + 1)?;
+        }
+        if let Some(delimiter) = delimiters.get(ranges.len()) {
+            writer.write_all(delimiter)?;
+        }
+        writer.flush()?;
+        self.verify(file)?;
+        Ok(self.len())
+    }
+}
+
+fn send_range<S: AsRawFd + Write + ?Sized>(socket: &mut S, file: &File, mut offset: u64, len: u64) -> io::Result<()> {
+    let end = offset + len;
+    // the delimiters written before
+    socket.flush()?;
+    while offset < end {
+        let count = end - offset;
+        match sendfile(socket, file, &mut offset, count) {
+            Ok(0) => return Err(shrank()),
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            // the `socket` is not supported
+            Err(ref e) if e.raw_os_error() == Some(libc::EINVAL) || e.raw_os_error() == Some(libc::ENOSYS) => {
+                return copy_range(socket, file, offset, end - offset);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn copy_range<W: Write + ?Sized>(writer: &mut W, file: &File, mut offset: u64, len: u64) -> io::Result<()> {
+    let end = offset + len;
+    let mut buf = vec![0; cmp::min(len, BUF_SIZE as u64) as usize];
+    while offset < end {
+        let want = cmp::min(end - offset, buf.len() as u64) as usize;
+        match file.read_at(&mut buf[..want], offset) {
+            Ok(0) => return Err(shrank()),
+            Ok(n) => {
+                writer.write_all(&buf[..n])?;
+                offset += n as u64;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn shrank() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while sending")
+}
+
+#[test]
+fn sendfile_test() {
+    use http::{header, Request};
+    use http_core::{self, Body};
+    use std::fs;
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+    use std::path::Path;
+    use super::Config;
+
+    let content = fs::read("Cargo.toml").unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let body = |range: &str| {
+        let req = Request::builder().header(header::RANGE, range).body(()).unwrap();
+        match http_core::file(&req, Path::new("Cargo.toml"), &Config::new()).unwrap().into_body() {
+            Body::File(body) => body,
+            body => panic!("{:?}", body),
+        }
+    };
+    for body in &[body(""), body("bytes=1-10"), body("bytes=0-1,5-6")] {
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut socket, _) = listener.accept().unwrap();
+        assert_eq!(body.send_to(&mut socket).unwrap(), body.len());
+        drop(socket);
+        let mut sent = vec![];
+        client.read_to_end(&mut sent).unwrap();
+
+        let mut written = vec![];
+        assert_eq!(body.write_to(&mut written).unwrap(), body.len());
+        assert_eq!((sent.len() as u64, &sent), (body.len(), &written));
+        match body.ranges() {
+            None => assert_eq!(sent, content),
+            Some(&[(1, 10)]) => assert_eq!(sent, &content[1..11]),
+            Some(_) => assert!(body.delimiters().is_some()),
+        }
+    }
+
+    // the file shrinks after the `Response` generated
+    let path = ::std::env::temp_dir().join(format!("hyper-fs-sendfile-{}", ::std::process::id()));
+    fs::write(&path, &content).unwrap();
+    let req = Request::builder().body(()).unwrap();
+    let body = match http_core::file(&req, &path, &Config::new()).unwrap().into_body() {
+        Body::File(body) => body,
+        body => panic!("{:?}", body),
+    };
+    fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(1).unwrap();
+    assert_eq!(body.write_to(&mut vec![]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    fs::remove_file(&path).ok();
+}
@@ -0,0 +1,377 @@
+use super::Config;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use std::fs::{self, Metadata};
+use std::io;
+use std::fmt;
+use std::cmp;
+
+struct Entry {
+    metadata: Metadata,
+    expire: Instant,
+}
+
+// (path, follow_links) -> Entry
+type Entries = HashMap<(PathBuf, bool), Entry>;
+
+fn invalidate(entries: &Mutex<Entries>, path: &Path) {
+    let mut entries = entries.lock().unwrap();
+    entries.remove(&(path.to_path_buf(), true));
+    entries.remove(&(path.to_path_buf(), false));
+}
+
+fn invalidate_all(entries: &Mutex<Entries>, dir: &Path) {
+    entries.lock().unwrap().retain(|k, _| !k.0.starts_with(dir));
+}
+
+/// Cache of `metadata()` for the resolved paths
+///
+/// The entries are invalidated by the inotify watches on their directories(Linux), and expire after `ttl` anyway,
+/// the changes inotify can not see(the target of symlink, NFS...) are picked up by `ttl`. Set it by `Config::metadata_cache`.
+pub struct MetadataCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Arc<Mutex<Entries>>,
+    watcher: Option<inotify::Watcher>,
+}
+
+impl MetadataCache {
+    /// Cache at most `capacity` paths for `ttl`, inotify is used if it is available.
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        let entries = Arc::new(Mutex::new(HashMap::new()));
+        let watcher = inotify::Watcher::new(&entries)
+            .map_err(|e| warn!("inotify is unavailable, MetadataCache uses the ttl only: {}", e))
+            .ok();
+        Self {
+            ttl,
+            capacity,
+            entries,
+            watcher,
+        }
+    }
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    /// Whether the entries are invalidated by inotify.
+    pub fn is_watching(&self) -> bool {
+        self.watcher.is_some()
+    }
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+        if let Some(watcher) = self.watcher.as_ref() {
+            watcher.retain(|_| false);
+        }
+    }
+    /// Forget the `path`, for the changes made by yourself.
+    pub fn invalidate<P: AsRef<Path>>(&self, path: P) {
+        invalidate(&self.entries, path.as_ref())
+    }
+    // full, the expired entries are removed, then the eighth expiring first
+    fn evict(&self, entries: &mut Entries, now: Instant) {
+        entries.retain(|_, e| e.expire > now);
+        if !entries.is_empty() && entries.len() >= self.capacity {
+            let mut expires = entries.values().map(|e| e.expire).collect::<Vec<_>>();
+            expires.sort();
+            let expire = expires[cmp::min(self.capacity / 8, expires.len() - 1)];
+            entries.retain(|_, e| e.expire > expire);
+        }
+    }
+    // the unexpired entry only
+    fn cached(&self, path: &Path, follow_links: bool) -> Option<Metadata> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(&(path.to_path_buf(), follow_links))
+            .filter(|e| e.expire > Instant::now())
+            .map(|e| e.metadata.clone())
+    }
+    pub(crate) fn metadata(&self, path: &Path, follow_links: bool) -> io::Result<Metadata> {
+        if let Some(metadata) = self.cached(path, follow_links) {
+            return Ok(metadata);
+        }
+        let (key, now) = ((path.to_path_buf(), follow_links), Instant::now());
+        // watch before stat, the change between them is not lost
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+        if let (Some(watcher), Some(dir)) = (self.watcher.as_ref(), dir) {
+            watcher.watch(dir);
+        }
+        let metadata = if follow_links {
+            fs::metadata(path)
+        } else {
+            fs::symlink_metadata(path)
+        }?;
+        if let (Some(watcher), true) = (self.watcher.as_ref(), metadata.is_dir()) {
+            watcher.watch(path);
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let evicted = entries.len() >= self.capacity;
+        if evicted {
+            self.evict(&mut entries, now);
+        }
+        entries.insert(
+            key,
+            Entry {
+                metadata: metadata.clone(),
+                expire: now + self.ttl,
+            },
+        );
+        // the watches of the directories no longer cached are released,
+        // out of the lock of entries, the inotify thread locks them in the other order
+        if let (Some(watcher), true) = (self.watcher.as_ref(), evicted) {
+            let dirs = entries
+                .keys()
+                .flat_map(|k| Some(k.0.clone()).into_iter().chain(k.0.parent().map(Path::to_path_buf)))
+                .collect::<HashSet<_>>();
+            drop(entries);
+            watcher.retain(|dir| dirs.contains(dir));
+        }
+        Ok(metadata)
+    }
+}
+
+impl fmt::Debug for MetadataCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MetadataCache")
+            .field("ttl", &self.ttl)
+            .field("capacity", &self.capacity)
+            .field("len", &self.len())
+            .field("watching", &self.is_watching())
+            .field("watches", &self.watcher.as_ref().map(|w| w.len()).unwrap_or(0))
+            .finish()
+    }
+}
+
+/// `metadata()`(`follow_links`) or `symlink_metadata()` of the `path`, by the `MetadataCache` of `config` if it is set.
+pub(crate) fn metadata(config: &Config, path: &Path, follow_links: bool) -> io::Result<Metadata> {
+    match config.get_metadata_cache() {
+        Some(cache) => cache.metadata(path, follow_links),
+        None if follow_links => fs::metadata(path),
+        None => fs::symlink_metadata(path),
+    }
+}
+
+/// The metadata in the `MetadataCache` of `config`, `None` if it is not cached, no `stat` is called.
+pub(crate) fn cached(config: &Config, path: &Path, follow_links: bool) -> Option<Metadata> {
+    config.get_metadata_cache().and_then(|cache| cache.cached(path, follow_links))
+}
+
+/// Forget the `path`, the paths under it and its parent, after the tree is changed by the writes.
+pub(crate) fn changed(config: &Config, path: &Path) {
+    if let Some(cache) = config.get_metadata_cache() {
+        invalidate_all(&cache.entries, path);
+        if let Some(dir) = path.parent() {
+            invalidate(&cache.entries, dir);
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(target_os = "linux")] {
+        mod inotify {
+            use libc;
+
+            use super::{invalidate, invalidate_all, Entries};
+
+            use std::collections::HashMap;
+            use std::sync::{Arc, Mutex, Weak};
+            use std::path::{Path, PathBuf};
+            use std::ffi::{CString, OsStr};
+            use std::os::unix::ffi::OsStrExt;
+            use std::os::unix::io::RawFd;
+            use std::io;
+            use std::mem;
+            use std::thread;
+
+            const MASK: u32 = libc::IN_ATTRIB | libc::IN_MODIFY | libc::IN_CLOSE_WRITE | libc::IN_CREATE | libc::IN_DELETE
+                | libc::IN_MOVED_FROM | libc::IN_MOVED_TO | libc::IN_DELETE_SELF | libc::IN_MOVE_SELF;
+
+            #[derive(Default)]
+            struct Watches {
+                dirs: HashMap<PathBuf, libc::c_int>,
+                wds: HashMap<libc::c_int, PathBuf>,
+            }
+
+            struct Fd(RawFd);
+
+            impl Drop for Fd {
+                fn drop(&mut self) {
+                    unsafe { libc::close(self.0) };
+                }
+            }
+
+            /// The inotify instance, its thread exits after it is dropped.
+            pub struct Watcher {
+                fd: Arc<Fd>,
+                watches: Arc<Mutex<Watches>>,
+            }
+
+            impl Watcher {
+                pub fn new(entries: &Arc<Mutex<Entries>>) -> io::Result<Self> {
+                    let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+                    if fd < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    let fd = Arc::new(Fd(fd));
+                    let watches = Arc::new(Mutex::new(Watches::default()));
+                    let (weak, entries, watches2) = (Arc::downgrade(&fd), entries.clone(), watches.clone());
+                    thread::Builder::new()
+                        .name("hyper-fs-inotify".to_owned())
+                        .spawn(move || run(weak, entries, watches2))?;
+                    Ok(Self { fd, watches })
+                }
+                /// Watch the directory `dir` if it is not watched.
+                pub fn watch(&self, dir: &Path) {
+                    let mut watches = self.watches.lock().unwrap();
+                    if watches.dirs.contains_key(dir) {
+                        return;
+                    }
+                    let cpath = match CString::new(dir.as_os_str().as_bytes()) {
+                        Ok(p) => p,
+                        Err(_) => return,
+                    };
+                    // out of `max_user_watches`, the ttl works
+                    let wd = unsafe { libc::inotify_add_watch((self.fd).0, cpath.as_ptr(), MASK | libc::IN_ONLYDIR) };
+                    if wd < 0 {
+                        debug!("inotify_add_watch({:?}): {}", dir, io::Error::last_os_error());
+                        return;
+                    }
+                    watches.dirs.insert(dir.to_path_buf(), wd);
+                    watches.wds.insert(wd, dir.to_path_buf());
+                }
+                /// Release the watches of the directories not kept by `f`.
+                pub fn retain<F: Fn(&Path) -> bool>(&self, f: F) {
+                    let mut watches = self.watches.lock().unwrap();
+                    let released = watches.dirs.iter().filter(|&(dir, _)| !f(dir)).map(|(_, wd)| *wd).collect::<Vec<_>>();
+                    for wd in released {
+                        unsafe { libc::inotify_rm_watch((self.fd).0, wd) };
+                        if let Some(dir) = watches.wds.remove(&wd) {
+                            watches.dirs.remove(&dir);
+                        }
+                    }
+                }
+                /// How many directories are watched.
+                pub fn len(&self) -> usize {
+                    self.watches.lock().unwrap().dirs.len()
+                }
+            }
+
+            fn run(fd: Weak<Fd>, entries: Arc<Mutex<Entries>>, watches: Arc<Mutex<Watches>>) {
+                let mut buf = vec![0u8; 64 * 1024];
+                loop {
+                    // hold the fd while reading only, wake up to see whether the Watcher is dropped
+                    let raw = match fd.upgrade() {
+                        Some(fd) => fd.0,
+                        None => return,
+                    };
+                    let mut pfd = libc::pollfd { fd: raw, events: libc::POLLIN, revents: 0 };
+                    unsafe { libc::poll(&mut pfd, 1, 1000) };
+                    let fd = match fd.upgrade() {
+                        Some(fd) => fd,
+                        None => return,
+                    };
+                    let n = unsafe { libc::read(fd.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+                    if n <= 0 {
+                        continue;
+                    }
+                    let mut offset = 0;
+                    while offset + mem::size_of::<libc::inotify_event>() <= n as usize {
+                        let event = unsafe { &*(buf[offset..].as_ptr() as *const libc::inotify_event) };
+                        let name_start = offset + mem::size_of::<libc::inotify_event>();
+                        let name = &buf[name_start..name_start + event.len as usize];
+                        let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+                        offset = name_start + event.len as usize;
+                        handle(&entries, &watches, event.wd, event.mask, OsStr::from_bytes(name));
+                    }
+                }
+            }
+
+            fn handle(entries: &Mutex<Entries>, watches: &Mutex<Watches>, wd: libc::c_int, mask: u32, name: &OsStr) {
+                if mask & libc::IN_Q_OVERFLOW != 0 {
+                    entries.lock().unwrap().clear();
+                    return;
+                }
+                let mut watches = watches.lock().unwrap();
+                let dir = match watches.wds.get(&wd) {
+                    Some(dir) => dir.clone(),
+                    None => return,
+                };
+                if mask & (libc::IN_IGNORED | libc::IN_DELETE_SELF | libc::IN_MOVE_SELF) != 0 {
+                    if mask & libc::IN_IGNORED != 0 {
+                        watches.wds.remove(&wd);
+                        watches.dirs.remove(&dir);
+                    }
+                    invalidate_all(entries, &dir);
+                    return;
+                }
+                // the mtime of the directory is changed too
+                invalidate(entries, &dir);
+                if !name.is_empty() {
+                    let path = dir.join(name);
+                    if mask & (libc::IN_DELETE | libc::IN_MOVED_FROM) != 0 {
+                        invalidate_all(entries, &path);
+                    } else {
+                        invalidate(entries, &path);
+                    }
+                }
+            }
+        }
+    } else {
+        mod inotify {
+            use super::Entries;
+
+            use std::sync::{Arc, Mutex};
+            use std::path::Path;
+            use std::io;
+
+            pub struct Watcher;
+
+            impl Watcher {
+                pub fn new(_entries: &Arc<Mutex<Entries>>) -> io::Result<Self> {
+                    Err(io::Error::new(io::ErrorKind::Other, "inotify is Linux only"))
+                }
+                pub fn watch(&self, _dir: &Path) {}
+                pub fn retain<F: Fn(&Path) -> bool>(&self, _f: F) {}
+                pub fn len(&self) -> usize {
+                    0
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn metadata_cache_test() {
+    let cache = MetadataCache::new(Duration::from_secs(60), 2);
+    let path = Path::new("tests/index/file");
+    assert!(cache.metadata(path, true).unwrap().is_file());
+    assert!(cache.metadata(Path::new("tests/index"), true).unwrap().is_dir());
+    assert_eq!(cache.len(), 2);
+    cache.invalidate(path);
+    assert_eq!(cache.len(), 1);
+    // full, the expired entries are removed first, then the one expiring first
+    cache.metadata(path, true).unwrap();
+    cache.metadata(path, false).unwrap();
+    assert_eq!(cache.len(), 2);
+    assert!(cache.cached(Path::new("tests/index"), true).is_none());
+    // the empty parent is not watched, the watch of `tests` is released with `tests/index`
+    cache.metadata(Path::new("Cargo.toml"), true).unwrap();
+    if let Some(watcher) = cache.watcher.as_ref() {
+        assert_eq!(watcher.len(), 1);
+    }
+    cache.clear();
+    if let Some(watcher) = cache.watcher.as_ref() {
+        assert_eq!(watcher.len(), 0);
+    }
+    assert!(cache.metadata(Path::new("tests/index/none"), true).is_err());
+}
@@ -1,36 +1,42 @@
 use futures::{Async, Future, Poll, Sink, Stream};
 use futures::sync::mpsc::{SendError, Sender};
-use hyper::{header, Body, Chunk, Error as HyperError, Headers, Method, StatusCode};
+use hyper::{header, Body as HyperBody, Chunk, Error as HyperError, Headers};
 use hyper::server::{Request, Response};
 
-use bytes::{BufMut, BytesMut};
-use futures_cpupool::{CpuFuture, CpuPool};
-use tokio_core::reactor::Handle;
+use bytes::{Bytes, BytesMut};
 
-use super::{Config, Error, FutureObject};
+use super::{Config, ContentCache, Error, FutureObject};
+use executor::{spawn_fn, spawn_request, Blocking, BlockingExecutor, Spawner};
+use http_core::{self, Body, FileBody};
+use mmap::Mmap;
+use uring::Uring;
+use stat_cache;
 
-use std::io::{self, Read, Seek, SeekFrom};
-use std::fs::{self, File, Metadata};
+use std::collections::VecDeque;
+use std::io;
+use std::fs::{File, Metadata};
 use std::path::PathBuf;
-use std::{mem, time};
+use std::sync::Arc;
+use std::time::SystemTime;
+use std::{cmp, mem};
 
 /// Static File
 pub struct StaticFile<C> {
     inner: Option<Inner<C>>,
-    content: Option<CpuFuture<(Response, Request, Option<SendAllCallBackBox>), (Error, Request)>>,
-    handle: Handle,
+    content: Option<Blocking<(Response, Request, Option<SendAllCallBackBox>), (Error, Request)>>,
+    spawner: Arc<dyn Spawner>,
 }
 
 trait SendAll {
-    fn send_all(self, handle: &Handle);
+    fn send_all(self, spawner: &dyn Spawner);
 }
 
 trait SendAllCallBack {
-    fn send_all_call_back(self: Box<Self>, handle: &Handle);
+    fn send_all_call_back(self: Box<Self>, spawner: &dyn Spawner);
 }
 impl<T: SendAll> SendAllCallBack for T {
-    fn send_all_call_back(self: Box<Self>, handle: &Handle) {
-        (*self).send_all(handle)
+    fn send_all_call_back(self: Box<Self>, spawner: &dyn Spawner) {
+        (*self).send_all(spawner)
     }
 }
 type SendAllCallBackBox = Box<SendAllCallBack + Send + 'static>;
@@ -38,7 +44,7 @@ type SendAllCallBackBox = Box<SendAllCallBack + Send + 'static>;
 type HeaderMaker = FnMut(&mut File, &Metadata, &PathBuf, &Request, &mut header::Headers) -> io::Result<()> + Send + 'static;
 
 pub struct Inner<C> {
-    pool: CpuPool,
+    executor: Arc<dyn BlockingExecutor>,
     file: PathBuf,
     config: C,
     headers: Option<header::Headers>,
@@ -49,9 +55,18 @@ impl<C> StaticFile<C>
 where
     C: AsRef<Config> + Send + 'static,
 {
-    pub fn new<P: Into<PathBuf>>(handle: Handle, pool: CpuPool, file: P, config: C) -> Self {
+    /// `spawner` pumps the body(`Handle` usually), `executor` runs the blocking filesystem work(`CpuPool` usually).
+    pub fn new<S, B, P>(spawner: S, executor: B, file: P, config: C) -> Self
+    where
+        S: Spawner,
+        B: BlockingExecutor,
+        P: Into<PathBuf>,
+    {
+        Self::with_executors(Arc::new(spawner), Arc::new(executor), file, config)
+    }
+    pub(crate) fn with_executors<P: Into<PathBuf>>(spawner: Arc<dyn Spawner>, executor: Arc<dyn BlockingExecutor>, file: P, config: C) -> Self {
         let inner = Inner {
-            pool: pool,
+            executor,
             file: file.into(),
             config: config,
             headers: Some(header::Headers::new()),
@@ -60,7 +75,7 @@ where
         Self {
             inner: Some(inner),
             content: None,
-            handle: handle,
+            spawner,
         }
     }
     ///  You should seek to 0 if you modify the File(Read or seek), You could not write or append it.
@@ -82,9 +97,23 @@ where
     pub fn headers_mut(&mut self) -> &mut Option<Headers> {
         &mut self.inner.as_mut().unwrap().headers
     }
-    pub fn call(mut self, pool: &CpuPool, req: Request) -> FutureObject {
+    pub fn call<B: BlockingExecutor + ?Sized>(mut self, executor: &B, req: Request) -> FutureObject {
         let inner = mem::replace(&mut self.inner, None).expect("Call twice");
-        self.content = Some(pool.spawn_fn(move || inner.call(req)));
+        // the fresh content of `ContentCache` is served on the current thread if the metadata is in memory,
+        // neither the file nor the `headers_maker` is touched
+        let cached = inner
+            .config()
+            .get_content_cache()
+            .and_then(|cache| {
+                stat_cache::cached(inner.config(), &inner.file, true)
+                    .map(|md| cache.is_fresh(&inner.file, &md, inner.header_maker.is_some()))
+            })
+            .unwrap_or(false);
+        self.content = Some(if cached {
+            Blocking::done(inner.call(req))
+        } else {
+            spawn_request(executor, req, move |req| inner.call(req))
+        });
         Box::new(self)
     }
 }
@@ -101,7 +130,7 @@ impl<C> Future for StaticFile<C> {
         {
             Ok(Async::NotReady) => Ok(Async::NotReady),
             Ok(Async::Ready((response, req, Some(t)))) => {
-                t.send_all_call_back(&self.handle);
+                t.send_all_call_back(&*self.spawner);
                 Ok(Async::Ready((response, req)))
             }
             Ok(Async::Ready((response, req, None))) => Ok(Async::Ready((response, req))),
@@ -117,163 +146,93 @@ where
     pub fn config(&self) -> &Config {
         self.config.as_ref()
     }
-    fn call(mut self, mut req: Request) -> Result<(Response, Request, Option<SendAllCallBackBox>), (Error, Request)> {
-        let mut headers = mem::replace(&mut self.headers, None).unwrap_or_else(header::Headers::new);
-        headers.set(header::AcceptRanges(vec![header::RangeUnit::Bytes]));
-        if *self.config().get_cache_secs() != 0 {
-            headers.set(header::CacheControl(vec![
-                header::CacheDirective::Public,
-                header::CacheDirective::MaxAge(*self.config().get_cache_secs()),
-            ]));
-        }
-
-        // method error
-        match *req.method() {
-            Method::Head | Method::Get => {}
-            _ => return Err((Error::Method, req)),
-        }
-        // io error
-        let metadata = match fs::metadata(&self.file) {
-            Ok(metada) => {
-                if !metada.is_file() {
-                    return Err((Error::Typo, req));
-                }
-                metada
-            }
-            Err(e) => {
-                return Err((e.into(), req));
-            }
+    fn call(mut self, req: Request) -> Result<(Response, Request, Option<SendAllCallBackBox>), (Error, Request)> {
+        let res = match http_core::file(&http_core::from_hyper(&req), &self.file, self.config()) {
+            Ok(res) => res,
+            Err(e) => return Err((e, req)),
         };
+        let (mut res, body) = http_core::into_hyper(res, self.headers.take());
 
-        //301, redirect
-        // https://rust-lang.org/logo.ico///?labels=E-easy&state=open
-        // http://0.0.0.0:8000///
-        if req.path().len() != 1 && req.path().ends_with('/') {
-            let mut new_path = req.path().to_owned();
-            while new_path.ends_with('/') {
-                new_path.pop();
-            }
-            if new_path.is_empty() {
-                new_path.push('/');
-            }
-            if let Some(query) = req.query() {
-                new_path.push('?');
-                new_path.push_str(query);
+        // response body  stream
+        let body = match body {
+            Body::File(body) => body,
+            _ => return Ok((res, req, None)),
+        };
+        // 200, the `headers_maker` is called
+        let make = body.ranges().is_none() && self.header_maker.is_some();
+        let cache = self.config()
+            .get_content_cache()
+            .filter(|cache| cache.is_cacheable(body.size()))
+            .cloned();
+        let cached = cache
+            .as_ref()
+            .and_then(|cache| cache.get(&self.file, body.modified(), body.size()));
+        match cached {
+            Some((content, Some(ref headers))) if make => {
+                res.headers_mut().extend(headers.iter());
+                return Ok((cached_body(res, &body, content), req, None));
             }
-            headers.set(header::Location::new(new_path));
-            return Ok((
-                Response::new()
-                    .with_status(StatusCode::MovedPermanently)
-                    .with_headers(headers),
-                req,
-                None,
-            ));
+            Some((content, _)) if !make => return Ok((cached_body(res, &body, content), req, None)),
+            _ => {}
         }
-        // HTTP Last-Modified
-        let last_modified = match metadata.modified() {
-            Ok(time) => time,
+
+        let mut file = match File::open(&self.file) {
+            Ok(file) => file,
             Err(e) => {
                 return Err((e.into(), req));
             }
         };
-        let delta_modified = last_modified
-            .duration_since(time::UNIX_EPOCH)
-            .expect("SystemTime::duration_since(UNIX_EPOCH) failed");
-        let http_last_modified = header::HttpDate::from(last_modified);
-
-        let size = metadata.len();
-        let etag = header::EntityTag::weak(format!(
-            "{:x}-{:x}.{:x}",
-            size,
-            delta_modified.as_secs(),
-            delta_modified.subsec_nanos()
-        ));
-        headers.set(header::LastModified(http_last_modified));
-        headers.set(header::ETag(etag.clone()));
-
-        // Range
-        let range: Option<header::Range> = req.headers_mut().remove();
-        let mut headers = if let Some(header::Range::Bytes(ranges)) = range {
-            match self.range(
-                &ranges[..],
-                &req,
-                headers,
+        let mut made = None;
+        if make {
+            let metadata = match file.metadata() {
+                Ok(md) => md,
+                Err(e) => {
+                    return Err((e.into(), req));
+                }
+            };
+            let before = cache.as_ref().map(|_| res.headers().clone());
+            let mut maker = self.header_maker.take().unwrap();
+            if let Err(e) = maker(
+                &mut file,
                 &metadata,
-                &last_modified,
-                &delta_modified,
-                &etag,
+                &self.file,
+                &req,
+                res.headers_mut(),
             ) {
-                Ok(o) => {
-                    return match o {
-                        Ok((res, call_back)) => Ok((res, req, call_back)),
-                        Err(e) => Err((e, req)),
-                    }
-                }
-                Err(h) => h,
+                return Err((e.into(), req));
             }
+            // the `Headers` the maker set are cached with the content
+            made = before.map(|before| {
+                res.headers()
+                    .iter()
+                    .filter(|h| before.get_raw(h.name()) != Some(h.raw()))
+                    .fold(Headers::new(), |mut made, h| {
+                        made.set_raw(h.name().to_owned(), h.raw().clone());
+                        made
+                    })
+            });
+        }
+        if let Some(content) = cache.and_then(|cache| self.cache_content(&cache, &file, &body, made)) {
+            return Ok((cached_body(res, &body, content), req, None));
+        }
+        let mmap = if body.size() <= self.config().get_mmap() {
+            Mmap::new(&file, body.size(), body.modified())
+                .map_err(|e| debug!("mmap({:?}): {}", self.file, e))
+                .ok()
         } else {
-            // 304
-            let mut _304 = false;
-            if let Some(&header::IfNoneMatch::Items(ref etags)) = req.headers().get() {
-                if !etags.is_empty() && *self.config.as_ref().get_cache_secs() > 0 && etag == etags[0] {
-                    _304 = true;
-                }
-            }
-            if _304 {
-                return Ok((
-                    Response::new()
-                        .with_headers(headers)
-                        .with_status(StatusCode::NotModified),
-                    req,
-                    None,
-                ));
-            }
-            headers
+            None
         };
-
-        // 200
-        // response Header
-        headers.set(header::ContentLength(size));
-        let mut res = Response::new().with_headers(headers);
-        // response body  stream
-        match *req.method() {
-            Method::Get => {
-                let mut file = match File::open(&self.file) {
-                    Ok(file) => file,
-                    Err(e) => {
-                        return Err((e.into(), req));
-                    }
-                };
-                if self.header_maker.is_some() {
-                    let mut maker = mem::replace(&mut self.header_maker, None).unwrap();
-                    if let Err(e) = maker(
-                        &mut file,
-                        &metadata,
-                        &self.file,
-                        &req,
-                        &mut res.headers_mut(),
-                    ) {
-                        return Err((e.into(), req));
-                    }
-                    // have to reset seek if moved...
-                }
-
-                let (sender, body) = Body::pair();
-                res.set_body(body);
-                Ok((
-                    res,
-                    req,
-                    Some(Box::new(FileChunkStream::new(
-                        &self.pool,
-                        sender,
-                        file,
-                        *self.config().get_chunk_size(),
-                    )) as SendAllCallBackBox),
-                ))
-            }
-            Method::Head => Ok((res, req, None)),
-            _ => unreachable!(),
-        }
+        let (sender, hyper_body) = HyperBody::pair();
+        res.set_body(hyper_body);
+        let stream = Box::new(FileChunkStream::new(
+            &self.executor,
+            sender,
+            file,
+            mmap,
+            &body,
+            self.config(),
+        )) as SendAllCallBackBox;
+        Ok((res, req, Some(stream)))
     }
 }
 
@@ -281,268 +240,316 @@ impl<C> Inner<C>
 where
     C: AsRef<Config>,
 {
-    fn range(
-        &self,
-        ranges: &[header::ByteRangeSpec],
-        req: &Request,
-        headers: header::Headers,
-        metadata: &Metadata,
-        last_modified: &time::SystemTime,
-        delta_modified: &time::Duration,
-        etag: &header::EntityTag,
-    ) -> Result<Result<(Response, Option<SendAllCallBackBox>), Error>, header::Headers> {
-        let valid_ranges: Vec<_> = ranges
-            .iter()
-            .filter_map(|r| r.to_satisfiable_range(metadata.len()))
-            .collect();
-
-        let not_modified = if let Some(&header::IfRange::EntityTag(ref e)) = req.headers().get() {
-            Some(e == etag)
-        } else if let Some(&header::IfRange::Date(ref d)) = req.headers().get() {
-            let http_last_modified_sub_nsecs = header::HttpDate::from(*last_modified - time::Duration::new(0, delta_modified.subsec_nanos()));
-            Some(http_last_modified_sub_nsecs <= *d)
-        } else {
-            None
-        };
-        match not_modified {
-            Some(not_modified) => {
-                match (not_modified, valid_ranges.len() == ranges.len()) {
-                    (true, true) => Ok(self.build_range_response(valid_ranges, metadata, req, headers)),
-                    (true, false) => if *self.config.as_ref().get_cache_secs() > 0 {
-                        Ok(Ok((
-                            Response::new()
-                                .with_headers(headers)
-                                .with_status(StatusCode::NotModified),
-                            None,
-                        )))
-                    } else {
-                        Err(headers)
-                    },
-                    (false, _) => {
-                        // 200
-                        Err(headers)
-                    }
-                }
-            }
-            None => {
-                if valid_ranges.len() != ranges.len() {
-                    Ok(Ok((
-                        Response::new()
-                            .with_headers(headers)
-                            .with_status(StatusCode::RangeNotSatisfiable),
-                        None,
-                    )))
-                } else {
-                    Ok(self.build_range_response(valid_ranges, metadata, req, headers))
-                }
-            }
-        }
+    // read the small file into `ContentCache`
+    fn cache_content(&self, cache: &ContentCache, file: &File, body: &FileBody, headers: Option<Headers>) -> Option<Bytes> {
+        let content = read_a_chunk(file, 0, body.size() as usize).ok()?;
+        // modified after the `Response` generated
+        Snapshot::of(body).verify(file).ok()?;
+        let content = Bytes::from(content);
+        cache.insert(&self.file, body.modified(), content.clone(), headers);
+        Some(content)
     }
-    fn build_range_response(
-        &self,
-        valid_ranges: Vec<(u64, u64)>,
-        metadata: &Metadata,
-        req: &Request,
-        mut headers: header::Headers,
-    ) -> Result<(Response, Option<SendAllCallBackBox>), Error> {
-        let content_length = valid_ranges
-            .as_slice()
-            .iter()
-            .fold(0u64, |len, &(a, b)| len + b - a + 1);
-        // accept-ranges: bytes
-        // content-range: bytes 2001-4285/4286
-        let content_ranges = {
-            let mut s = "bytes ".to_owned();
-            for (idx, &(a, b)) in valid_ranges.as_slice().iter().enumerate() {
-                if idx + 1 == valid_ranges.len() {
-                    s.push_str(&format!("{}-{}", a, b));
-                } else {
-                    s.push_str(&format!("{}-{},", a, b));
-                }
-            }
-            s.push_str(&format!("/{}", metadata.len()));
-            s
-        };
-        headers.set(header::ContentLength(content_length));
-        headers.set_raw("content-ranges", content_ranges);
-        let mut res = Response::new()
-            .with_status(StatusCode::PartialContent)
-            .with_headers(headers);
-        match *req.method() {
-            Method::Get => {
-                let file = match File::open(&self.file) {
-                    Ok(file) => file,
-                    Err(e) => {
-                        return Err(e.into());
-                    }
-                };
-                let (sender, body) = Body::pair();
-                res.set_body(body);
-                Ok((
-                    res,
-                    Some(Box::new(FileRangeChunkStream::new(
-                        &self.pool,
-                        sender,
-                        file,
-                        valid_ranges,
-                        *self.config().get_chunk_size(),
-                    )) as SendAllCallBackBox),
-                ))
+}
+
+// the body of the cached content
+fn cached_body(mut res: Response, body: &FileBody, content: Bytes) -> Response {
+    let content = match body.ranges() {
+        None => content,
+        Some(ranges) if ranges.len() == 1 => content.slice(ranges[0].0 as usize, ranges[0].1 as usize + 1),
+        Some(ranges) => {
+            let delimiters = body.delimiters().unwrap_or(&[]);
+            let mut buf = BytesMut::with_capacity(body.len() as usize);
+            for (i, &(a, b)) in ranges.iter().enumerate() {
+                buf.extend_from_slice(delimiters.get(i).map(|d| &d[..]).unwrap_or(&[]));
+                buf.extend_from_slice(&content[a as usize..b as usize + 1]);
             }
-            Method::Head => Ok((res, None)),
-            _ => unreachable!(),
+            buf.extend_from_slice(delimiters.last().map(|d| &d[..]).unwrap_or(&[]));
+            buf.freeze()
         }
-    }
+    };
+    res.set_body(content);
+    res
 }
 
 impl SendAll for FileChunkStream {
-    fn send_all(mut self, handle: &Handle) {
+    fn send_all(mut self, spawner: &dyn Spawner) {
         let sender = mem::replace(&mut self.sender, None).unwrap();
-        handle.spawn(sender.send_all(self).map(|_| ()).map_err(|_| ()));
+        spawner.spawn(Box::new(sender.send_all(self).map(|_| ()).map_err(|_| ())));
     }
 }
 
-type OptionFileChunk = Option<(File, Chunk)>;
+/// Read the ranges of file chunk by chunk, `read_ahead` chunks are in flight at most.
 struct FileChunkStream {
-    inner: CpuFuture<OptionFileChunk, HyperError>,
-    pool: CpuPool,
-    chunk_size: usize,
+    file: Arc<File>,
+    mmap: Option<Arc<Mmap>>,
+    uring: Option<Arc<Uring>>,
+    snapshot: Snapshot,
+    // not being scheduled
+    parts: VecDeque<Part>,
+    pending: VecDeque<Blocking<Chunk, HyperError>>,
+    executor: Arc<dyn BlockingExecutor>,
+    chunk_size: ChunkSizer,
+    read_ahead: usize,
     sender: Option<Sender<Result<Chunk, HyperError>>>,
 }
+
+// the ranges of the file and the bytes between them
+enum Part {
+    Range(u64, u64),
+    Bytes(Bytes),
+}
+
 impl FileChunkStream {
-    fn new(pool: &CpuPool, sender: Sender<Result<Chunk, HyperError>>, file: File, chunk_size: usize) -> Self {
-        let chunk = pool.spawn_fn(move || read_a_chunk(file, chunk_size));
-        FileChunkStream {
-            inner: chunk,
-            chunk_size: chunk_size,
-            pool: pool.clone(),
+    fn new(
+        executor: &Arc<dyn BlockingExecutor>,
+        sender: Sender<Result<Chunk, HyperError>>,
+        file: File,
+        mmap: Option<Mmap>,
+        body: &FileBody,
+        config: &Config,
+    ) -> Self {
+        let ranges = match body.ranges() {
+            // 206
+            Some(ranges) => ranges.to_vec(),
+            // 200
+            None if body.size() > 0 => vec![(0, body.size() - 1)],
+            None => vec![],
+        };
+        // the delimiters of `multipart/byteranges` around the ranges
+        let mut parts = VecDeque::with_capacity(ranges.len() * 2 + 1);
+        let mut delimiters = body.delimiters().unwrap_or(&[]).iter().cloned();
+        for (first, last) in ranges {
+            parts.extend(delimiters.next().map(Part::Bytes));
+            parts.push_back(Part::Range(first, last));
+        }
+        parts.extend(delimiters.map(Part::Bytes));
+        advise_sequential(&file);
+        let chunk_size = ChunkSizer::new(config, block_size(&file));
+        let mut stream = FileChunkStream {
+            file: Arc::new(file),
+            mmap: mmap.map(Arc::new),
+            uring: config.io_uring.clone(),
+            snapshot: Snapshot::of(body),
+            parts,
+            pending: VecDeque::new(),
+            executor: executor.clone(),
+            chunk_size,
+            read_ahead: cmp::max(*config.get_read_ahead(), 1),
             sender: Some(sender),
+        };
+        stream.schedule();
+        stream
+    }
+    fn schedule(&mut self) {
+        while self.pending.len() < self.read_ahead {
+            let (offset, len) = match self.parts.pop_front() {
+                Some(Part::Range(first, last)) => {
+                    let len = self.chunk_size.next(first, last - first + 1);
+                    if first + len <= last {
+                        self.parts.push_front(Part::Range(first + len, last));
+                    }
+                    (first, len as usize)
+                }
+                Some(Part::Bytes(bytes)) => {
+                    self.pending.push_back(Blocking::done(Ok(Chunk::from(bytes))));
+                    continue;
+                }
+                None => break,
+            };
+            let (file, snapshot) = (self.file.clone(), self.snapshot);
+            let chunk = match (self.mmap.clone(), self.uring.as_ref()) {
+                // read it if the file is not the one mapped
+                (Some(mmap), _) => spawn_fn(&*self.executor, move || {
+                    let chunk = match mmap.chunk(&file, offset, len) {
+                        Some(chunk) => chunk,
+                        None => read_a_chunk(&file, offset, len)?,
+                    };
+                    snapshot.verify(&file)?;
+                    Ok(chunk)
+                }),
+                (None, Some(uring)) => uring.read(file, offset, len, snapshot),
+                (None, None) => spawn_fn(&*self.executor, move || {
+                    let chunk = read_a_chunk(&file, offset, len)?;
+                    snapshot.verify(&file)?;
+                    Ok(chunk)
+                }),
+            };
+            self.pending.push_back(chunk);
         }
     }
 }
+
 impl Stream for FileChunkStream {
     type Item = Result<Chunk, HyperError>;
     type Error = SendError<Self::Item>;
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        match self.inner.poll() {
-            Ok(Async::Ready(Some((file, chunk)))) => {
-                let chunk_size = self.chunk_size;
-                let new_chunk = self.pool.spawn_fn(move || read_a_chunk(file, chunk_size));
-                self.inner = new_chunk;
+        let chunk = match self.pending.front_mut() {
+            Some(chunk) => chunk.poll(),
+            None => return Ok(Async::Ready(None)),
+        };
+        match chunk {
+            Ok(Async::Ready(chunk)) => {
+                self.pending.pop_front();
+                self.schedule();
                 Ok(Async::Ready(Some(Ok(chunk))))
             }
             Ok(Async::NotReady) => Ok(Async::NotReady),
-            Ok(Async::Ready(None)) => Ok(Async::Ready(None)),
-            Err(e) => Ok(Async::Ready(Some(Err(e)))),
+            // end the body short of `Content-Length`, hyper closes the connection then,
+            // the client sees a incomplete body instead of a wrong one(the `Err` panics tokio-proto).
+            Err(e) => {
+                warn!("abort the body of {:?}: {}", self.file, e);
+                self.parts.clear();
+                self.pending.clear();
+                Ok(Async::Ready(None))
+            }
         }
     }
 }
 
-fn read_a_chunk(mut file: File, chunk_size: usize) -> Result<OptionFileChunk, HyperError> {
-    let mut buf = BytesMut::with_capacity(chunk_size);
-    match file.read(unsafe { buf.bytes_mut() }) {
-        Ok(0) => Ok(None),
-        Ok(len) => {
-            unsafe {
-                buf.advance_mut(len);
+/// The size of the next chunk: `chunk_size`, or growing from a filesystem block to `chunk_size` if `adaptive_chunk_size`.
+#[derive(Debug)]
+struct ChunkSizer {
+    block: u64,
+    max: u64,
+    next: u64,
+    adaptive: bool,
+}
+
+impl ChunkSizer {
+    fn new(config: &Config, block: u64) -> Self {
+        let max = cmp::max(*config.get_chunk_size(), 1) as u64;
+        if !config.get_adaptive_chunk_size() {
+            return ChunkSizer {
+                block: 1,
+                max,
+                next: max,
+                adaptive: false,
+            };
+        }
+        let block = cmp::max(block, 512);
+        ChunkSizer {
+            block,
+            max: cmp::max(max / block * block, block),
+            next: block,
+            adaptive: true,
+        }
+    }
+    // the length of the chunk at `offset`, at most `remain`
+    fn next(&mut self, offset: u64, remain: u64) -> u64 {
+        let mut len = self.next;
+        if self.adaptive {
+            // end at a block boundary, the next reads are aligned
+            let end = (offset + len) / self.block * self.block;
+            if end > offset {
+                len = end - offset;
             }
-            let chunk = Chunk::from(buf.freeze());
-            Ok(Some((file, chunk)))
+            self.next = cmp::min(self.next * 2, self.max);
         }
-        Err(e) => Err(HyperError::Io(e)),
+        cmp::min(len, remain)
     }
 }
 
-impl SendAll for FileRangeChunkStream {
-    fn send_all(mut self, handle: &Handle) {
-        let sender = mem::replace(&mut self.sender, None).unwrap();
-        handle.spawn(sender.send_all(self).map(|_| ()).map_err(|_| ()));
+fn read_a_chunk(file: &File, offset: u64, len: usize) -> Result<Chunk, HyperError> {
+    let mut buf = vec![0; len];
+    let mut count = 0;
+    while count < len {
+        match read_at(file, &mut buf[count..], offset + count as u64) {
+            Ok(0) => break,
+            Ok(n) => count += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(HyperError::Io(e)),
+        }
+    }
+    // `Content-Length` is promised already
+    if count < len {
+        return Err(file_changed("file shrank while sending"));
     }
+    Ok(Chunk::from(buf))
 }
 
-type OptionFileRangeChunk = Option<(File, Vec<(u64, u64)>, Chunk)>;
-
-struct FileRangeChunkStream {
-    inner: CpuFuture<OptionFileRangeChunk, HyperError>,
-    pool: CpuPool,
-    chunk_size: usize,
-    sender: Option<Sender<Result<Chunk, HyperError>>>,
+/// The size and mtime of the file when the `Response` is generated, the body is aborted if they change.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Snapshot {
+    size: u64,
+    modified: SystemTime,
 }
 
-impl FileRangeChunkStream {
-    fn new(pool: &CpuPool, sender: Sender<Result<Chunk, HyperError>>, file: File, ranges: Vec<(u64, u64)>, chunk_size: usize) -> Self {
-        let chunk = pool.spawn_fn(move || read_a_range_chunk(file, ranges, chunk_size));
-        FileRangeChunkStream {
-            inner: chunk,
-            chunk_size: chunk_size,
-            pool: pool.clone(),
-            sender: Some(sender),
+impl Snapshot {
+    fn of(body: &FileBody) -> Self {
+        Snapshot {
+            size: body.size(),
+            modified: body.modified(),
         }
     }
-}
-impl Stream for FileRangeChunkStream {
-    type Item = Result<Chunk, HyperError>;
-    type Error = SendError<Self::Item>;
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        match self.inner.poll() {
-            Ok(Async::Ready(Some((file, ranges, chunk)))) => {
-                let chunk_size = self.chunk_size;
-                let new_chunk = self.pool
-                    .spawn_fn(move || read_a_range_chunk(file, ranges, chunk_size));
-                self.inner = new_chunk;
-                Ok(Async::Ready(Some(Ok(chunk))))
-            }
-            Ok(Async::NotReady) => Ok(Async::NotReady),
-            Ok(Async::Ready(None)) => Ok(Async::Ready(None)),
-            Err(e) => Ok(Async::Ready(Some(Err(e)))),
+    pub(crate) fn verify(&self, file: &File) -> Result<(), HyperError> {
+        let md = file.metadata().map_err(HyperError::Io)?;
+        if md.len() != self.size || md.modified().ok() != Some(self.modified) {
+            return Err(file_changed("file changed while sending"));
         }
+        Ok(())
     }
 }
-fn read_a_range_chunk(mut file: File, mut ranges: Vec<(u64, u64)>, chunk_size: usize) -> Result<OptionFileRangeChunk, HyperError> {
-    if ranges.is_empty() {
-        return Ok(None);
-    }
-    let range_size = (ranges[0].1 - ranges[0].0 + 1) as usize;
-    let cap = if range_size <= chunk_size {
-        range_size
-    } else {
-        chunk_size
-    };
-    let mut buf = BytesMut::with_capacity(cap);
-    let mut count = 0;
 
-    while count < chunk_size {
-        let start = ranges[0].0;
-        let range_size = (ranges[0].1 - ranges[0].0 + 1) as usize;
-        let reserve_size = if range_size <= chunk_size {
-            ranges.remove(0);
-            range_size
-        } else {
-            ranges[0].0 += chunk_size as u64;
-            chunk_size
-        };
-        buf.reserve(reserve_size);
-        match file.seek(SeekFrom::Start(start)) {
-            Ok(s) => debug_assert_eq!(s, start),
-            Err(e) => return Err(HyperError::Io(e)),
+pub(crate) fn file_changed(msg: &str) -> HyperError {
+    HyperError::Io(io::Error::new(io::ErrorKind::UnexpectedEof, msg))
+}
+
+cfg_if! {
+    if #[cfg(unix)] {
+        fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            use std::os::unix::fs::FileExt;
+            file.read_at(buf, offset)
         }
-        match file.read(unsafe { buf.bytes_mut() }) {
-            Ok(len) => {
-                // ?
-                if len < reserve_size {
-                    unreachable!()
-                }
-                unsafe {
-                    buf.advance_mut(reserve_size);
-                }
-                if ranges.is_empty() {
-                    break;
-                }
-                count += reserve_size;
+        fn block_size(file: &File) -> u64 {
+            use std::os::unix::fs::MetadataExt;
+            file.metadata().map(|md| md.blksize()).unwrap_or(4096)
+        }
+    } else if #[cfg(windows)] {
+        fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            use std::os::windows::fs::FileExt;
+            file.seek_read(buf, offset)
+        }
+        fn block_size(_file: &File) -> u64 {
+            4096
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(target_os = "linux")] {
+        // hint the kernel to read ahead more aggressively
+        fn advise_sequential(file: &File) {
+            use std::os::unix::io::AsRawFd;
+            unsafe {
+                ::libc::posix_fadvise(file.as_raw_fd(), 0, 0, ::libc::POSIX_FADV_SEQUENTIAL);
             }
-            Err(e) => return Err(HyperError::Io(e)),
         }
+    } else {
+        fn advise_sequential(_file: &File) {}
     }
-    let chunk = Chunk::from(buf.freeze());
-    Ok(Some((file, ranges, chunk)))
+}
+
+#[test]
+fn chunk_sizer_test() {
+    let config = Config::new().chunk_size(20_000).adaptive_chunk_size(true);
+    let mut sizer = ChunkSizer::new(&config, 4096);
+    let lens = (0..5).fold((vec![], 100), |(mut lens, offset), _| {
+        let len = sizer.next(offset, 1_000_000);
+        lens.push(len);
+        (lens, offset + len)
+    });
+    // 16384 = 20000 / 4096 * 4096
+    assert_eq!(lens.0, vec![3996, 8192, 16384, 16384, 16384]);
+    assert_eq!(sizer.next(0, 10), 10);
+
+    let mut sizer = ChunkSizer::new(&Config::new().chunk_size(20_000), 4096);
+    assert_eq!(sizer.next(100, 1_000_000), 20_000);
+}
+
+#[test]
+fn read_a_chunk_test() {
+    let file = File::open("Cargo.toml").unwrap();
+    let size = file.metadata().unwrap().len();
+    assert_eq!(read_a_chunk(&file, 0, size as usize).unwrap().len() as u64, size);
+    assert!(read_a_chunk(&file, 1, size as usize).is_err());
 }
@@ -1,25 +1,36 @@
 use url::percent_encoding::percent_decode;
-use tokio_core::reactor::Handle;
-use hyper::server::{Request};
-use hyper::{header, Method};
-use futures_cpupool::CpuPool;
-use futures::{future};
+use url::form_urlencoded;
+use hyper::server::{Request, Response, Service};
+use hyper::{header, Error as HyperError, Method};
+use futures::{future, Future};
 
-use super::{Config, Error, FutureObject};
+use super::{error_handler, Config, Error, FutureObject, HyperFutureObject};
+use admission::{ClassExecutor, WorkClass};
+use executor::{spawn_fn, BlockingExecutor, Spawner};
+use stat_cache;
+use upload;
+use ops;
+use trash;
+use webdav;
 use super::{StaticFile, StaticIndex};
 
 #[cfg(feature = "default")]
 use super::content_type_maker;
 
-use std::path::PathBuf;
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::io;
 
 /// Static File System
+///
+/// It is long-lived: `call` borrows it, and it is a `hyper::server::Service` with `error_handler` built in.
 // Todu: full test...
 pub struct StaticFs<C> {
     url: String,   // http's base path
     path: PathBuf, // Fs's base path
-    handle: Handle,
-    pool: CpuPool,
+    spawner: Arc<dyn Spawner>,
+    executor: Arc<dyn BlockingExecutor>,
     headers_file: Option<header::Headers>,
     headers_index: Option<header::Headers>,
     config: C,
@@ -29,7 +40,17 @@ impl<C> StaticFs<C>
 where
     C: AsRef<Config> + Clone + Send + 'static,
 {
-    pub fn new<U, P>(handle: Handle, pool: CpuPool, url: U, path: P, config: C) -> Self
+    /// `spawner` pumps the body(`Handle` usually), `executor` runs the blocking filesystem work(`CpuPool` usually).
+    pub fn new<S, B, U, P>(spawner: S, executor: B, url: U, path: P, config: C) -> Self
+    where
+        S: Spawner,
+        B: BlockingExecutor,
+        U: Into<String>,
+        P: Into<PathBuf>,
+    {
+        Self::with_executors(Arc::new(spawner), Arc::new(executor), url, path, config)
+    }
+    pub(crate) fn with_executors<U, P>(spawner: Arc<dyn Spawner>, executor: Arc<dyn BlockingExecutor>, url: U, path: P, config: C) -> Self
     where
         U: Into<String>,
         P: Into<PathBuf>,
@@ -37,8 +58,8 @@ where
         Self {
             url: url.into(),
             path: path.into(),
-            handle: handle,
-            pool: pool,
+            spawner,
+            executor,
             config: config,
             headers_index: None,
             headers_file: None,
@@ -53,20 +74,37 @@ where
     pub fn headers_index_mut(&mut self) -> &mut Option<header::Headers> {
         &mut self.headers_index
     }
-    pub fn call(self, req: Request)-> FutureObject {
+    /// Remove the partial files of the resumable uploads not appended for `Config::upload_expiry_secs`,
+    /// they are removed from a directory when it is uploaded into, call it periodically to purge the others.
+    ///
+    /// It walks the whole root, run it on the blocking executor.
+    pub fn purge_uploads(&self) -> usize {
+        upload::purge(&self.path, self.config().get_upload_expiry_secs())
+    }
+    /// `Arc<StaticFs>` is a `Service` can be cloned for every connection cheaply.
+    pub fn into_service(self) -> Arc<Self> {
+        Arc::new(self)
+    }
+    pub fn call(&self, req: Request)-> FutureObject {
+        self.serve(req, self.headers_file.as_ref(), self.headers_index.as_ref())
+    }
+    // `call` with the init `Headers` of `Mounts` and `VirtualHosts`
+    pub(crate) fn serve(&self, req: Request, headers_file: Option<&header::Headers>, headers_index: Option<&header::Headers>) -> FutureObject {
         // method error
-        match *req.method() {
-            Method::Head | Method::Get => {}
-            _ => return Box::new(future::err((Error::Method, req))),
+        if !self.allowed(&req) {
+            return Box::new(future::err((Error::Method, req)));
         }
-        let req_path_dec = percent_decode(req.path().as_bytes())
-            .decode_utf8()
-            .unwrap()
-            .into_owned()
-            .to_owned();
+        if *req.method() == Method::Options {
+            return webdav::options(self.config(), req);
+        }
+        let req_path_dec = match percent_decode(req.path().as_bytes()).decode_utf8() {
+            Ok(path) => path.into_owned(),
+            Err(_) => return Box::new(future::err((Error::not_found(), req))),
+        };
         debug!("{}", req_path_dec);
 
-        let res_after_router = router(&req_path_dec, &self.url, &self.path);
+        // the path is `stat`ed on the executor later
+        let res_after_router = route(&req_path_dec, &self.url, &self.path);
         debug!(
             "\nurl/path: {:?} -> {:?}\nreqRaw: {:?}\nreqDec_afterRouter: {:?}",
             self.url,
@@ -79,69 +117,292 @@ where
             Err(e) => return Box::new(future::err((e, req))),
         };
 
-        let metadata = if self.config().get_follow_links() {
-            fspath.metadata()
+        match *req.method() {
+            Method::Head | Method::Get => {}
+            Method::Post if self.archives(&req) => {}
+            _ => return self.call_method(req_path, fspath, req),
+        }
+        // the items deleted under the directory, they are as writable as the tree
+        if let Some(trash) = self.config().get_trash().cloned() {
+            if self.config().get_write() && query(&req, "trash").is_some() {
+                return match self.executor(WorkClass::Index) {
+                    Ok(executor) => trash::list(executor, trash, self.path.clone(), req_path, req),
+                    Err(e) => Box::new(future::err((e, req))),
+                };
+            }
+        }
+        let responder = Responder {
+            spawner: self.spawner.clone(),
+            executor: self.executor.clone(),
+            config: self.config.clone(),
+            headers_file: headers_file.cloned(),
+            headers_index: headers_index.cloned(),
+        };
+        let follow_links = self.config().get_follow_links();
+        // the progress of a resumable upload, or the metadata as usual
+        if *req.method() == Method::Head && self.config().get_write() {
+            let executor = match self.executor(WorkClass::File) {
+                Ok(executor) => executor,
+                Err(e) => return Box::new(future::err((e, req))),
+            };
+            let (config, path) = (self.config.clone(), fspath.clone());
+            let progress = spawn_fn(&*executor, move || match upload::offset(&path, config.as_ref()) {
+                Some(offset) => Ok(Err(offset)),
+                None => stat_cache::metadata(config.as_ref(), &path, follow_links).map(Ok),
+            });
+            return Box::new(progress.then(move |result| match result {
+                Ok(Err(offset)) => upload::progress(offset, req),
+                Ok(Ok(md)) => responder.call(Ok(md), req_path, fspath, req),
+                Err(e) => responder.call(Err(e), req_path, fspath, req),
+            }));
+        }
+        match (stat_cache::cached(self.config(), &fspath, follow_links), self.config().get_metadata_cache()) {
+            (Some(md), _) => responder.call(Ok(md), req_path, fspath, req),
+            // the miss of `MetadataCache` stats on the executor
+            (None, Some(_)) => {
+                let (config, path) = (self.config.clone(), fspath.clone());
+                let metadata = spawn_fn(&*self.executor, move || stat_cache::metadata(config.as_ref(), &path, follow_links));
+                Box::new(metadata.then(move |md| responder.call(md, req_path, fspath, req)))
+            }
+            (None, None) => responder.call(stat_cache::metadata(self.config(), &fspath, follow_links), req_path, fspath, req),
+        }
+    }
+    // the methods enabled by `Config::write`, `Config::webdav` and `Config::archive`
+    fn allowed(&self, req: &Request) -> bool {
+        let (write, webdav) = (self.config().get_write(), self.config().get_webdav().is_some());
+        match *req.method() {
+            Method::Head | Method::Get => true,
+            Method::Post => write || self.archives(req),
+            Method::Put | Method::Delete => write,
+            Method::Options => webdav,
+            Method::Extension(ref m) => match m.as_str() {
+                "MKCOL" | "MOVE" => write,
+                "PROPFIND" => webdav,
+                "PROPPATCH" | "COPY" | "LOCK" | "UNLOCK" => write && webdav,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+    // `?archive=` of a directory, `POST` it with the selected names
+    fn archives(&self, req: &Request) -> bool {
+        self.config().get_archive() && query(req, "archive").is_some()
+    }
+    // the methods other than GET and HEAD
+    fn call_method(&self, req_path: String, fspath: PathBuf, req: Request) -> FutureObject {
+        let method = req.method().to_string();
+        let class = if method == "PROPFIND" { WorkClass::Index } else { WorkClass::File };
+        let executor = match self.executor(class) {
+            Ok(executor) => executor,
+            Err(e) => return Box::new(future::err((e, req))),
+        };
+        let to = if method == "MOVE" || method == "COPY" {
+            match self.destination(&req) {
+                Ok(to) => Some(to),
+                Err(e) => return Box::new(future::err((e, req))),
+            }
         } else {
-            fspath.symlink_metadata()
+            None
         };
-        match metadata {
-            Ok(md) => {
-                let config = self.config.clone();
-                if md.is_file() {
-                    let mut file_server = StaticFile::new(
-                        self.handle.clone(),
-                        self.pool.clone(),
-                        fspath,
-                        config.as_ref().clone(),
-                    );
-                    if self.headers_file.is_some() {
-                        *file_server.headers_mut() = self.headers_file.clone();
-                    }
-                    file_server.headers_maker(content_type_maker);
-                    file_server.call(&self.pool, req)
-                } else if md.is_dir() {
-                    let mut index_server = StaticIndex::new(req_path, fspath, config.clone());
-                    if self.headers_index.is_some() {
-                        *index_server.headers_mut() = self.headers_index.clone();
-                    }
-                    index_server.call(&self.pool, req)
-                } else {
-                    Box::new(future::err((Error::Typo, req)))
-                }
+        // the root can not be removed
+        if fspath == self.path && (method == "DELETE" || method == "MOVE") {
+            return Box::new(future::err((Error::forbidden(), req)));
+        }
+        let writer = Writer {
+            url: self.url.clone(),
+            root: self.path.clone(),
+            executor: executor.clone(),
+            config: self.config.clone(),
+        };
+        // the target is `stat`ed on the executor, it exists unless it is created by the method
+        let creates = creates(req.method());
+        let (path, root) = (fspath.clone(), self.path.clone());
+        let target = spawn_fn(&*executor, move || match fs::metadata(&path) {
+            Ok(md) => Ok(Some(md)),
+            Err(_) if creates || path == root => Ok(None),
+            Err(e) => Err(Error::from(e)),
+        });
+        Box::new(target.then(move |target| match target {
+            Ok(target) => writer.call(target, method, req_path, fspath, to, req),
+            Err(e) => Box::new(future::err((e, req))),
+        }))
+    }
+    // the destination of MOVE and COPY under the root
+    fn destination(&self, req: &Request) -> Result<PathBuf, Error> {
+        match ops::destination(req).map(|dest| route(&dest, &self.url, &self.path)) {
+            Ok(Ok((_, ref to))) if *to == self.path => Err(Error::forbidden()),
+            Ok(Ok((_, to))) => Ok(to),
+            // out of the root
+            Ok(Err(Error::Route)) => Err(Error::forbidden()),
+            Ok(Err(e)) | Err(e) => Err(e),
+        }
+    }
+    fn executor(&self, class: WorkClass) -> Result<Arc<dyn BlockingExecutor>, Error> {
+        admit(self.config(), &self.executor, class)
+    }
+}
+
+// the parts of `StaticFs` run the other methods after the `stat` of the target
+struct Writer<C> {
+    url: String,
+    root: PathBuf,
+    executor: Arc<dyn BlockingExecutor>,
+    config: C,
+}
+
+impl<C> Writer<C>
+where
+    C: AsRef<Config> + Clone + Send + 'static,
+{
+    // `target` is the metadata of `fspath`, `None` if it does not exist
+    fn call(self, target: Option<Metadata>, method: String, req_path: String, fspath: PathBuf, to: Option<PathBuf>, req: Request) -> FutureObject {
+        let Writer { url, root, executor, config } = self;
+        let locks = config.as_ref().get_webdav().cloned();
+        if let Some(ref locks) = locks {
+            let check = match method.as_str() {
+                "PROPFIND" | "LOCK" | "UNLOCK" => Ok(()),
+                _ => webdav::check(locks, &req, &req_path, &fspath, target.as_ref(), to.as_ref()),
+            };
+            if let Err(e) = check {
+                return Box::new(future::err((e, req)));
             }
-            Err(e) => Box::new(future::err((e.into(), req))),
+        }
+        if let (Some(trash), Some(id)) = (config.as_ref().get_trash().cloned(), query(&req, "restore")) {
+            if method == "POST" {
+                return trash::restore(executor, trash, id, url, root, config, req);
+            }
+        }
+        let is_dir = target.as_ref().map(|md| md.is_dir()).unwrap_or(false);
+        let removed = fspath.clone();
+        let future = match method.as_str() {
+            "PUT" if req.path().ends_with('/') => Box::new(future::err((Error::Method, req))),
+            "PUT" => upload::put(executor, root, fspath, config, req),
+            "POST" if !is_dir => Box::new(future::err((Error::Method, req))),
+            "POST" => upload::post(executor, root, fspath, config, req),
+            "DELETE" => ops::delete(executor, root, req_path, fspath, config, req),
+            "MKCOL" => ops::mkcol(executor, root, fspath, config, req),
+            "MOVE" => ops::moves(executor, root, fspath, to.expect("MOVE without destination"), config, req),
+            "COPY" => webdav::copy(executor, root, fspath, to.expect("COPY without destination"), config, req),
+            "PROPFIND" => webdav::propfind(executor, req_path, fspath, config, req),
+            "PROPPATCH" => webdav::proppatch(executor, root, req_path, fspath, config, req),
+            "LOCK" => webdav::lock(executor, root, req_path, fspath, target, config, req),
+            _ => webdav::unlock(locks.as_ref().expect("UNLOCK without Config::webdav"), &fspath, req),
+        };
+        // the locks of the removed are released
+        match locks {
+            Some(locks) if method == "DELETE" || method == "MOVE" => Box::new(future.map(move |(res, req)| {
+                if res.status().is_success() {
+                    locks.remove_tree(&removed);
+                }
+                (res, req)
+            })),
+            _ => future,
         }
     }
 }
 
-pub fn router(req_path_dec: &str, base: &str, path: &PathBuf) -> Result<(String, PathBuf), Error> {
-    let components_raw = req_path_dec
-        .split('/')
-        .filter(|c| !c.is_empty() && c != &".");
+// the parts of `StaticFs` serve the file or index after its `stat`
+struct Responder<C> {
+    spawner: Arc<dyn Spawner>,
+    executor: Arc<dyn BlockingExecutor>,
+    config: C,
+    headers_file: Option<header::Headers>,
+    headers_index: Option<header::Headers>,
+}
 
-    let components =match components_raw.fold(Ok(vec![]), |cs, c| 
-        if cs.is_ok() {
-            let mut cs = cs.unwrap();
-            match (cs.len()>0, c == "..") {
-                (_, false)=> {
-                    cs.push(c);
-                    Ok(cs)
-                },
-                (true, true)=>{
-                    cs.pop();
-                    Ok(cs)
-                },
-                (false, true)=> {
-                    Err(cs)
-                },
+impl<C> Responder<C>
+where
+    C: AsRef<Config> + Clone + Send + 'static,
+{
+    fn call(self, metadata: io::Result<Metadata>, req_path: String, fspath: PathBuf, req: Request) -> FutureObject {
+        let md = match metadata {
+            Ok(md) => md,
+            Err(e) => return Box::new(future::err((e.into(), req))),
+        };
+        let archive = md.is_dir() && self.config.as_ref().get_archive() && query(&req, "archive").is_some();
+        let class = if md.is_file() || archive { WorkClass::File } else { WorkClass::Index };
+        let executor = match admit(self.config.as_ref(), &self.executor, class) {
+            Ok(executor) => executor,
+            Err(e) => return Box::new(future::err((e, req))),
+        };
+        if md.is_file() {
+            let mut file_server = StaticFile::with_executors(self.spawner, executor.clone(), fspath, self.config);
+            if self.headers_file.is_some() {
+                *file_server.headers_mut() = self.headers_file;
+            }
+            file_server.headers_maker(content_type_maker);
+            file_server.call(&*executor, req)
+        } else if md.is_dir() {
+            let mut index_server = StaticIndex::new(req_path, fspath, self.config);
+            if self.headers_index.is_some() {
+                *index_server.headers_mut() = self.headers_index;
             }
+            if archive {
+                index_server.archive(self.spawner, executor.clone());
+            }
+            index_server.call(&*executor, req)
         } else {
-          cs
+            Box::new(future::err((Error::Typo, req)))
         }
-     ) {
-         Ok(o)=> o,
-         Err(e)=> e,
-     };
+    }
+}
+
+// the executor of the `class` under `Config::admission`
+fn admit(config: &Config, executor: &Arc<dyn BlockingExecutor>, class: WorkClass) -> Result<Arc<dyn BlockingExecutor>, Error> {
+    match config.get_admission() {
+        Some(admission) if !admission.admit(class) => Err(Error::Busy(admission.get_retry_after())),
+        Some(admission) => Ok(Arc::new(ClassExecutor::new(admission.clone(), executor.clone(), class))),
+        None => Ok(executor.clone()),
+    }
+}
+
+impl<C> Service for StaticFs<C>
+where
+    C: AsRef<Config> + Clone + Send + 'static,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = HyperError;
+    type Future = HyperFutureObject;
+    fn call(&self, req: Request) -> Self::Future {
+        Box::new(
+            StaticFs::call(self, req)
+                .or_else(error_handler)
+                .map(|res_req| res_req.0),
+        )
+    }
+}
+
+// the decoded value of the query parameter `name`, `Some("")` if it has no value
+pub(crate) fn query(req: &Request, name: &str) -> Option<String> {
+    req.query().and_then(|query| {
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.into_owned())
+    })
+}
+
+// the methods create the path, it does not exist before
+fn creates(method: &Method) -> bool {
+    match *method {
+        Method::Put => true,
+        Method::Extension(ref m) => m == "MKCOL" || m == "LOCK",
+        _ => false,
+    }
+}
+
+pub fn router(req_path_dec: &str, base: &str, path: &PathBuf) -> Result<(String, PathBuf), Error> {
+    let (req_path, out) = route(req_path_dec, base, path)?;
+    if out == *path || out.exists() {
+        Ok((req_path, out))
+    } else {
+        Err(Error::not_found())
+    }
+}
+
+/// `router` without checking whether the path exists, for the writes.
+pub(crate) fn route(req_path_dec: &str, base: &str, path: &Path) -> Result<(String, PathBuf), Error> {
+    let components = components(req_path_dec);
     debug!("{} -> {:?}", req_path_dec, components);
 
     let req_path = || {
@@ -167,21 +428,64 @@ pub fn router(req_path_dec: &str, base: &str, path: &PathBuf) -> Result<(String,
                 }
             }
             (Some(c), None) => {
-                let mut out = path.clone();
+                let mut out = path.to_path_buf();
                 out.push(c);
                 components2.for_each(|cc| out.push(cc));
-                if out.exists() {
-                    return Ok((req_path(), out));
-                } else {
-                    return Err(Error::not_found());
-                }
+                return Ok((req_path(), out));
             }
-            (None, None) => return Ok((req_path(), path.clone())),
+            (None, None) => return Ok((req_path(), path.to_path_buf())),
             (None, Some(_)) => return Err(Error::Route),
         }
     }
 }
 
+/// Check the target `path` of a write under `root`(blocking): its parent must be under the root after the symlinks resolved,
+/// and it has no hidden component if `hide_entry`.
+pub(crate) fn confine(root: &Path, path: &Path, hide_entry: bool) -> Result<(), Error> {
+    let relative = path.strip_prefix(root).map_err(|_| Error::forbidden())?;
+    if hide_entry && relative.iter().any(|c| c.to_string_lossy().starts_with('.')) {
+        return Err(Error::forbidden());
+    }
+    if relative.as_os_str().is_empty() {
+        return Ok(());
+    }
+    let root = fs::canonicalize(root)?;
+    // the nearest existing ancestor, the missing parent fails the write later
+    let mut dir = path.parent();
+    while let Some(parent) = dir {
+        match fs::canonicalize(parent) {
+            Ok(ref parent) if parent.starts_with(&root) => return Ok(()),
+            Ok(_) => break,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => dir = parent.parent(),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Err(Error::forbidden())
+}
+
+/// split the decoded path into components, `.` is ignored and `..` can not escape the root.
+pub(crate) fn components(req_path_dec: &str) -> Vec<&str> {
+    let mut components_raw = req_path_dec
+        .split('/')
+        .filter(|c| !c.is_empty() && c != &".");
+
+    // `Err` at the `..` out of the root
+    match components_raw.try_fold(vec![], |mut cs, c| match (!cs.is_empty(), c == "..") {
+        (_, false) => {
+            cs.push(c);
+            Ok(cs)
+        }
+        (true, true) => {
+            cs.pop();
+            Ok(cs)
+        }
+        (false, true) => Err(cs),
+    }) {
+        Ok(o) => o,
+        Err(e) => e,
+    }
+}
+
 #[test]
 fn router_test() {
     use std::path::Path;
@@ -1,16 +1,19 @@
-use hyper::{header, Method, StatusCode};
+use hyper::{header, Method};
 use hyper::server::{Request, Response};
 
 use walkdir::{DirEntry, WalkDir};
 use url::percent_encoding::percent_encode_byte;
-use futures_cpupool::{CpuFuture, CpuPool};
-use futures::{Future, Poll};
+use futures::{future, Future, Poll};
 
 use super::{Config, Error, FutureObject};
+use archive::{self, Archive, Format};
+use executor::{spawn_request, Blocking, BlockingExecutor, Spawner};
+use http_core::{self, Body};
+use static_fs::query;
 
 use std::path::PathBuf;
-use std::{mem, time};
-use std::fs;
+use std::sync::Arc;
+use std::mem;
 use std::io;
 
 struct Inner<C> {
@@ -33,7 +36,8 @@ where
 /// Static Index: Simple html list the name of every entry for a index
 pub struct StaticIndex<C> {
     inner: Option<Inner<C>>,
-    content: Option<CpuFuture<(Response, Request), (Error, Request)>>,
+    content: Option<Blocking<(Response, Request), (Error, Request)>>,
+    archive: Option<(Arc<dyn Spawner>, Arc<dyn BlockingExecutor>)>,
 }
 
 impl<C> StaticIndex<C>
@@ -50,6 +54,7 @@ where
         Self {
             inner: Some(inner),
             content: None,
+            archive: None,
         }
     }
     /// You can set the init `Haeders`
@@ -58,9 +63,20 @@ where
     pub fn headers_mut(&mut self) -> &mut Option<header::Headers> {
         &mut self.inner.as_mut().unwrap().headers
     }
-    pub fn call(mut self, pool: &CpuPool, req: Request) -> FutureObject {
+    /// Serve `?archive=` if `Config::archive`, `spawner` pumps the body and `executor` reads the files.
+    pub fn archive(&mut self, spawner: Arc<dyn Spawner>, executor: Arc<dyn BlockingExecutor>) {
+        self.archive = Some((spawner, executor));
+    }
+    pub fn call<B: BlockingExecutor + ?Sized>(mut self, executor: &B, req: Request) -> FutureObject {
         let mut inner = mem::replace(&mut self.inner, None).expect("Call twice");
-        self.content = Some(pool.spawn_fn(move || inner.call(req)));
+        if let Some((spawner, archive_executor)) = self.archive.take() {
+            match inner.archive_format(&req) {
+                Ok(Some(format)) => return inner.archive(spawner, archive_executor, format, req),
+                Ok(None) => {}
+                Err(e) => return Box::new(future::err((e, req))),
+            }
+        }
+        self.content = Some(spawn_request(executor, req, move |req| inner.call(req)));
         Box::new(self)
     }
 }
@@ -82,118 +98,70 @@ where
     C: AsRef<Config>,
 {
     fn call(&mut self, req: Request) -> Result<(Response, Request), (Error, Request)> {
-        let mut headers = mem::replace(&mut self.headers, None).unwrap_or_else(header::Headers::new);
-        if *self.config().get_cache_secs() != 0 {
-            headers.set(header::CacheControl(vec![
-                header::CacheDirective::Public,
-                header::CacheDirective::MaxAge(*self.config().get_cache_secs()),
-            ]));
+        let res = match http_core::index(&http_core::from_hyper(&req), &self.title, &self.path, self.config()) {
+            Ok(res) => res,
+            Err(e) => return Err((e, req)),
+        };
+        let (mut res, body) = http_core::into_hyper(res, self.headers.take());
+
+        // response body
+        if let Body::Bytes(html) = body {
+            res.set_body(html);
         }
-        // method error
+        Ok((res, req))
+    }
+    // `?archive=` of the listed directory, `POST` the names for the selected entries
+    fn archive_format(&self, req: &Request) -> Result<Option<Format>, Error> {
+        let config = self.config();
         match *req.method() {
-            Method::Head | Method::Get => {}
-            _ => return Err((Error::Method, req)),
-        }
-        // 301
-        if !req.path().ends_with('/') {
-            let mut new_path = req.path().to_owned();
-            new_path.push('/');
-            if let Some(query) = req.query() {
-                new_path.push('?');
-                new_path.push_str(query);
-            }
-            headers.set(header::Location::new(new_path));
-            return Ok((
-                Response::new()
-                    .with_status(StatusCode::MovedPermanently)
-                    .with_headers(headers),
-                req,
-            ));
-        }
-        if !self.config().get_show_index() {
-            match fs::read_dir(&self.path) {
-                Ok(_) => {
-                    return Ok((Response::new().with_headers(headers), req));
-                }
-                Err(e) => {
-                    return Err((e.into(), req));
-                }
-            }
+            Method::Get | Method::Head | Method::Post if config.get_archive() && config.get_show_index() => {}
+            _ => return Ok(None),
         }
-        // HTTP Last-Modified
-        let metadata = match self.path.as_path().metadata() {
-            Ok(m) => m,
-            Err(e) => {
-                return Err((e.into(), req));
-            }
-        };
-        let last_modified = match metadata.modified() {
-            Ok(time) => time,
-            Err(e) => {
-                return Err((e.into(), req));
-            }
-        };
-        let delta_modified = last_modified
-            .duration_since(time::UNIX_EPOCH)
-            .expect("SystemTime::duration_since(UNIX_EPOCH) failed");
-        let http_last_modified = header::HttpDate::from(last_modified);
-        let etag = header::EntityTag::weak(format!(
-            "{:x}-{:x}.{:x}",
-            metadata.len(),
-            delta_modified.as_secs(),
-            delta_modified.subsec_nanos()
-        ));
-
-        let mut _304 = false;
-        if let Some(&header::IfNoneMatch::Items(ref etags)) = req.headers().get() {
-            if !etags.is_empty() && *self.config.as_ref().get_cache_secs() > 0 && etag == etags[0] {
-                _304 = true;
-            }
+        match query(req, "archive") {
+            Some(format) => Format::parse(&format).map(Some).ok_or(Error::Malformed),
+            None => Ok(None),
         }
-        if _304 {
-            return Ok((
-                Response::new()
-                    .with_headers(headers)
-                    .with_status(StatusCode::NotModified),
-                req,
-            ));
+    }
+    fn archive(self, spawner: Arc<dyn Spawner>, executor: Arc<dyn BlockingExecutor>, format: Format, mut req: Request) -> FutureObject {
+        let name = self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "archive".to_owned());
+        if *req.method() != Method::Post {
+            let archive = Archive::new(format, vec![(self.path.clone(), name.clone())], self.config());
+            return archive::respond(&*spawner, executor, archive, &name, self.headers, req);
         }
-
-        // io error
-        let html = match render_html(&self.title, &self.path, req.path(), self.config()) {
-            Ok(html) => html,
-            Err(e) => {
-                return Err((e.into(), req));
-            }
-        };
-
-        // response Header
-        headers.set(header::ContentLength(html.len() as u64));
-        headers.set(header::LastModified(http_last_modified));
-        headers.set(header::ETag(etag));
-        let mut res = Response::new().with_headers(headers);
-
-        // response body  stream
-        match *req.method() {
-            Method::Get => {
-                res.set_body(html);
+        let config = self.config().clone();
+        let (path, headers) = (self.path, self.headers);
+        let names = archive::read_names(&mut req);
+        Box::new(names.then(move |names| -> FutureObject {
+            match names.and_then(|names| archive::select(&path, names, &config)) {
+                Ok(roots) => {
+                    let archive = Archive::new(format, roots, &config);
+                    archive::respond(&*spawner, executor, archive, &name, headers, req)
+                }
+                Err(e) => Box::new(future::err((e, req))),
             }
-            Method::Head => {}
-            _ => unreachable!(),
-        }
-        Ok((res, req))
+        }))
     }
 }
 
-fn render_html(title: &str, index: &PathBuf, path: &str, config: &Config) -> io::Result<String> {
+pub(crate) fn render_html(title: &str, index: &PathBuf, path: &str, config: &Config) -> io::Result<String> {
+    let (title, path) = (escape(title), escape(path));
     let mut html = format!(
         "
 <!DOCTYPE HTML>
 <html><head><meta http-equiv=\"Content-Type\" content=\"text/html; charset=utf-8\">
 <title>Index listing for {}</title>
-</head><body><h1>Index listing for  <a href=\"{}../\">{}</a></h1><hr><ul>",
+</head><body><h1>Index listing for  <a href=\"{}../\">{}</a></h1><hr>",
         title, path, title
     );
+    // the entries can be selected for the zip
+    let select = config.get_archive();
+    if select {
+        html.push_str("<form method=\"post\" action=\"?archive=zip\">");
+    }
+    html.push_str("<ul>");
 
     let mut walker = WalkDir::new(index).min_depth(1).max_depth(1);
     if config.get_follow_links() {
@@ -201,19 +169,71 @@ fn render_html(title: &str, index: &PathBuf, path: &str, config: &Config) -> io:
     }
     if config.get_hide_entry() {
         for entry in walker.into_iter().filter_entry(|e| !is_hidden(e)) {
-            entries_render(&entry?, &mut html);
+            entries_render(&entry?, select, &mut html);
         }
     } else {
         for entry in walker {
-            entries_render(&entry?, &mut html);
+            entries_render(&entry?, select, &mut html);
         }
     }
-    html.push_str("</ul><hr></body></html>");
+    html.push_str("</ul>");
+    if select {
+        html.push_str(SELECT_SUBMIT);
+    }
+    html.push_str("<hr>");
+    if config.get_archive() {
+        html.push_str(ARCHIVE_LINKS);
+    }
+    if config.get_write() {
+        html.push_str(UPLOAD_FORM);
+    }
+    html.push_str("</body></html>");
     Ok(html)
 }
 
+// download the directory by `?archive=`
+const ARCHIVE_LINKS: &str = "<p>Download: <a href=\"?archive=zip\">zip</a> \
+<a href=\"?archive=tar.gz\">tar.gz</a> <a href=\"?archive=tar\">tar</a></p>";
+
+const SELECT_SUBMIT: &str = "<input type=\"submit\" value=\"Download selected(zip)\"></form>";
+
+// POST the files to the index, dropping the files on the page posts them too.
+const UPLOAD_FORM: &str = "<form method=\"post\" enctype=\"multipart/form-data\">\
+<input type=\"file\" name=\"file\" multiple required> <input type=\"submit\" value=\"Upload\"> \
+<small>or drop the files here</small></form>
+<script>
+document.addEventListener('dragover', function (e) { e.preventDefault(); });
+document.addEventListener('drop', function (e) {
+  e.preventDefault();
+  var form = new FormData();
+  for (var i = 0; i < e.dataTransfer.files.length; i++) { form.append('file', e.dataTransfer.files[i]); }
+  if (!e.dataTransfer.files.length) { return; }
+  document.body.style.opacity = 0.5;
+  fetch(location.pathname, { method: 'POST', body: form }).then(function (res) {
+    if (!res.ok) { alert('Upload failed: ' + res.status + ' ' + res.statusText); }
+    location.reload();
+  });
+});
+</script>";
+
+/// Escape the names in the html.
+pub(crate) fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
 #[inline]
-fn is_hidden(entry: &DirEntry) -> bool {
+pub(crate) fn is_hidden(entry: &DirEntry) -> bool {
     entry
         .file_name()
         .to_str()
@@ -221,13 +241,35 @@ fn is_hidden(entry: &DirEntry) -> bool {
         .unwrap_or(false)
 }
 #[inline]
-fn entries_render(entry: &DirEntry, html: &mut String) {
-    let mut name = entry.file_name().to_string_lossy().into_owned();
-    let mut name_dec = name.bytes().map(percent_encode_byte).collect::<String>();
+fn entries_render(entry: &DirEntry, select: bool, html: &mut String) {
+    let raw = entry.file_name().to_string_lossy();
+    let mut name = escape(&raw);
+    let mut name_dec = raw.bytes().map(percent_encode_byte).collect::<String>();
+    html.push_str("<li>");
+    if select {
+        html.push_str(&format!("<input type=\"checkbox\" name=\"name\" value=\"{}\"> ", name));
+    }
     if entry.file_type().is_dir() {
         name.push('/');
         name_dec.push('/');
     }
-    let li = format!("<li><a href=\"{}\">{}</a></li>", name_dec, name);
+    let li = format!("<a href=\"{}\">{}</a></li>", name_dec, name);
     html.push_str(&li);
 }
+
+#[test]
+fn render_html_test() {
+    use std::{env, fs, process};
+
+    let dir = env::temp_dir().join(format!("hyper-fs-index-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a<\"&'b"), "").unwrap();
+    let html = render_html("/<\"&/", &dir, "/<\"&/", &Config::new().archive(true)).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(html.contains("<title>Index listing for /&lt;&quot;&amp;/</title>"));
+    assert!(html.contains("<a href=\"/&lt;&quot;&amp;/../\">"));
+    assert!(html.contains("<a href=\"%61%3C%22%26%27%62\">a&lt;&quot;&amp;&#39;b</a>"));
+    assert!(html.contains("<input type=\"checkbox\" name=\"name\" value=\"a&lt;&quot;&amp;&#39;b\">"));
+    assert!(!html.contains("a<"));
+}
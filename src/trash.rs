@@ -0,0 +1,345 @@
+use futures::Future;
+use hyper::server::{Request, Response};
+use hyper::{header, StatusCode};
+
+use super::{Config, Error, FutureObject};
+use executor::{spawn_fn, BlockingExecutor};
+use date::rfc3339;
+use ops::copy_tree;
+use static_fs::{confine, route};
+use webdav::encode_href;
+use quota;
+use stat_cache;
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use std::{fmt, process};
+
+// the purges by `put` run at most once in it
+const PURGE_INTERVAL: u64 = 3600;
+
+/// A deleted file or directory in the `Trash`.
+#[derive(Debug, Clone)]
+pub struct TrashItem {
+    id: String,
+    root: PathBuf,
+    href: String,
+    deleted: SystemTime,
+}
+
+impl TrashItem {
+    /// The id to restore it(`POST ?restore=id`).
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+    /// The canonical root of the served directory it is deleted from.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+    /// The decoded URL path it is deleted from.
+    pub fn href(&self) -> &str {
+        &self.href
+    }
+    pub fn deleted(&self) -> SystemTime {
+        self.deleted
+    }
+}
+
+/// The trash of `DELETE`, set it by `Config::trash`.
+///
+/// The deleted are moved into the dated directories(`2018-01-31`) of `dir`, it should be out of the served tree
+/// and on the same filesystem(or they are copied).
+/// `GET ?trash` of a directory lists the items deleted under it from the same root, `POST ?restore=id` moves a item back
+/// if it is deleted from the root, both need `Config::write`, and the items older than `retention_secs` are removed by `purge`.
+pub struct Trash {
+    dir: PathBuf,
+    retention_secs: u64,
+    counter: AtomicUsize,
+    purged: Mutex<Option<Instant>>,
+}
+
+impl Trash {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+            retention_secs: 30 * 86_400,
+            counter: AtomicUsize::new(0),
+            purged: Mutex::new(None),
+        }
+    }
+    /// The seconds the items are kept, default is 2592000(30 days).
+    pub fn retention_secs(mut self, retention_secs: u64) -> Self {
+        self.retention_secs = retention_secs;
+        self
+    }
+    pub fn get_dir(&self) -> &Path {
+        &self.dir
+    }
+    pub fn get_retention_secs(&self) -> u64 {
+        self.retention_secs
+    }
+    /// The items, the latest first, it blocks.
+    pub fn items(&self) -> io::Result<Vec<TrashItem>> {
+        let days = match fs::read_dir(&self.dir) {
+            Ok(rd) => rd,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let mut items = vec![];
+        for day in days.filter_map(|e| e.ok()) {
+            for entry in fs::read_dir(day.path())?.filter_map(|e| e.ok()) {
+                let path = entry.path();
+                if path.extension().map(|e| e == "path").unwrap_or(false) {
+                    match read_item(&path) {
+                        Ok(ref item) if valid(&item.id) => items.push(item.clone()),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        items.sort_by(|a, b| b.deleted.cmp(&a.deleted).then_with(|| b.id.cmp(&a.id)));
+        Ok(items)
+    }
+    /// Remove the items older than `retention_secs`, return how many are removed,
+    /// they are purged at most hourly by the `DELETE`s, call it periodically to purge the others, it blocks.
+    pub fn purge(&self) -> usize {
+        let retention = Duration::from_secs(self.retention_secs);
+        let expired = self.items()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|item| item.deleted.elapsed().map(|age| age > retention).unwrap_or(false))
+            .filter(|item| {
+                let (path, info) = self.paths(&item.id);
+                remove(&path).and_then(|_| fs::remove_file(&info)).is_ok()
+            })
+            .count();
+        // the empty days
+        if let Ok(rd) = fs::read_dir(&self.dir) {
+            for day in rd.filter_map(|e| e.ok()) {
+                fs::remove_dir(day.path()).ok();
+            }
+        }
+        expired
+    }
+    // the item and its `.path` file
+    fn paths(&self, id: &str) -> (PathBuf, PathBuf) {
+        let day = self.dir.join(&id[..10]);
+        (day.join(id), day.join(format!("{}.path", id)))
+    }
+    /// The item of the `id`.
+    pub(crate) fn item(&self, id: &str) -> Result<TrashItem, Error> {
+        if !valid(id) {
+            return Err(Error::not_found());
+        }
+        read_item(&self.paths(id).1).map_err(Into::into)
+    }
+    /// Move the `path` deleted from `href` under the `root` into it, return the id.
+    pub(crate) fn put(&self, root: &Path, path: &Path, href: &str) -> Result<String, Error> {
+        let root = canonical(root)?;
+        self.purge_hourly();
+        let stamp = rfc3339(SystemTime::now());
+        let id = format!(
+            "{}_{}{}{}-{}-{}",
+            &stamp[..10],
+            &stamp[11..13],
+            &stamp[14..16],
+            &stamp[17..19],
+            process::id(),
+            self.counter.fetch_add(1, Ordering::Relaxed)
+        );
+        let (item, info) = self.paths(&id);
+        fs::create_dir_all(item.parent().expect("the day of trash"))?;
+        File::create(&info)?.write_all(format!("{}\n{}", root.display(), href).as_bytes())?;
+        if let Err(e) = move_tree(path, &item) {
+            fs::remove_file(&info).ok();
+            return Err(e.into());
+        }
+        Ok(id)
+    }
+    /// Move the `item` back to `to`, it must not exist.
+    pub(crate) fn restore(&self, item: &TrashItem, to: &Path) -> Result<(), Error> {
+        if fs::symlink_metadata(to).is_ok() {
+            return Err(Error::Conflict);
+        }
+        let (path, info) = self.paths(&item.id);
+        move_tree(&path, to).map_err(|e| match e.kind() {
+            // the parent directory does not exist
+            io::ErrorKind::NotFound if path.exists() => Error::Conflict,
+            _ => e.into(),
+        })?;
+        fs::remove_file(&info)?;
+        Ok(())
+    }
+    fn purge_hourly(&self) {
+        {
+            let mut purged = self.purged.lock().unwrap();
+            if purged.map(|t| t.elapsed() < Duration::from_secs(PURGE_INTERVAL)).unwrap_or(false) {
+                return;
+            }
+            *purged = Some(Instant::now());
+        }
+        self.purge();
+    }
+}
+
+impl fmt::Debug for Trash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Trash")
+            .field("dir", &self.dir)
+            .field("retention_secs", &self.retention_secs)
+            .finish()
+    }
+}
+
+// the ids made by `put`: `2018-01-31_235959-pid-n`
+fn valid(id: &str) -> bool {
+    id.len() > 11 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// the root is compared as the text it is saved as
+fn canonical(root: &Path) -> io::Result<PathBuf> {
+    fs::canonicalize(root).map(|root| PathBuf::from(root.to_string_lossy().into_owned()))
+}
+
+// the `.path` file: the root and the href after the first newline
+fn read_item(info: &Path) -> io::Result<TrashItem> {
+    let mut content = String::new();
+    let mut file = File::open(info)?;
+    file.read_to_string(&mut content)?;
+    let (root, href) = match content.find('\n') {
+        Some(n) => (PathBuf::from(&content[..n]), content[n + 1..].to_owned()),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "trash item without the root")),
+    };
+    let id = info.file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Ok(TrashItem {
+        id,
+        root,
+        href,
+        deleted: file.metadata()?.modified()?,
+    })
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(ref md) if md.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+// rename, or copy and remove if it is on the other filesystem
+fn move_tree(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(ref e) if e.kind() != io::ErrorKind::NotFound => {}
+        result => return result,
+    }
+    if let Err(e) = copy_tree(from, to, true, false) {
+        remove(to).ok();
+        return Err(e);
+    }
+    remove(from)
+}
+
+/// `GET ?trash` of the directory `href`, the items deleted under it from the `root` as the lines of `id`, the time and the path.
+#[allow(clippy::result_large_err)]
+pub(crate) fn list(executor: Arc<dyn BlockingExecutor>, trash: Arc<Trash>, root: PathBuf, href: String, req: Request) -> FutureObject {
+    let prefix = if href.ends_with('/') { href } else { format!("{}/", href) };
+    let list = spawn_fn(&*executor, move || {
+        let root = canonical(&root)?;
+        trash.items().map(|items| {
+            items
+                .into_iter()
+                .filter(|item| item.root == root && item.href.starts_with(&prefix))
+                .collect::<Vec<_>>()
+        })
+    });
+    Box::new(list.then(move |result| match result {
+        Ok(items) => {
+            let body = items
+                .iter()
+                .map(|item| format!("{}\t{}\t{}\n", item.id, rfc3339(item.deleted), item.href))
+                .collect::<String>();
+            let res = Response::new()
+                .with_header(header::ContentType::plaintext())
+                .with_header(header::CacheControl(vec![header::CacheDirective::NoStore]))
+                .with_header(header::ContentLength(body.len() as u64))
+                .with_body(body);
+            Ok((res, req))
+        }
+        Err(e) => Err((e.into(), req)),
+    }))
+}
+
+/// `POST ?restore=id`, move the item deleted from the `root` back to the path it is deleted from under the `url`.
+#[allow(clippy::result_large_err)]
+pub(crate) fn restore<C>(executor: Arc<dyn BlockingExecutor>, trash: Arc<Trash>, id: String, url: String, root: PathBuf, config: C, req: Request) -> FutureObject
+where
+    C: AsRef<Config> + Send + 'static,
+{
+    let quota = config.as_ref().get_quota().cloned();
+    let hide_entry = config.as_ref().get_hide_entry();
+    let restore = spawn_fn(&*executor, move || {
+        let item = trash.item(&id)?;
+        // the items of the other roots are not here
+        if item.root != canonical(&root)? {
+            return Err(Error::not_found());
+        }
+        let (_, to) = route(&item.href, &url, &root)?;
+        if to == root {
+            return Err(Error::Conflict);
+        }
+        confine(&root, &to, hide_entry)?;
+        trash.restore(&item, &to)?;
+        if let Some(quota) = quota {
+            quota.add(&to, None, quota::tree_size(&to), 0);
+        }
+        Ok((item.href, to))
+    });
+    Box::new(restore.then(move |result| match result {
+        Ok((href, to)) => {
+            debug!("restore {:?}", to);
+            stat_cache::changed(config.as_ref(), &to);
+            let res = Response::new()
+                .with_status(StatusCode::Created)
+                .with_header(header::Location::new(encode_href(&href)))
+                .with_header(header::ContentLength(0));
+            Ok((res, req))
+        }
+        // the item does not belong to the `url`
+        Err(Error::Route) => Err((Error::Conflict, req)),
+        Err(e) => Err((e, req)),
+    }))
+}
+
+#[test]
+fn trash_test() {
+    let dir = ::std::env::temp_dir().join(format!("hyper-fs-trash-{}", process::id()));
+    let trash = Trash::new(dir.join("trash")).retention_secs(0);
+    fs::create_dir_all(dir.join("tree/d")).unwrap();
+    File::create(dir.join("tree/d/f")).unwrap();
+
+    let id = trash.put(&dir.join("tree"), &dir.join("tree/d"), "/d").unwrap();
+    assert!(!dir.join("tree/d").exists());
+    let items = trash.items().unwrap();
+    assert_eq!((items.len(), items[0].id(), items[0].href()), (1, id.as_str(), "/d"));
+    assert_eq!(items[0].root(), canonical(&dir.join("tree")).unwrap());
+    assert!(trash.item("../../x").is_err());
+
+    let item = trash.item(&id).unwrap();
+    trash.restore(&item, &dir.join("tree/d")).unwrap();
+    assert!(dir.join("tree/d/f").exists());
+    assert!(trash.items().unwrap().is_empty());
+
+    trash.put(&dir.join("tree"), &dir.join("tree/d/f"), "/d/f").unwrap();
+    ::std::thread::sleep(Duration::from_millis(1100));
+    assert_eq!(trash.purge(), 1);
+    assert!(fs::read_dir(dir.join("trash")).unwrap().next().is_none());
+    fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,601 @@
+use futures::{Future, Stream};
+use hyper::server::{Request, Response};
+use hyper::{header, StatusCode};
+
+use super::{Config, Error, FutureObject};
+use executor::{spawn_fn, BlockingExecutor};
+use http_core::last_modified_etag;
+use multipart::{self, Event, Parser};
+use quota::Charge;
+use stat_cache;
+use static_fs::confine;
+
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::process;
+use std::time::Duration;
+
+use walkdir::WalkDir;
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The precondition of the writes.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Condition {
+    None,
+    // If-None-Match: *, create only
+    Create,
+    // If-Match: etags or *, overwrite only
+    Match(Vec<String>),
+}
+
+impl Condition {
+    pub(crate) fn from_request(req: &Request) -> Self {
+        let values = |name: &str| -> Option<Vec<String>> {
+            req.headers().get_raw(name).map(|raw| {
+                raw.iter()
+                    .filter_map(|line| ::std::str::from_utf8(line).ok())
+                    .flat_map(|line| line.split(','))
+                    .map(|v| v.trim().to_owned())
+                    .filter(|v| !v.is_empty())
+                    .collect()
+            })
+        };
+        if let Some(etags) = values("If-Match") {
+            return Condition::Match(etags);
+        }
+        match values("If-None-Match") {
+            Some(ref etags) if etags.iter().any(|e| e == "*") => Condition::Create,
+            _ => Condition::None,
+        }
+    }
+    /// Check the current file `path`, return whether it exists.
+    pub(crate) fn check(&self, path: &Path) -> Result<bool, Error> {
+        let metadata = match fs::metadata(path) {
+            Ok(md) => Some(md),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        if metadata.as_ref().map(|md| !md.is_file()).unwrap_or(false) {
+            return Err(Error::Conflict);
+        }
+        self.check_metadata(metadata.as_ref())
+    }
+    /// Check the `metadata` of the target(`None` if it does not exist), return whether it exists.
+    pub(crate) fn check_metadata(&self, metadata: Option<&Metadata>) -> Result<bool, Error> {
+        match (self, metadata) {
+            (Condition::Create, Some(_)) => Err(Error::Precondition),
+            (Condition::Match(_), None) => Err(Error::Precondition),
+            (Condition::Match(etags), Some(md)) => {
+                let (_, etag) = last_modified_etag(md)?;
+                // the ETags are weak, compare them weakly
+                if etags.iter().any(|e| e == "*" || weak(e) == weak(&etag)) {
+                    Ok(true)
+                } else {
+                    Err(Error::Precondition)
+                }
+            }
+            (_, md) => Ok(md.is_some()),
+        }
+    }
+}
+
+pub(crate) fn weak(etag: &str) -> &str {
+    etag.strip_prefix("W/").unwrap_or(etag)
+}
+
+/// The file being uploaded into a temp file beside the `path`, the temp file is removed when it is dropped.
+pub(crate) struct Upload {
+    path: PathBuf,
+    temp: PathBuf,
+    file: Option<File>,
+    size: u64,
+    max_size: u64,
+    condition: Condition,
+    // the end of the `Content-Range` and the partial file of a resumable upload
+    end: Option<u64>,
+    partial: Option<PathBuf>,
+    charge: Option<Charge>,
+    written: u64,
+}
+
+impl Upload {
+    /// Check the `condition` and create the temp file.
+    pub(crate) fn create(path: PathBuf, condition: Condition, max_size: u64) -> Result<Self, Error> {
+        condition.check(&path)?;
+        Self::new(path, condition, max_size)
+    }
+    fn new(path: PathBuf, condition: Condition, max_size: u64) -> Result<Self, Error> {
+        let temp = temp_path(&path)?;
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)
+            .map_err(|e| match e.kind() {
+                // the parent directory does not exist
+                io::ErrorKind::NotFound => Error::Conflict,
+                _ => e.into(),
+            })?;
+        Ok(Upload {
+            path,
+            temp,
+            file: Some(file),
+            size: 0,
+            max_size,
+            condition,
+            end: None,
+            partial: None,
+            charge: None,
+            written: 0,
+        })
+    }
+    /// Append the bytes `[start, end)` to the partial file of `path`, `start` must be the bytes received or 0 to restart.
+    ///
+    /// The partial file is renamed to a temp file while it is appended, so a concurrent append of it fails(`409`),
+    /// and it is renamed back with the bytes received when the upload is dropped.
+    pub(crate) fn resume(path: PathBuf, start: u64, end: u64, condition: Condition, config: &Config) -> Result<Self, Error> {
+        let partial = partial_path(&path).ok_or(Error::Conflict)?;
+        if let Some(dir) = path.parent() {
+            purge_dir(dir, config.get_upload_expiry_secs());
+        }
+        let mut upload = if start == 0 {
+            condition.check(&path)?;
+            fs::remove_file(&partial).ok();
+            Self::new(path, condition, config.get_max_upload_size())?
+        } else {
+            let temp = temp_path(&path)?;
+            fs::rename(&partial, &temp).map_err(|e| match e.kind() {
+                // not started, expired or being appended
+                io::ErrorKind::NotFound => Error::Conflict,
+                _ => e.into(),
+            })?;
+            let file = OpenOptions::new().append(true).open(&temp).ok();
+            let size = file.as_ref().and_then(|f| f.metadata().ok()).map(|md| md.len()).unwrap_or(0);
+            let upload = Upload {
+                path,
+                temp,
+                file,
+                size,
+                max_size: config.get_max_upload_size(),
+                condition,
+                end: None,
+                partial: Some(partial.clone()),
+                charge: None,
+                written: 0,
+            };
+            if upload.file.is_none() || size != start {
+                return Err(Error::Conflict);
+            }
+            upload
+        };
+        upload.end = Some(end);
+        upload.partial = Some(partial);
+        Ok(upload)
+    }
+    /// Charge the bytes written to the `Quota`.
+    pub(crate) fn charge(&mut self, charge: Charge) {
+        self.charge = Some(charge);
+    }
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+    pub(crate) fn write(&mut self, buf: &[u8]) -> Result<(), Error> {
+        self.size += buf.len() as u64;
+        if self.size > self.max_size {
+            return Err(Error::TooLarge);
+        }
+        // the body is longer than the `Content-Range`
+        if self.end.map(|end| self.size > end).unwrap_or(false) {
+            return Err(Error::Malformed);
+        }
+        self.written += buf.len() as u64;
+        if let Some(ref charge) = self.charge {
+            charge.check(self.written)?;
+        }
+        self.file.as_mut().expect("write after commit").write_all(buf)?;
+        Ok(())
+    }
+    /// Flush the temp file and replace the `path` with it atomically, return whether the `path` was created and the new ETag.
+    pub(crate) fn commit(mut self) -> Result<(bool, String), Error> {
+        self.partial = None;
+        self.file.take().expect("commit twice").sync_all()?;
+        let existed = self.condition.check(&self.path)?;
+        let replaced = if existed { fs::metadata(&self.path)?.len() } else { 0 };
+        if self.condition == Condition::Create {
+            // fails if the path is created by others meanwhile
+            fs::hard_link(&self.temp, &self.path).map_err(|e| match e.kind() {
+                io::ErrorKind::AlreadyExists => Error::Precondition,
+                _ => e.into(),
+            })?;
+        } else {
+            fs::rename(&self.temp, &self.path)?;
+        }
+        if let Some(ref charge) = self.charge {
+            charge.add(&self.path, self.size, replaced);
+        }
+        let (_, etag) = last_modified_etag(&fs::metadata(&self.path)?)?;
+        Ok((!existed, etag))
+    }
+    /// Flush the temp file and link it to the `path` or `name (n).ext` if it exists, return the file name.
+    pub(crate) fn commit_unique(mut self) -> Result<String, Error> {
+        self.file.take().expect("commit twice").sync_all()?;
+        let (stem, ext) = {
+            let name = self.path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            match name.rfind('.') {
+                Some(idx) if idx > 0 => (name[..idx].to_owned(), name[idx..].to_owned()),
+                _ => (name, String::new()),
+            }
+        };
+        for n in 0..1000 {
+            let name = if n == 0 {
+                format!("{}{}", stem, ext)
+            } else {
+                format!("{} ({}){}", stem, n, ext)
+            };
+            // the temp file is removed by `drop`
+            let path = self.path.with_file_name(&name);
+            match fs::hard_link(&self.temp, &path) {
+                Ok(_) => {
+                    if let Some(ref charge) = self.charge {
+                        charge.add(&path, self.size, 0);
+                    }
+                    return Ok(name);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(Error::Conflict)
+    }
+}
+
+impl Drop for Upload {
+    // the temp file is renamed or linked if it is committed, its name is never reused
+    fn drop(&mut self) {
+        self.file.take();
+        match self.partial.take() {
+            Some(partial) => fs::rename(&self.temp, &partial).ok(),
+            None => fs::remove_file(&self.temp).ok(),
+        };
+    }
+}
+
+fn temp_path(path: &Path) -> Result<PathBuf, Error> {
+    match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => Ok(dir.join(format!(
+            ".{}.{}-{}.upload",
+            name.to_string_lossy(),
+            process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ))),
+        _ => Err(Error::Conflict),
+    }
+}
+
+fn partial_path(path: &Path) -> Option<PathBuf> {
+    match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => Some(dir.join(format!(".{}.partial", name.to_string_lossy()))),
+        _ => None,
+    }
+}
+
+// the partial and temp files untouched for `expiry_secs`
+fn expired(path: &Path, metadata: &Metadata, expiry_secs: u32) -> bool {
+    let upload = path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.starts_with('.') && (name.ends_with(".partial") || name.ends_with(".upload")))
+        .unwrap_or(false);
+    upload && metadata.is_file() && metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .map(|age| age > Duration::from_secs(u64::from(expiry_secs)))
+        .unwrap_or(false)
+}
+
+fn purge_dir(dir: &Path, expiry_secs: u32) {
+    if let Ok(rd) = fs::read_dir(dir) {
+        for entry in rd.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if entry.metadata().map(|md| expired(&path, &md, expiry_secs)).unwrap_or(false) {
+                debug!("purge {:?}", path);
+                fs::remove_file(&path).ok();
+            }
+        }
+    }
+}
+
+/// Remove the expired partial files of the resumable uploads under `root`, return how many are removed.
+pub(crate) fn purge(root: &Path, expiry_secs: u32) -> usize {
+    WalkDir::new(root)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.metadata().map(|md| expired(e.path(), &md, expiry_secs)).unwrap_or(false))
+        .filter(|e| fs::remove_file(e.path()).is_ok())
+        .count()
+}
+
+/// The bytes received of the resumable upload to `path`, `None` if there is not one.
+pub(crate) fn offset(path: &Path, config: &Config) -> Option<u64> {
+    let partial = partial_path(path)?;
+    let metadata = fs::metadata(&partial).ok()?;
+    if expired(&partial, &metadata, config.get_upload_expiry_secs()) {
+        None
+    } else {
+        Some(metadata.len())
+    }
+}
+
+fn set_offset(res: &mut Response, offset: u64) {
+    res.headers_mut().set_raw("Upload-Offset", offset.to_string());
+    if offset > 0 {
+        res.headers_mut().set_raw("Range", format!("bytes=0-{}", offset - 1));
+    }
+    res.headers_mut().set(header::CacheControl(vec![header::CacheDirective::NoStore]));
+}
+
+/// The progress(`HEAD`) of the resumable upload.
+pub(crate) fn progress(offset: u64, req: Request) -> FutureObject {
+    let mut res = Response::new().with_status(StatusCode::NoContent);
+    set_offset(&mut res, offset);
+    Box::new(::futures::future::ok((res, req)))
+}
+
+// `Content-Range: bytes first-last/total` of `PUT` as `[first, last + 1)` and the total,
+// `bytes */total` with a empty body completes the upload of the chunks sent with `bytes first-last/*`
+fn content_range(req: &Request) -> Result<Option<(u64, u64, Option<u64>)>, Error> {
+    if req.headers().get_raw("Content-Range").is_none() {
+        return Ok(None);
+    }
+    match req.headers().get::<header::ContentRange>() {
+        Some(&header::ContentRange(header::ContentRangeSpec::Bytes {
+            range: Some((first, last)),
+            instance_length: total,
+        })) if total.map(|total| last < total).unwrap_or(true) =>
+        {
+            Ok(Some((first, last + 1, total)))
+        }
+        Some(&header::ContentRange(header::ContentRangeSpec::Bytes {
+            range: None,
+            instance_length: Some(total),
+        })) => Ok(Some((total, total, Some(total)))),
+        _ => Err(Error::Malformed),
+    }
+}
+
+/// `PUT` the body of `Request` to the `path`, or append it to the resumable upload if it has `Content-Range`,
+/// the upload is completed when the last byte of the total is received, else `202` with its `Upload-Offset`.
+///
+/// The upload is charged to the `Config::quota` of the `root`.
+#[allow(clippy::result_large_err)]
+pub(crate) fn put<C>(executor: Arc<dyn BlockingExecutor>, root: PathBuf, path: PathBuf, config: C, mut req: Request) -> FutureObject
+where
+    C: AsRef<Config> + Clone + Send + 'static,
+{
+    let max_size = config.as_ref().get_max_upload_size();
+    let range = match content_range(&req) {
+        Ok(range) => range,
+        Err(e) => return Box::new(::futures::future::err((e, req))),
+    };
+    let total = match range {
+        Some((_, _, total)) => total,
+        None => req.headers().get::<header::ContentLength>().map(|len| len.0),
+    };
+    if total.map(|total| total > max_size).unwrap_or(false) {
+        return Box::new(::futures::future::err((Error::TooLarge, req)));
+    }
+    let condition = Condition::from_request(&req);
+    let mut charge = Charge::new(config.as_ref(), &req);
+    // the bytes of this request
+    let incoming = match range {
+        Some((start, end, _)) => Some(end - start),
+        None => total,
+    };
+    let body = req.body_mut().take().unwrap_or_default();
+    let (executor2, executor3) = (executor.clone(), executor.clone());
+    let (path2, config2) = (path.clone(), config.clone());
+    let create = move || {
+        confine(&root, &path2, config2.as_ref().get_hide_entry())?;
+        charge.reserve(&root, path2.parent().unwrap_or(&root), incoming)?;
+        let mut upload = match range {
+            Some((start, end, _)) => Upload::resume(path2, start, end, condition, config2.as_ref())?,
+            None => Upload::create(path2, condition, max_size)?,
+        };
+        upload.charge(charge);
+        Ok(upload)
+    };
+    let upload = spawn_fn(&*executor, create)
+        .and_then(move |upload| {
+            body.map_err(|e| Error::Io(io::Error::other(e)))
+                .fold(upload, move |mut upload, chunk| {
+                    spawn_fn(&*executor2, move || upload.write(&chunk).map(|_| upload))
+                })
+        })
+        .and_then(move |upload| {
+            debug!("PUT {:?}: {} bytes", upload.path, upload.size());
+            spawn_fn(&*executor3, move || match range {
+                // the body is shorter than the `Content-Range`
+                Some((_, end, _)) if upload.size() != end => Err(Error::Malformed),
+                Some((_, end, total)) if total != Some(end) => Ok(Err(end)),
+                _ => upload.commit().map(Ok),
+            })
+        });
+    Box::new(upload.then(move |result| match result {
+        Ok(Ok((created, etag))) => {
+            stat_cache::changed(config.as_ref(), &path);
+            let mut res = Response::new().with_status(if created {
+                StatusCode::Created
+            } else {
+                StatusCode::NoContent
+            });
+            res.headers_mut().set_raw("ETag", etag);
+            if created {
+                res.headers_mut().set(header::ContentLength(0));
+            }
+            Ok((res, req))
+        }
+        Ok(Err(offset)) => {
+            let mut res = Response::new()
+                .with_status(StatusCode::Accepted)
+                .with_header(header::ContentLength(0));
+            set_offset(&mut res, offset);
+            Ok((res, req))
+        }
+        Err(e) => Err((e, req)),
+    }))
+}
+
+// the state of `POST`
+struct Form {
+    root: PathBuf,
+    hide_entry: bool,
+    dir: PathBuf,
+    parser: Parser,
+    upload: Option<Upload>,
+    size: u64,
+    max_size: u64,
+    charge: Charge,
+    saved: Vec<String>,
+}
+
+impl Form {
+    fn feed(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        self.size += chunk.len() as u64;
+        if self.size > self.max_size {
+            return Err(Error::TooLarge);
+        }
+        self.charge.check(self.size)?;
+        let Form {
+            ref root,
+            hide_entry,
+            ref dir,
+            ref mut parser,
+            ref mut upload,
+            max_size,
+            ref charge,
+            ref mut saved,
+            ..
+        } = *self;
+        parser.feed(chunk, |event| {
+            match event {
+                // the fields without file are ignored
+                Event::Begin(Some(filename)) => {
+                    *upload = match multipart::sanitize(&filename) {
+                        Some(name) => {
+                            let path = dir.join(name);
+                            confine(root, &path, hide_entry)?;
+                            let mut new = Upload::new(path, Condition::Create, max_size)?;
+                            new.charge(charge.clone());
+                            Some(new)
+                        }
+                        None => None,
+                    }
+                }
+                Event::Begin(None) => *upload = None,
+                Event::Data(data) => if let Some(upload) = upload.as_mut() {
+                    upload.write(data)?;
+                },
+                Event::End => if let Some(upload) = upload.take() {
+                    saved.push(upload.commit_unique()?);
+                },
+            }
+            Ok(())
+        })
+    }
+}
+
+/// `POST` the files of the `multipart/form-data` body into the directory `path`,
+/// the names are sanitized and `name (n).ext` is used if it exists, then it redirects(`303`) to the listing.
+#[allow(clippy::result_large_err)]
+pub(crate) fn post<C>(executor: Arc<dyn BlockingExecutor>, root: PathBuf, path: PathBuf, config: C, mut req: Request) -> FutureObject
+where
+    C: AsRef<Config> + Send + 'static,
+{
+    let max_size = config.as_ref().get_max_upload_size();
+    let boundary = match req.headers().get::<header::ContentType>() {
+        Some(header::ContentType(mime))
+            if mime.type_() == ::hyper::mime::MULTIPART && mime.subtype() == ::hyper::mime::FORM_DATA =>
+        {
+            mime.get_param(::hyper::mime::BOUNDARY).map(|b| b.as_str().to_owned())
+        }
+        _ => return Box::new(::futures::future::err((Error::MediaType, req))),
+    };
+    let boundary = match boundary {
+        Some(boundary) => boundary,
+        None => return Box::new(::futures::future::err((Error::Malformed, req))),
+    };
+    match req.headers().get::<header::ContentLength>() {
+        Some(&header::ContentLength(len)) if len > max_size => {
+            return Box::new(::futures::future::err((Error::TooLarge, req)))
+        }
+        _ => {}
+    }
+    let mut charge = Charge::new(config.as_ref(), &req);
+    let incoming = req.headers().get::<header::ContentLength>().map(|len| len.0);
+    let dir = path.clone();
+    let hide_entry = config.as_ref().get_hide_entry();
+    let create = move || {
+        charge.reserve(&root, &dir, incoming)?;
+        Ok(Form {
+            parser: Parser::new(&boundary),
+            root,
+            hide_entry,
+            dir,
+            upload: None,
+            size: 0,
+            max_size,
+            charge,
+            saved: vec![],
+        })
+    };
+    let executor2 = executor.clone();
+    let body = req.body_mut().take().unwrap_or_default();
+    let form = spawn_fn(&*executor, create)
+        .and_then(move |form| {
+            body.map_err(|e| Error::Io(io::Error::other(e)))
+                .fold(form, move |mut form, chunk| {
+                    spawn_fn(&*executor2, move || form.feed(&chunk).map(|_| form))
+                })
+        })
+        .and_then(|form| form.parser.finish().map(|_| form.saved));
+    Box::new(form.then(move |result| match result {
+        Ok(saved) => {
+            debug!("POST {:?}: {:?}", path, saved);
+            stat_cache::changed(config.as_ref(), &path);
+            let mut body = saved.join("\n");
+            body.push('\n');
+            let location = req.path().to_owned();
+            let res = Response::new()
+                .with_status(StatusCode::SeeOther)
+                .with_header(header::Location::new(location))
+                .with_header(header::ContentType::plaintext())
+                .with_header(header::ContentLength(body.len() as u64))
+                .with_body(body);
+            Ok((res, req))
+        }
+        Err(e) => Err((e, req)),
+    }))
+}
+
+#[test]
+fn condition_test() {
+    let mut req = Request::new(::hyper::Method::Put, "/a".parse().unwrap());
+    assert_eq!(Condition::from_request(&req), Condition::None);
+    req.headers_mut().set_raw("If-None-Match", "*");
+    assert_eq!(Condition::from_request(&req), Condition::Create);
+    req.headers_mut().set_raw("If-Match", "\"a\", W/\"b\"");
+    assert_eq!(
+        Condition::from_request(&req),
+        Condition::Match(vec!["\"a\"".to_owned(), "W/\"b\"".to_owned()])
+    );
+
+    let file = Path::new("tests/index/file");
+    let (_, etag) = last_modified_etag(&fs::metadata(file).unwrap()).unwrap();
+    assert_eq!(Condition::Create.check(file).ok(), None);
+    assert_eq!(Condition::Match(vec![etag]).check(file).ok(), Some(true));
+    assert_eq!(Condition::Match(vec!["*".to_owned()]).check(Path::new("tests/none")).ok(), None);
+    assert_eq!(Condition::Create.check(Path::new("tests/none")).ok(), Some(false));
+}
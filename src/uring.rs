@@ -0,0 +1,260 @@
+/*!
+io_uring backend for reading the chunks of files(the `io-uring` feature, Linux 5.6+).
+
+A thread owns the ring and submits the reads of all downloads, the results are delivered as `Blocking`s like `spawn_fn`.
+*/
+
+cfg_if! {
+    if #[cfg(all(target_os = "linux", feature = "io-uring"))] {
+        use io_uring::{opcode, types, IoUring};
+        use hyper::{Chunk, Error as HyperError};
+        use libc;
+
+        use executor::Blocking;
+        use static_file::{file_changed, Snapshot};
+
+        use std::collections::{HashMap, VecDeque};
+        use std::sync::{Arc, Mutex};
+        use std::os::unix::io::{AsRawFd, RawFd};
+        use std::fs::File;
+        use std::{fmt, io, thread};
+
+        // the read of the eventfd, it wakes the ring thread up for the new reads
+        const WAKER: u64 = u64::MAX;
+
+        struct Read {
+            file: Arc<File>,
+            buf: Vec<u8>,
+            filled: usize,
+            offset: u64,
+            snapshot: Snapshot,
+            tx: ::futures::sync::oneshot::Sender<Result<Chunk, HyperError>>,
+        }
+
+        struct Shared {
+            queue: Mutex<VecDeque<Read>>,
+            eventfd: RawFd,
+        }
+
+        impl Shared {
+            fn wake(&self) {
+                let one = 1u64;
+                unsafe { libc::write(self.eventfd, &one as *const u64 as *const libc::c_void, 8) };
+            }
+        }
+
+        impl Drop for Shared {
+            fn drop(&mut self) {
+                unsafe { libc::close(self.eventfd) };
+            }
+        }
+
+        /// The shared io_uring instance, set it by `Config::io_uring`.
+        ///
+        /// The reads fall back to the `BlockingExecutor` if the kernel does not support io_uring(`Uring::new` fails).
+        pub struct Uring {
+            entries: u32,
+            shared: Arc<Shared>,
+        }
+
+        impl Uring {
+            /// Setup a ring with `entries` submission entries, at most `entries - 1` reads are in flight.
+            pub fn new(entries: u32) -> io::Result<Self> {
+                let ring = IoUring::new(entries)?;
+                let eventfd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+                if eventfd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let shared = Arc::new(Shared {
+                    queue: Mutex::new(VecDeque::new()),
+                    eventfd,
+                });
+                let shared2 = shared.clone();
+                thread::Builder::new()
+                    .name("hyper-fs-uring".to_owned())
+                    .spawn(move || run(ring, &shared2, entries as usize))?;
+                Ok(Self {
+                    entries,
+                    shared,
+                })
+            }
+            pub fn entries(&self) -> u32 {
+                self.entries
+            }
+            /// Read `len` bytes of the `file` at `offset`, it fails if the file shrinks or is not the `snapshot`.
+            pub(crate) fn read(&self, file: Arc<File>, offset: u64, len: usize, snapshot: Snapshot) -> Blocking<Chunk, HyperError> {
+                let (tx, blocking) = Blocking::pair();
+                self.shared.queue.lock().unwrap().push_back(Read {
+                    file,
+                    buf: vec![0; len],
+                    filled: 0,
+                    offset,
+                    snapshot,
+                    tx,
+                });
+                self.shared.wake();
+                blocking
+            }
+        }
+
+        impl Drop for Uring {
+            fn drop(&mut self) {
+                self.shared.wake();
+            }
+        }
+
+        impl fmt::Debug for Uring {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.debug_struct("Uring").field("entries", &self.entries).finish()
+            }
+        }
+
+        // the thread exits after the `Uring` is dropped(only the thread holds the `Shared`) and all reads are done.
+        fn run(mut ring: IoUring, shared: &Arc<Shared>, entries: usize) {
+            let mut inflight: HashMap<u64, Read> = HashMap::new();
+            let mut next = 0u64;
+            let mut counter = Box::new(0u64);
+            let mut waker_armed = false;
+            loop {
+                let closing = Arc::strong_count(shared) == 1;
+                let mut sq = Vec::new();
+                // the kernel writes the buffers, they are not dropped before completed
+                if !waker_armed && !closing {
+                    let read = opcode::Read::new(types::Fd(shared.eventfd), &mut *counter as *mut u64 as *mut u8, 8);
+                    sq.push((WAKER, read.build().user_data(WAKER)));
+                    waker_armed = true;
+                }
+                {
+                    let mut queue = shared.queue.lock().unwrap();
+                    while inflight.len() + 1 < entries {
+                        let mut read = match queue.pop_front() {
+                            Some(read) => read,
+                            None => break,
+                        };
+                        next = (next + 1) % WAKER;
+                        let sqe = read_sqe(&mut read).user_data(next);
+                        inflight.insert(next, read);
+                        sq.push((next, sqe));
+                    }
+                }
+                if closing && inflight.is_empty() && !waker_armed {
+                    return;
+                }
+                // the reads which do not fit the submission queue are queued again, the waker is armed again
+                let mut unsubmitted = Vec::new();
+                for (user_data, sqe) in sq {
+                    if !unsubmitted.is_empty() || !push(&mut ring, &sqe) {
+                        unsubmitted.push(user_data);
+                    }
+                }
+                for user_data in unsubmitted.into_iter().rev() {
+                    match inflight.remove(&user_data) {
+                        Some(read) => shared.queue.lock().unwrap().push_front(read),
+                        None => waker_armed = false,
+                    }
+                }
+                match ring.submit_and_wait(1) {
+                    Ok(_) => {}
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    // EBUSY(the completion queue overflows) etc., reap the completions and retry
+                    Err(e) => error!("io_uring_enter: {}", e),
+                }
+                let completed: Vec<(u64, i32)> = ring.completion().map(|cqe| (cqe.user_data(), cqe.result())).collect();
+                for (user_data, result) in completed {
+                    if user_data == WAKER {
+                        waker_armed = false;
+                        continue;
+                    }
+                    let mut read = match inflight.remove(&user_data) {
+                        Some(read) => read,
+                        None => continue,
+                    };
+                    if result < 0 && -result != libc::EINTR && -result != libc::EAGAIN {
+                        let e = io::Error::from_raw_os_error(-result);
+                        read.tx.send(Err(HyperError::Io(e))).ok();
+                        continue;
+                    }
+                    if result > 0 {
+                        read.filled += result as usize;
+                    }
+                    if result == 0 {
+                        read.tx.send(Err(file_changed("file shrank while sending"))).ok();
+                        continue;
+                    }
+                    if read.filled == read.buf.len() {
+                        let Read { buf, file, snapshot, tx, .. } = read;
+                        tx.send(snapshot.verify(&file).map(|_| Chunk::from(buf))).ok();
+                        continue;
+                    }
+                    // short read, read the rest
+                    shared.queue.lock().unwrap().push_front(read);
+                }
+            }
+        }
+
+        // push a entry, the full submission queue is submitted and tried again once
+        fn push(ring: &mut IoUring, sqe: &::io_uring::squeue::Entry) -> bool {
+            for _ in 0..2 {
+                if unsafe { ring.submission().push(sqe) }.is_ok() {
+                    return true;
+                }
+                if let Err(e) = ring.submit() {
+                    debug!("io_uring_enter: {}", e);
+                }
+            }
+            false
+        }
+
+        fn read_sqe(read: &mut Read) -> ::io_uring::squeue::Entry {
+            let filled = read.filled;
+            let buf = &mut read.buf[filled..];
+            opcode::Read::new(types::Fd(read.file.as_raw_fd()), buf.as_mut_ptr(), buf.len() as u32)
+                .offset64((read.offset + filled as u64) as libc::off64_t)
+                .build()
+        }
+    } else {
+        use hyper::{Chunk, Error as HyperError};
+
+        use executor::Blocking;
+        use static_file::Snapshot;
+
+        use std::fs::File;
+        use std::io;
+        use std::sync::Arc;
+
+        // never constructed without the `io-uring` feature
+        #[derive(Debug)]
+        pub(crate) struct Uring;
+
+        impl Uring {
+            pub(crate) fn read(&self, _file: Arc<File>, _offset: u64, _len: usize, _snapshot: Snapshot) -> Blocking<Chunk, HyperError> {
+                Blocking::done(Err(HyperError::Io(io::Error::other("io_uring is disabled"))))
+            }
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+#[test]
+fn uring_test() {
+    use futures::{Future, Stream};
+    use futures_cpupool::CpuPool;
+    use hyper::{Method, Request};
+    use super::{Config, StaticFile};
+
+    // more reads in flight than the entries
+    let config = Config::new().io_uring(4).chunk_size(100).read_ahead(16);
+    if config.get_io_uring().is_none() {
+        println!("skip uring_test: io_uring is unavailable");
+        return;
+    }
+    let content = ::std::fs::read("Cargo.toml").unwrap();
+    let pool = CpuPool::new(1);
+    let req = Request::new(Method::Get, "/Cargo.toml".parse().unwrap());
+    let (res, _) = StaticFile::new(pool.clone(), pool.clone(), "Cargo.toml", config)
+        .call(&pool, req)
+        .wait()
+        .unwrap();
+    let body = res.body().concat2().wait().unwrap();
+    assert_eq!(&*body, &content[..]);
+}
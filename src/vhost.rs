@@ -0,0 +1,197 @@
+use hyper::server::{Request, Response, Service};
+use hyper::{header, Error as HyperError};
+use futures::{future, Future};
+
+use super::{error_handler, Config, Error, FutureObject, HyperFutureObject};
+use super::StaticFs;
+use executor::{BlockingExecutor, Spawner};
+
+use std::cmp::Reverse;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq)]
+enum HostPattern {
+    // docs.example.com
+    Exact(String),
+    // *.example.com -> .example.com
+    Wildcard(String),
+}
+
+impl HostPattern {
+    fn new(pattern: &str) -> Self {
+        let pattern = normalize(pattern);
+        if pattern.starts_with("*.") {
+            HostPattern::Wildcard(pattern[1..].to_owned())
+        } else {
+            HostPattern::Exact(pattern)
+        }
+    }
+    fn matches(&self, host: &str) -> bool {
+        match *self {
+            HostPattern::Exact(ref name) => name == host,
+            HostPattern::Wildcard(ref suffix) => host.len() > suffix.len() && host.ends_with(suffix.as_str()),
+        }
+    }
+    // exact names first, then the longest wildcard
+    fn priority(&self) -> (bool, usize) {
+        match *self {
+            HostPattern::Exact(ref name) => (true, name.len()),
+            HostPattern::Wildcard(ref suffix) => (false, suffix.len()),
+        }
+    }
+}
+
+// lowercase and without the trailing dot of FQDN
+fn normalize(host: &str) -> String {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    host.to_lowercase()
+}
+
+struct Host<C> {
+    pattern: HostPattern,
+    fs: StaticFs<C>,
+}
+
+/// Name-based Virtual Hosting: select the root and `Config` by the `Host` of `Request`
+///
+/// `Location` of redirects is the path only, so the original host is kept by the client.
+pub struct VirtualHosts<C> {
+    spawner: Arc<dyn Spawner>,
+    executor: Arc<dyn BlockingExecutor>,
+    hosts: Vec<Host<C>>,
+    default: Option<Host<C>>,
+    headers_file: Option<header::Headers>,
+    headers_index: Option<header::Headers>,
+}
+
+impl<C> VirtualHosts<C>
+where
+    C: AsRef<Config> + Clone + Send + 'static,
+{
+    /// `spawner` pumps the body(`Handle` usually), `executor` runs the blocking filesystem work(`CpuPool` usually).
+    pub fn new<S: Spawner, B: BlockingExecutor>(spawner: S, executor: B) -> Self {
+        Self {
+            spawner: Arc::new(spawner),
+            executor: Arc::new(executor),
+            hosts: Vec::new(),
+            default: None,
+            headers_file: None,
+            headers_index: None,
+        }
+    }
+    /// serve `path` for the `name`, `*.example.com` matches all the subdomains of `example.com`.
+    pub fn host<N, P>(mut self, name: N, path: P, config: C) -> Self
+    where
+        N: AsRef<str>,
+        P: Into<PathBuf>,
+    {
+        let pattern = HostPattern::new(name.as_ref());
+        let fs = self.static_fs(path, config);
+        self.hosts.retain(|h| h.pattern != pattern);
+        self.hosts.push(Host {
+            pattern,
+            fs,
+        });
+        self.hosts.sort_by_key(|h| Reverse(h.pattern.priority()));
+        self
+    }
+    /// serve `path` if the `Host` is missing or not matched.
+    pub fn default_host<P: Into<PathBuf>>(mut self, path: P, config: C) -> Self {
+        let fs = self.static_fs(path, config);
+        self.default = Some(Host {
+            pattern: HostPattern::Wildcard(String::new()),
+            fs,
+        });
+        self
+    }
+    /// the init `Headers` of file for all the hosts.
+    pub fn headers_file_mut(&mut self) -> &mut Option<header::Headers> {
+        &mut self.headers_file
+    }
+    /// the init `Headers` of index for all the hosts.
+    pub fn headers_index_mut(&mut self) -> &mut Option<header::Headers> {
+        &mut self.headers_index
+    }
+    fn static_fs<P: Into<PathBuf>>(&self, path: P, config: C) -> StaticFs<C> {
+        StaticFs::with_executors(self.spawner.clone(), self.executor.clone(), "/", path, config)
+    }
+    pub fn call(&self, req: Request) -> FutureObject {
+        let name = req.headers()
+            .get::<header::Host>()
+            .map(|h| normalize(h.hostname()))
+            .or_else(|| req.uri().host().map(normalize));
+
+        let host = name.as_ref()
+            .and_then(|name| self.hosts.iter().find(|h| h.pattern.matches(name)))
+            .or(self.default.as_ref());
+        debug!("{:?} -> {:?}", name, host.map(|h| &h.pattern));
+
+        match host {
+            Some(h) => h.fs.serve(req, self.headers_file.as_ref(), self.headers_index.as_ref()),
+            None => Box::new(future::err((Error::not_found(), req))),
+        }
+    }
+}
+
+impl<C> Service for VirtualHosts<C>
+where
+    C: AsRef<Config> + Clone + Send + 'static,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = HyperError;
+    type Future = HyperFutureObject;
+    fn call(&self, req: Request) -> Self::Future {
+        Box::new(
+            VirtualHosts::call(self, req)
+                .or_else(error_handler)
+                .map(|res_req| res_req.0),
+        )
+    }
+}
+
+#[test]
+fn host_pattern_test() {
+    let exact = HostPattern::new("Docs.Example.com.");
+    assert!(exact.matches("docs.example.com"));
+    assert!(!exact.matches("api.docs.example.com"));
+
+    let wildcard = HostPattern::new("*.example.com");
+    assert!(wildcard.matches("docs.example.com"));
+    assert!(wildcard.matches("api.docs.example.com"));
+    assert!(!wildcard.matches("example.com"));
+    assert!(!wildcard.matches("docsexample.com"));
+
+    let mut patterns = [wildcard.clone(), HostPattern::new("*.docs.example.com"), exact.clone()];
+    patterns.sort_by_key(|p| Reverse(p.priority()));
+    assert_eq!(patterns[0], exact);
+    assert_eq!(patterns[2], wildcard);
+}
+
+#[test]
+fn virtual_hosts_test() {
+    use futures_cpupool::CpuPool;
+    use hyper::{Method, StatusCode};
+
+    let pool = CpuPool::new(1);
+    let hosts = VirtualHosts::new(pool.clone(), pool)
+        .host("*.example.com", "tests", Config::new())
+        .host("docs.example.com", "src", Config::new())
+        .default_host("tests/index", Config::new());
+    let status = |host: Option<header::Host>, path: &str| {
+        let mut req = Request::new(Method::Get, path.parse().unwrap());
+        if let Some(host) = host {
+            req.headers_mut().set(host);
+        }
+        hosts.call(req).or_else(error_handler).wait().unwrap().0.status()
+    };
+    // the exact name beats the wildcard, the port is ignored
+    assert_eq!(status(Some(header::Host::new("Docs.Example.com", Some(8080))), "/lib.rs"), StatusCode::Ok);
+    assert_eq!(status(Some(header::Host::new("api.example.com", None)), "/lib.rs"), StatusCode::NotFound);
+    assert_eq!(status(Some(header::Host::new("api.example.com", Some(80))), "/index/file"), StatusCode::Ok);
+    // the default for the others and the missing `Host`
+    assert_eq!(status(Some(header::Host::new("example.org", None)), "/file"), StatusCode::Ok);
+    assert_eq!(status(None, "/file"), StatusCode::Ok);
+    assert_eq!(status(None, "/lib.rs"), StatusCode::NotFound);
+}
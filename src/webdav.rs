@@ -0,0 +1,847 @@
+/*!
+WebDAV(RFC 4918) class 1 and 2 on top of `StaticFs`, enable it by `Config::webdav`.
+
+`GET`/`HEAD` are served as usual, `PUT`, `DELETE`, `MKCOL` and `MOVE` are the ones of the write mode(`Config::write`),
+this module adds `OPTIONS`, `PROPFIND`(depth 0 and 1), `PROPPATCH`(the properties are not writable),
+`COPY` and the in-memory `LOCK`/`UNLOCK`.
+*/
+
+use futures::{future, Future, Stream};
+use hyper::server::{Request, Response};
+use hyper::{header, Method, StatusCode};
+use url::percent_encoding::{percent_decode, utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+use httpdate::fmt_http_date;
+
+use super::{Config, Error, FutureObject};
+use executor::{spawn_fn, BlockingExecutor};
+use date::rfc3339;
+use http_core::last_modified_etag;
+use ops::{copy_tree, created_or_replaced, decode_href, overwrite, prepare_destination};
+use static_fs::{components, confine};
+use upload::{weak, Condition};
+use quota::{tree_size, Charge};
+use xml::{self, Element};
+use stat_cache;
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::fs::{self, Metadata, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{fmt, io, process};
+
+const DAV: &str = "DAV:";
+// the XML bodies larger than it are rejected
+const MAX_BODY: usize = 1024 * 1024;
+// the seconds of the lock without `Timeout`
+const DEFAULT_TIMEOUT: u64 = 3600;
+// the properties of `allprop` and `propname`
+const LIVE: &[&str] = &[
+    "creationdate",
+    "displayname",
+    "getcontentlength",
+    "getcontenttype",
+    "getetag",
+    "getlastmodified",
+    "lockdiscovery",
+    "resourcetype",
+    "supportedlock",
+];
+
+#[derive(Debug, Clone)]
+struct Lock {
+    token: String,
+    root: PathBuf,
+    href: String,
+    exclusive: bool,
+    infinite: bool,
+    owner: String,
+    // `None` is `Infinite`
+    timeout: Option<u64>,
+    expire: Option<Instant>,
+}
+
+impl Lock {
+    fn covers(&self, path: &Path) -> bool {
+        self.root == path || self.infinite && path.starts_with(&self.root)
+    }
+    fn to_xml(&self, out: &mut String) {
+        let timeout = match self.expire {
+            Some(expire) => {
+                let now = Instant::now();
+                format!("Second-{}", if expire > now { (expire - now).as_secs() } else { 0 })
+            }
+            None => "Infinite".to_owned(),
+        };
+        out.push_str(&format!(
+            "<D:activelock><D:locktype><D:write/></D:locktype><D:lockscope><D:{}/></D:lockscope>\
+             <D:depth>{}</D:depth><D:owner>{}</D:owner><D:timeout>{}</D:timeout>\
+             <D:locktoken><D:href>{}</D:href></D:locktoken><D:lockroot><D:href>{}</D:href></D:lockroot></D:activelock>",
+            if self.exclusive { "exclusive" } else { "shared" },
+            if self.infinite { "infinity" } else { "0" },
+            self.owner,
+            timeout,
+            self.token,
+            xml::escape(&self.href)
+        ));
+    }
+}
+
+/// The write locks of WebDAV, they are kept in memory and shared by the clones of the `Config`.
+pub struct DavLocks {
+    locks: Mutex<HashMap<String, Lock>>,
+    counter: AtomicUsize,
+}
+
+impl DavLocks {
+    pub fn new() -> Self {
+        Self {
+            locks: Mutex::new(HashMap::new()),
+            counter: AtomicUsize::new(0),
+        }
+    }
+    /// How many locks are active.
+    pub fn len(&self) -> usize {
+        self.active().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    // the locks not expired
+    fn active(&self) -> ::std::sync::MutexGuard<'_, HashMap<String, Lock>> {
+        let mut locks = self.locks.lock().unwrap();
+        let now = Instant::now();
+        locks.retain(|_, l| l.expire.map(|e| e > now).unwrap_or(true));
+        locks
+    }
+    // a random UUID(the keys of `RandomState` are random) for the `opaquelocktoken` URI
+    fn token(&self) -> String {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() << 30 ^ u64::from(d.subsec_nanos()))
+            .unwrap_or(0);
+        let counter = self.counter.fetch_add(1, Ordering::Relaxed) as u64;
+        let random = |seed: u64| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u64(seed);
+            hasher.write_u64(counter);
+            hasher.write_u32(process::id());
+            hasher.finish()
+        };
+        let (a, b) = (random(nanos), random(!nanos));
+        format!(
+            "opaquelocktoken:{:08x}-{:04x}-4{:03x}-{:04x}-{:012x}",
+            a >> 32,
+            a >> 16 & 0xffff,
+            a & 0xfff,
+            b >> 48 & 0x3fff | 0x8000,
+            b & 0xffff_ffff_ffff
+        )
+    }
+    /// Whether the `path` can be written with the submitted `tokens`, `subtree`: its members are changed too,
+    /// `membership`: the members of its parent are changed.
+    pub(crate) fn check_write(&self, path: &Path, subtree: bool, membership: bool, tokens: &[String]) -> Result<(), Error> {
+        let locks = self.active();
+        let relevant = locks
+            .values()
+            .filter(|l| {
+                l.covers(path) || subtree && l.root.starts_with(path) || membership && Some(l.root.as_path()) == path.parent()
+            })
+            .collect::<Vec<_>>();
+        // one of the shared locks of a root is enough
+        let submitted = |root: &PathBuf| relevant.iter().any(|l| l.root == *root && tokens.contains(&l.token));
+        if !relevant.iter().all(|l| submitted(&l.root)) {
+            Err(Error::Locked)
+        } else {
+            Ok(())
+        }
+    }
+    fn lock(&self, lock: Lock) -> Result<Lock, Error> {
+        let mut locks = self.active();
+        let conflict = locks
+            .values()
+            .filter(|l| l.covers(&lock.root) || lock.infinite && l.root.starts_with(&lock.root))
+            .any(|l| lock.exclusive || l.exclusive);
+        if conflict {
+            return Err(Error::Locked);
+        }
+        locks.insert(lock.token.clone(), lock.clone());
+        Ok(lock)
+    }
+    fn refresh(&self, path: &Path, tokens: &[String], timeout: Option<u64>) -> Result<Lock, Error> {
+        let mut locks = self.active();
+        for token in tokens {
+            if let Some(lock) = locks.get_mut(token) {
+                if lock.covers(path) {
+                    lock.timeout = timeout;
+                    lock.expire = expire(timeout);
+                    return Ok(lock.clone());
+                }
+            }
+        }
+        Err(Error::Precondition)
+    }
+    fn unlock(&self, path: &Path, token: &str) -> Result<(), Error> {
+        let mut locks = self.active();
+        match locks.get(token) {
+            Some(l) if l.covers(path) => {}
+            _ => return Err(Error::Conflict),
+        }
+        locks.remove(token);
+        Ok(())
+    }
+    fn discovery(&self, path: &Path) -> Vec<Lock> {
+        self.active().values().filter(|l| l.covers(path)).cloned().collect()
+    }
+    /// Forget the locks of the removed `path` and its members.
+    pub(crate) fn remove_tree(&self, path: &Path) {
+        self.active().retain(|_, l| !l.root.starts_with(path))
+    }
+    fn valid(&self, token: &str, path: &Path) -> bool {
+        self.active().get(token).map(|l| l.covers(path)).unwrap_or(false)
+    }
+}
+
+impl Default for DavLocks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for DavLocks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DavLocks").field("locks", &self.len()).finish()
+    }
+}
+
+fn expire(timeout: Option<u64>) -> Option<Instant> {
+    timeout.map(|secs| Instant::now() + Duration::from_secs(secs))
+}
+
+fn method(req: &Request, name: &str) -> bool {
+    match *req.method() {
+        Method::Extension(ref m) => m == name,
+        _ => false,
+    }
+}
+
+/// Evaluate the `If` header and check the locks of the write(not `LOCK`/`UNLOCK`), `href` is the decoded path of the `Request`,
+/// `target` is the metadata of `path`(`None` if it does not exist) and `to` is the destination of `MOVE` and `COPY`.
+pub(crate) fn check(locks: &DavLocks, req: &Request, href: &str, path: &Path, target: Option<&Metadata>, to: Option<&PathBuf>) -> Result<(), Error> {
+    let tokens = evaluate_if(locks, req, href, path, target)?;
+    let (subtree, membership) = match *req.method() {
+        Method::Delete => (true, true),
+        Method::Put => (false, target.is_none()),
+        _ if method(req, "MOVE") => (true, true),
+        _ if method(req, "MKCOL") => (false, true),
+        _ => (false, false),
+    };
+    // the source of `COPY` is not changed
+    if !method(req, "COPY") {
+        locks.check_write(path, subtree, membership, &tokens)?;
+    }
+    if let Some(to) = to {
+        locks.check_write(to, true, true, &tokens)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum Cond {
+    Token(String),
+    ETag(String),
+}
+
+// the lists of `If` with their resource tags, the conditions are negated by `Not`
+type IfLists = Vec<(Option<String>, Vec<(bool, Cond)>)>;
+
+// `If: <tag> (Not <token> [etag]) (...)`
+fn parse_if(s: &str) -> Option<IfLists> {
+    let mut lists = vec![];
+    let (mut tag, mut list, mut not): (_, Option<Vec<_>>, _) = (None, None, false);
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        let c = rest.chars().next()?;
+        match c {
+            '<' | '[' => {
+                let close = if c == '<' { '>' } else { ']' };
+                let end = rest.find(close)?;
+                let value = rest[1..end].to_owned();
+                rest = &rest[end + 1..];
+                match (list.as_mut(), c) {
+                    (None, '<') => tag = Some(value),
+                    (Some(conds), '<') => conds.push((not, Cond::Token(value))),
+                    (Some(conds), _) => conds.push((not, Cond::ETag(value))),
+                    (None, _) => return None,
+                }
+                not = false;
+            }
+            '(' if list.is_none() => {
+                list = Some(vec![]);
+                rest = &rest[1..];
+            }
+            ')' => {
+                lists.push((tag.clone(), list.take()?));
+                rest = &rest[1..];
+            }
+            'N' if rest.starts_with("Not") => {
+                not = true;
+                rest = &rest[3..];
+            }
+            c if c.is_whitespace() => rest = &rest[c.len_utf8()..],
+            _ => return None,
+        }
+    }
+    if list.is_some() || lists.is_empty() {
+        return None;
+    }
+    Some(lists)
+}
+
+// return the submitted lock tokens, `412` if the `If` header is false.
+fn evaluate_if(locks: &DavLocks, req: &Request, href: &str, path: &Path, target: Option<&Metadata>) -> Result<Vec<String>, Error> {
+    let raw = match req.headers().get_raw("If").and_then(|raw| raw.one()) {
+        Some(raw) => String::from_utf8_lossy(raw).into_owned(),
+        None => return Ok(vec![]),
+    };
+    let lists = parse_if(&raw).ok_or(Error::Malformed)?;
+    let tokens = lists
+        .iter()
+        .flat_map(|(_, conds)| conds.iter())
+        .filter_map(|(_, cond)| match *cond {
+            Cond::Token(ref token) => Some(token.clone()),
+            _ => None,
+        })
+        .collect();
+    let etag = target.and_then(|md| last_modified_etag(md).ok()).map(|(_, etag)| etag);
+    let mut applied = false;
+    let matched = lists.iter().any(|(tag, conds)| {
+        // the lists of the other resources are not evaluated
+        if let Some(ref tag) = *tag {
+            if normalize(&decode_href(tag).unwrap_or_default()) != normalize(href) {
+                return false;
+            }
+        }
+        applied = true;
+        conds.iter().all(|&(not, ref cond)| {
+            let result = match *cond {
+                Cond::Token(ref token) => locks.valid(token, path),
+                Cond::ETag(ref e) => etag.as_ref().map(|etag| weak(etag) == weak(e)).unwrap_or(false),
+            };
+            result != not
+        })
+    });
+    if matched || !applied {
+        Ok(tokens)
+    } else {
+        Err(Error::Precondition)
+    }
+}
+
+fn normalize(path: &str) -> String {
+    components(path).join("/")
+}
+
+/// Percent-encode the segments of the decoded `path`.
+pub(crate) fn encode_href(path: &str) -> String {
+    path.split('/')
+        .map(|s| utf8_percent_encode(s, PATH_SEGMENT_ENCODE_SET).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// `OPTIONS`, the methods of WebDAV are advertised.
+pub(crate) fn options(config: &Config, req: Request) -> FutureObject {
+    let mut allow = "OPTIONS, GET, HEAD, PROPFIND".to_owned();
+    if config.get_write() {
+        allow.push_str(", PUT, POST, DELETE, MKCOL, COPY, MOVE, PROPPATCH, LOCK, UNLOCK");
+    }
+    let mut res = Response::new().with_header(header::ContentLength(0));
+    res.headers_mut().set_raw("DAV", "1, 2");
+    res.headers_mut().set_raw("MS-Author-Via", "DAV");
+    res.headers_mut().set_raw("Allow", allow);
+    Box::new(future::ok((res, req)))
+}
+
+// the XML body of the request, `None` if it is empty
+fn read_body(req: &mut Request) -> Box<dyn Future<Item = Option<Element>, Error = Error>> {
+    let body = req.body_mut().take().unwrap_or_default();
+    let body = body.map_err(|e| Error::Io(io::Error::other(e)))
+        .fold(vec![], |mut buf, chunk| {
+            buf.extend_from_slice(&chunk);
+            if buf.len() > MAX_BODY {
+                Err(Error::TooLarge)
+            } else {
+                Ok(buf)
+            }
+        })
+        .and_then(|buf| {
+            let doc = String::from_utf8(buf).map_err(|_| Error::Malformed)?;
+            if doc.trim().is_empty() {
+                return Ok(None);
+            }
+            xml::parse(&doc).map(Some).ok_or(Error::Malformed)
+        });
+    Box::new(body)
+}
+
+fn multistatus(body: String) -> Response {
+    let xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">{}</D:multistatus>",
+        body
+    );
+    xml_response(StatusCode::MultiStatus, xml)
+}
+
+fn xml_response(status: StatusCode, xml: String) -> Response {
+    Response::new()
+        .with_status(status)
+        .with_header(header::ContentType("application/xml; charset=utf-8".parse().unwrap()))
+        .with_header(header::ContentLength(xml.len() as u64))
+        .with_body(xml)
+}
+
+// `Depth`: `Some(false)` is 0, `Some(true)` is infinity, `None` is 1
+fn depth(req: &Request, default: Option<bool>) -> Result<Option<bool>, Error> {
+    match req.headers().get_raw("Depth").and_then(|raw| raw.one()) {
+        None => Ok(default),
+        Some(b"0") => Ok(Some(false)),
+        Some(b"1") => Ok(None),
+        Some(v) if v.eq_ignore_ascii_case(b"infinity") => Ok(Some(true)),
+        Some(_) => Err(Error::Malformed),
+    }
+}
+
+enum Find {
+    All,
+    Names,
+    Props(Vec<(String, String)>),
+}
+
+impl Find {
+    fn new(body: Option<Element>) -> Result<Self, Error> {
+        let body = match body {
+            Some(body) => body,
+            None => return Ok(Find::All),
+        };
+        if !body.is(DAV, "propfind") {
+            return Err(Error::Malformed);
+        }
+        if body.child(DAV, "propname").is_some() {
+            return Ok(Find::Names);
+        }
+        if body.child(DAV, "allprop").is_some() {
+            return Ok(Find::All);
+        }
+        match body.child(DAV, "prop") {
+            Some(prop) => Ok(Find::Props(
+                prop.children.iter().map(|c| (c.ns.clone(), c.name.clone())).collect(),
+            )),
+            None => Err(Error::Malformed),
+        }
+    }
+}
+
+/// `PROPFIND` the `path` and its members for `Depth: 1`, `Depth: infinity` is refused,
+/// and `Depth: 1` of a directory is `403` unless `Config::show_index`.
+#[allow(clippy::result_large_err)]
+pub(crate) fn propfind<C>(executor: Arc<dyn BlockingExecutor>, href: String, path: PathBuf, config: C, mut req: Request) -> FutureObject
+where
+    C: AsRef<Config> + Send + 'static,
+{
+    let depth = match depth(&req, Some(true)) {
+        Ok(Some(true)) => {
+            let xml = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+                       <D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>";
+            return Box::new(future::ok((xml_response(StatusCode::Forbidden, xml.to_owned()), req)));
+        }
+        Ok(depth) => depth.is_none(),
+        Err(e) => return Box::new(future::err((e, req))),
+    };
+    let propfind = read_body(&mut req).and_then(move |body| {
+        spawn_fn(&*executor, move || {
+            let find = Find::new(body)?;
+            let config = config.as_ref();
+            let metadata = stat_cache::metadata(config, &path, config.get_follow_links())?;
+            // the members are listed as the index
+            if depth && metadata.is_dir() && !config.get_show_index() {
+                return Err(Error::forbidden());
+            }
+            let mut href = encode_href(&href);
+            if metadata.is_dir() && !href.ends_with('/') {
+                href.push('/');
+            }
+            let mut out = String::new();
+            response(&mut out, &href, &path, &metadata, &find, config);
+            if depth && metadata.is_dir() {
+                for entry in fs::read_dir(&path)? {
+                    let entry = entry?;
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    if config.get_hide_entry() && name.starts_with('.') {
+                        continue;
+                    }
+                    let child = entry.path();
+                    // the broken symlinks, or the symlinks not followed
+                    let metadata = match stat_cache::metadata(config, &child, config.get_follow_links()) {
+                        Ok(ref md) if !md.is_dir() && !md.is_file() => continue,
+                        Ok(md) => md,
+                        Err(_) => continue,
+                    };
+                    let mut child_href = format!("{}{}", href, encode_href(&name));
+                    if metadata.is_dir() {
+                        child_href.push('/');
+                    }
+                    response(&mut out, &child_href, &child, &metadata, &find, config);
+                }
+            }
+            Ok(out)
+        })
+    });
+    Box::new(propfind.then(move |result| match result {
+        Ok(out) => Ok((multistatus(out), req)),
+        Err(e) => Err((e, req)),
+    }))
+}
+
+// the `response` of a resource
+fn response(out: &mut String, href: &str, path: &Path, metadata: &Metadata, find: &Find, config: &Config) {
+    out.push_str(&format!("<D:response><D:href>{}</D:href>", xml::escape(href)));
+    let mut found = String::new();
+    let mut missing = String::new();
+    match *find {
+        Find::All => for name in LIVE {
+            if let Some(value) = live(name, href, path, metadata, config) {
+                found.push_str(&format!("<D:{0}>{1}</D:{0}>", name, value));
+            }
+        },
+        Find::Names => for name in LIVE {
+            if live(name, href, path, metadata, config).is_some() {
+                found.push_str(&format!("<D:{}/>", name));
+            }
+        },
+        Find::Props(ref props) => for (ns, name) in props {
+            let value = if ns == DAV {
+                live(name, href, path, metadata, config)
+            } else {
+                None
+            };
+            match value {
+                Some(value) => found.push_str(&format!("<D:{0}>{1}</D:{0}>", name, value)),
+                None => missing.push_str(&empty_prop(ns, name)),
+            }
+        },
+    }
+    if !found.is_empty() {
+        out.push_str(&propstat(&found, "200 OK"));
+    }
+    if !missing.is_empty() {
+        out.push_str(&propstat(&missing, "404 Not Found"));
+    }
+    out.push_str("</D:response>");
+}
+
+fn propstat(props: &str, status: &str) -> String {
+    format!(
+        "<D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 {}</D:status></D:propstat>",
+        props, status
+    )
+}
+
+fn empty_prop(ns: &str, name: &str) -> String {
+    if ns == DAV {
+        format!("<D:{}/>", name)
+    } else {
+        format!("<{} xmlns=\"{}\"/>", name, xml::escape(ns))
+    }
+}
+
+// the value of the live property
+fn live(name: &str, href: &str, path: &Path, metadata: &Metadata, config: &Config) -> Option<String> {
+    let is_dir = metadata.is_dir();
+    match name {
+        "creationdate" => metadata.created().or_else(|_| metadata.modified()).ok().map(rfc3339),
+        "displayname" => {
+            let href = href.strip_suffix('/').unwrap_or(href);
+            let name = href.rsplit('/').next().unwrap_or("");
+            let name = percent_decode(name.as_bytes()).decode_utf8_lossy().into_owned();
+            Some(xml::escape(&name))
+        }
+        "getcontentlength" if !is_dir => Some(metadata.len().to_string()),
+        "getcontenttype" if !is_dir => content_type(path),
+        "getetag" => last_modified_etag(metadata).ok().map(|(_, etag)| xml::escape(&etag)),
+        "getlastmodified" => last_modified_etag(metadata).ok().map(|(lm, _)| fmt_http_date(lm)),
+        "resourcetype" if is_dir => Some("<D:collection/>".to_owned()),
+        "resourcetype" => Some(String::new()),
+        "supportedlock" => Some(
+            "<D:lockentry><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>\
+             <D:lockentry><D:lockscope><D:shared/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>"
+                .to_owned(),
+        ),
+        "lockdiscovery" => {
+            let mut out = String::new();
+            if let Some(locks) = config.get_webdav() {
+                for lock in locks.discovery(path) {
+                    lock.to_xml(&mut out);
+                }
+            }
+            Some(out)
+        }
+        _ => None,
+    }
+}
+
+cfg_if! {
+    if #[cfg(feature = "default")] {
+        fn content_type(path: &Path) -> Option<String> {
+            Some(::mime_guess::from_path(path).first_or_octet_stream().to_string())
+        }
+    } else {
+        fn content_type(_path: &Path) -> Option<String> {
+            None
+        }
+    }
+}
+
+/// `PROPPATCH`, the properties are not stored, every one is `403`.
+#[allow(clippy::result_large_err)]
+pub(crate) fn proppatch<C>(executor: Arc<dyn BlockingExecutor>, root: PathBuf, href: String, path: PathBuf, config: C, mut req: Request) -> FutureObject
+where
+    C: AsRef<Config> + Send + 'static,
+{
+    let hide_entry = config.as_ref().get_hide_entry();
+    let confined = spawn_fn(&*executor, move || confine(&root, &path, hide_entry));
+    let proppatch = confined.join(read_body(&mut req)).and_then(move |(_, body)| {
+        let body = body.ok_or(Error::Malformed)?;
+        if !body.is(DAV, "propertyupdate") {
+            return Err(Error::Malformed);
+        }
+        let props = body.children
+            .iter()
+            .filter(|c| c.is(DAV, "set") || c.is(DAV, "remove"))
+            .filter_map(|c| c.child(DAV, "prop"))
+            .flat_map(|prop| prop.children.iter())
+            .map(|p| empty_prop(&p.ns, &p.name))
+            .collect::<String>();
+        let mut out = format!("<D:response><D:href>{}</D:href>", xml::escape(&encode_href(&href)));
+        out.push_str(&propstat(&props, "403 Forbidden"));
+        out.push_str("</D:response>");
+        Ok(out)
+    });
+    Box::new(proppatch.then(move |result| match result {
+        Ok(out) => Ok((multistatus(out), req)),
+        Err(e) => Err((e, req)),
+    }))
+}
+
+/// `COPY` the `from` to `to`, the members are copied too unless `Depth: 0`.
+#[allow(clippy::result_large_err)]
+pub(crate) fn copy<C>(executor: Arc<dyn BlockingExecutor>, root: PathBuf, from: PathBuf, to: PathBuf, config: C, req: Request) -> FutureObject
+where
+    C: AsRef<Config> + Send + 'static,
+{
+    let infinite = match depth(&req, Some(true)) {
+        Ok(Some(infinite)) => infinite,
+        Ok(None) => return Box::new(future::err((Error::Malformed, req))),
+        Err(e) => return Box::new(future::err((e, req))),
+    };
+    let condition = Condition::from_request(&req);
+    let overwrite = overwrite(&req);
+    let recursive = config.as_ref().get_recursive_delete();
+    let follow_links = config.as_ref().get_follow_links();
+    let mut charge = Charge::new(config.as_ref(), &req);
+    let quota = config.as_ref().get_quota().is_some();
+    let hide_entry = config.as_ref().get_hide_entry();
+    let to2 = to.clone();
+    let copy = spawn_fn(&*executor, move || {
+        confine(&root, &from, hide_entry)?;
+        confine(&root, &to2, hide_entry)?;
+        let metadata = fs::symlink_metadata(&from)?;
+        condition.check_metadata(Some(&metadata))?;
+        // the copy is charged as a upload
+        let (size, replaced) = if !quota {
+            (0, 0)
+        } else if infinite || !metadata.is_dir() {
+            (tree_size(&from), tree_size(&to2))
+        } else {
+            (0, tree_size(&to2))
+        };
+        charge.reserve(&root, to2.parent().unwrap_or(&root), Some(size))?;
+        let existed = prepare_destination(&from, &metadata, &to2, overwrite, recursive)?;
+        copy_tree(&from, &to2, infinite, follow_links).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::Conflict,
+            _ => e.into(),
+        })?;
+        charge.add(&to2, size, replaced);
+        Ok(existed)
+    });
+    Box::new(copy.then(move |result| match result {
+        Ok(existed) => {
+            stat_cache::changed(config.as_ref(), &to);
+            Ok((created_or_replaced(existed), req))
+        }
+        Err(e) => Err((e, req)),
+    }))
+}
+
+// `Timeout: Second-n, Infinite`, the first one is used
+fn timeout(req: &Request) -> Option<u64> {
+    let raw = match req.headers().get_raw("Timeout").and_then(|raw| raw.one()) {
+        Some(raw) => String::from_utf8_lossy(raw).into_owned(),
+        None => return Some(DEFAULT_TIMEOUT),
+    };
+    for t in raw.split(',').map(|t| t.trim()) {
+        if t.eq_ignore_ascii_case("Infinite") {
+            return None;
+        }
+        if t.len() > 7 && t[..7].eq_ignore_ascii_case("Second-") {
+            if let Ok(secs) = t[7..].parse::<u64>() {
+                return Some(secs);
+            }
+        }
+    }
+    Some(DEFAULT_TIMEOUT)
+}
+
+/// `LOCK` the `path`(a empty file is created if it does not exist), or refresh the lock if the body is empty,
+/// `target` is the metadata of `path`.
+#[allow(clippy::result_large_err)]
+pub(crate) fn lock<C>(executor: Arc<dyn BlockingExecutor>, root: PathBuf, href: String, path: PathBuf, target: Option<Metadata>, config: C, mut req: Request) -> FutureObject
+where
+    C: AsRef<Config> + Send + 'static,
+{
+    let locks = config.as_ref().get_webdav().expect("LOCK without Config::webdav").clone();
+    let infinite = match depth(&req, Some(true)) {
+        Ok(Some(infinite)) => infinite,
+        Ok(None) => return Box::new(future::err((Error::Malformed, req))),
+        Err(e) => return Box::new(future::err((e, req))),
+    };
+    let timeout = timeout(&req);
+    let tokens = match evaluate_if(&locks, &req, &href, &path, target.as_ref()) {
+        Ok(tokens) => tokens,
+        Err(e) => return Box::new(future::err((e, req))),
+    };
+    let hide_entry = config.as_ref().get_hide_entry();
+    let path2 = path.clone();
+    let lock = read_body(&mut req).and_then(move |body| {
+        spawn_fn(&*executor, move || {
+            confine(&root, &path2, hide_entry)?;
+            let info = match body {
+                Some(info) => info,
+                None => return locks.refresh(&path2, &tokens, timeout).map(|lock| (lock, false, false)),
+            };
+            let scope = match (info.is(DAV, "lockinfo"), info.child(DAV, "lockscope")) {
+                (true, Some(scope)) => scope,
+                _ => return Err(Error::Malformed),
+            };
+            let owner = info.child(DAV, "owner")
+                .map(|owner| {
+                    let mut out = xml::escape(&owner.text);
+                    owner.children.iter().for_each(|c| c.to_xml(&mut out));
+                    out
+                })
+                .unwrap_or_default();
+            let lock = locks.lock(Lock {
+                token: locks.token(),
+                root: path2.clone(),
+                href: encode_href(&href),
+                exclusive: scope.child(DAV, "exclusive").is_some(),
+                infinite,
+                owner,
+                timeout,
+                expire: expire(timeout),
+            })?;
+            // lock the unmapped URL
+            let created = match OpenOptions::new().write(true).create_new(true).open(&path2) {
+                Ok(_) => true,
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => false,
+                Err(e) => {
+                    locks.unlock(&path2, &lock.token).ok();
+                    return Err(match e.kind() {
+                        io::ErrorKind::NotFound => Error::Conflict,
+                        _ => e.into(),
+                    });
+                }
+            };
+            Ok((lock, true, created))
+        })
+    });
+    Box::new(lock.then(move |result| match result {
+        Ok((lock, new, created)) => {
+            if created {
+                stat_cache::changed(config.as_ref(), &path);
+            }
+            let mut xml = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery>".to_owned();
+            lock.to_xml(&mut xml);
+            xml.push_str("</D:lockdiscovery></D:prop>");
+            let mut res = xml_response(if created { StatusCode::Created } else { StatusCode::Ok }, xml);
+            if new {
+                res.headers_mut().set_raw("Lock-Token", format!("<{}>", lock.token));
+            }
+            Ok((res, req))
+        }
+        Err(e) => Err((e, req)),
+    }))
+}
+
+/// `UNLOCK` the lock of `Lock-Token`.
+pub(crate) fn unlock(locks: &DavLocks, path: &Path, req: Request) -> FutureObject {
+    let token = req.headers()
+        .get_raw("Lock-Token")
+        .and_then(|raw| raw.one())
+        .map(|raw| String::from_utf8_lossy(raw).trim().trim_matches(|c| c == '<' || c == '>').to_owned());
+    let result = match token {
+        Some(token) => locks.unlock(path, &token),
+        None => Err(Error::Malformed),
+    };
+    Box::new(future::result(match result {
+        Ok(_) => Ok((Response::new().with_status(StatusCode::NoContent), req)),
+        Err(e) => Err((e, req)),
+    }))
+}
+
+#[test]
+fn webdav_test() {
+    use std::slice;
+
+    assert_eq!(
+        parse_if("</a> (<opaquelocktoken:x> [W/\"e\"]) (Not <DAV:no-lock>)"),
+        Some(vec![
+            (
+                Some("/a".to_owned()),
+                vec![(false, Cond::Token("opaquelocktoken:x".to_owned())), (false, Cond::ETag("W/\"e\"".to_owned()))],
+            ),
+            (Some("/a".to_owned()), vec![(true, Cond::Token("DAV:no-lock".to_owned()))]),
+        ])
+    );
+    assert_eq!(parse_if("(<a>"), None);
+
+    let locks = DavLocks::new();
+    let lock = |root: &str, exclusive: bool, infinite: bool| Lock {
+        token: locks.token(),
+        root: PathBuf::from(root),
+        href: root.to_owned(),
+        exclusive,
+        infinite,
+        owner: String::new(),
+        timeout: Some(60),
+        expire: expire(Some(60)),
+    };
+    let a = locks.lock(lock("/r/a", true, true)).unwrap();
+    assert!(locks.lock(lock("/r/a/b", false, false)).is_err());
+    assert!(locks.lock(lock("/r", false, true)).is_err());
+    let c = locks.lock(lock("/r/c", false, false)).unwrap();
+    locks.lock(lock("/r/c", false, false)).unwrap();
+    assert_eq!(locks.len(), 3);
+    assert!(locks.check_write(Path::new("/r/a/b"), false, false, &[]).is_err());
+    assert!(locks.check_write(Path::new("/r/a/b"), false, false, slice::from_ref(&a.token)).is_ok());
+    assert!(locks.check_write(Path::new("/r"), true, false, slice::from_ref(&a.token)).is_err());
+    assert!(locks.check_write(Path::new("/r/c"), false, false, slice::from_ref(&c.token)).is_ok());
+    // a new member of the locked collection
+    assert!(locks.check_write(Path::new("/r/c/d"), false, true, &[]).is_err());
+    assert!(locks.unlock(Path::new("/r/c"), &a.token).is_err());
+    locks.remove_tree(Path::new("/r/a"));
+    assert_eq!(locks.len(), 2);
+}
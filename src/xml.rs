@@ -0,0 +1,219 @@
+/*!
+A minimal XML reader for the bodies of WebDAV(`propfind`, `propertyupdate`, `lockinfo`),
+the names are resolved to `(namespace, local name)`, DTDs and processing instructions are skipped.
+*/
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Element {
+    pub(crate) ns: String,
+    pub(crate) name: String,
+    pub(crate) children: Vec<Element>,
+    pub(crate) text: String,
+}
+
+impl Element {
+    pub(crate) fn is(&self, ns: &str, name: &str) -> bool {
+        self.ns == ns && self.name == name
+    }
+    pub(crate) fn child(&self, ns: &str, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.is(ns, name))
+    }
+    /// Write it back with the namespaces declared on every element, for the `owner` of `LOCK`.
+    pub(crate) fn to_xml(&self, out: &mut String) {
+        out.push_str(&format!("<{} xmlns=\"{}\">", self.name, escape(&self.ns)));
+        out.push_str(&escape(&self.text));
+        for c in &self.children {
+            c.to_xml(out);
+        }
+        out.push_str(&format!("</{}>", self.name));
+    }
+}
+
+/// Parse the document, `None` if it is not well-formed.
+pub(crate) fn parse(doc: &str) -> Option<Element> {
+    let mut stack: Vec<(Element, String)> = vec![];
+    let mut scopes: Vec<HashMap<String, String>> = vec![HashMap::new()];
+    let mut root = None;
+    let mut rest = doc;
+    while !rest.is_empty() {
+        if rest.starts_with("<?") {
+            rest = &rest[rest.find("?>")? + 2..];
+        } else if rest.starts_with("<!--") {
+            rest = &rest[rest.find("-->")? + 3..];
+        } else if rest.starts_with("<![CDATA[") {
+            let end = rest.find("]]>")?;
+            stack.last_mut()?.0.text.push_str(&rest[9..end]);
+            rest = &rest[end + 3..];
+        } else if rest.starts_with("<!") {
+            rest = &rest[rest.find('>')? + 1..];
+        } else if rest.starts_with("</") {
+            let end = rest.find('>')?;
+            let qname = rest[2..end].trim();
+            rest = &rest[end + 1..];
+            let (mut element, open) = stack.pop()?;
+            if open != qname {
+                return None;
+            }
+            scopes.pop();
+            element.text = element.text.trim().to_owned();
+            match stack.last_mut() {
+                Some(parent) => parent.0.children.push(element),
+                None if root.is_none() => root = Some(element),
+                None => return None,
+            }
+        } else if rest.starts_with('<') {
+            let end = tag_end(rest)?;
+            let tag = &rest[1..end];
+            rest = &rest[end + 1..];
+            let (tag, empty) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let (qname, attrs) = match tag.find(|c: char| c.is_whitespace()) {
+                Some(idx) => (&tag[..idx], attributes(&tag[idx..])?),
+                None => (tag, vec![]),
+            };
+            let mut scope = scopes.last().cloned().unwrap_or_default();
+            for (k, v) in attrs {
+                if k == "xmlns" {
+                    scope.insert(String::new(), v);
+                } else if let Some(prefix) = k.strip_prefix("xmlns:") {
+                    scope.insert(prefix.to_owned(), v);
+                }
+            }
+            let (prefix, name) = match qname.find(':') {
+                Some(idx) => (&qname[..idx], &qname[idx + 1..]),
+                None => ("", qname),
+            };
+            if name.is_empty() {
+                return None;
+            }
+            let ns = match scope.get(prefix) {
+                Some(ns) => ns.clone(),
+                None if prefix.is_empty() => String::new(),
+                // undeclared prefix
+                None => return None,
+            };
+            let element = Element {
+                ns,
+                name: name.to_owned(),
+                children: vec![],
+                text: String::new(),
+            };
+            if empty {
+                match stack.last_mut() {
+                    Some(parent) => parent.0.children.push(element),
+                    None if root.is_none() => root = Some(element),
+                    None => return None,
+                }
+            } else {
+                scopes.push(scope);
+                stack.push((element, qname.to_owned()));
+            }
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = unescape(&rest[..end])?;
+            match stack.last_mut() {
+                Some(parent) => parent.0.text.push_str(&text),
+                None if text.trim().is_empty() => {}
+                None => return None,
+            }
+            rest = &rest[end..];
+        }
+    }
+    if stack.is_empty() {
+        root
+    } else {
+        None
+    }
+}
+
+// the `>` of the tag out of the quotes
+fn tag_end(s: &str) -> Option<usize> {
+    let mut quote = None;
+    for (idx, c) in s.char_indices().skip(1) {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '>') => return Some(idx),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn attributes(mut s: &str) -> Option<Vec<(String, String)>> {
+    let mut attrs = vec![];
+    loop {
+        s = s.trim_matches(|c: char| c.is_whitespace());
+        if s.is_empty() {
+            return Some(attrs);
+        }
+        let eq = s.find('=')?;
+        let name = s[..eq].trim().to_owned();
+        s = s[eq + 1..].trim_matches(|c: char| c.is_whitespace());
+        let quote = s.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let end = s[1..].find(quote)? + 1;
+        attrs.push((name, unescape(&s[1..end])?));
+        s = &s[end + 1..];
+    }
+}
+
+fn unescape(s: &str) -> Option<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(idx) = rest.find('&') {
+        out.push_str(&rest[..idx]);
+        let end = rest[idx..].find(';')? + idx;
+        let entity = &rest[idx + 1..end];
+        let c = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ if entity.starts_with("#x") => ::std::char::from_u32(u32::from_str_radix(&entity[2..], 16).ok()?)?,
+            _ if entity.starts_with('#') => ::std::char::from_u32(entity[1..].parse().ok()?)?,
+            _ => return None,
+        };
+        out.push(c);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+/// Escape the text or attribute value.
+pub(crate) fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[test]
+fn xml_test() {
+    let doc = "<?xml version=\"1.0\" encoding=\"utf-8\" ?>\n<!-- x -->\
+<D:propfind xmlns:D=\"DAV:\"><D:prop xmlns:z='urn:z'>\
+<D:getetag/><z:a x=\"1&gt;\">a &amp; b</z:a><c xmlns=\"urn:c\"><![CDATA[<>]]></c></D:prop></D:propfind>";
+    let root = parse(doc).unwrap();
+    assert!(root.is("DAV:", "propfind"));
+    let prop = root.child("DAV:", "prop").unwrap();
+    assert_eq!(
+        prop.children.iter().map(|c| (c.ns.as_str(), c.name.as_str(), c.text.as_str())).collect::<Vec<_>>(),
+        vec![("DAV:", "getetag", ""), ("urn:z", "a", "a & b"), ("urn:c", "c", "<>")]
+    );
+    assert_eq!(parse("<a><b></a></b>"), None);
+    assert_eq!(parse("<x:a/>"), None);
+    assert_eq!(parse("<a/><b/>"), None);
+}
@@ -0,0 +1,419 @@
+extern crate futures;
+extern crate futures_cpupool;
+extern crate hyper;
+extern crate tokio_core;
+
+use futures_cpupool::Builder;
+use tokio_core::reactor::Core;
+use tokio_core::net::TcpListener;
+use futures::Stream;
+use hyper::server::Http;
+
+extern crate hyper_fs;
+use hyper_fs::{Config, StaticFs, Trash};
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::mpsc::*;
+use std::thread::Builder as ThreadBuilder;
+use std::{env, fs, process};
+
+// the litmus(http://www.webdav.org/neon/litmus/) suites: basic, copymove, props and locks
+#[test]
+fn main() {
+    let root = env::temp_dir().join(format!("hyper-fs-webdav-{}", process::id()));
+    fs::remove_dir_all(&root).ok();
+    fs::create_dir(&root).unwrap();
+
+    let config = Config::new()
+        .hide_entry(true)
+        .write(true)
+        .recursive_delete(true)
+        .webdav(true);
+    let dav = start(&root, config.clone().show_index(true));
+    let unlisted = start(&root, config.clone());
+
+    basic(&dav, &root);
+    copymove(&dav, &root);
+    props(&dav, &root);
+    listing(&unlisted);
+    locks(&dav, &root);
+    confine(&dav, &root);
+    resume(&dav);
+    ranges(&dav);
+    trash(&root, config);
+
+    fs::remove_dir_all(&root).ok();
+}
+
+fn basic(dav: &Dav, root: &Path) {
+    let res = dav.request("OPTIONS", "/", &[], "");
+    assert_eq!(res.status, 200);
+    assert!(res.header("DAV").unwrap().contains('2'));
+
+    assert_eq!(dav.request("PUT", "/res", &[], "litmus").status, 201);
+    assert_eq!(dav.request("GET", "/res", &[], "").body, "litmus");
+    assert_eq!(dav.request("PUT", "/res", &[], "litmus2").status, 204);
+    assert_eq!(dav.request("PUT", "/res-%e2%82%ac", &[], "euro").status, 201);
+    assert_eq!(fs::read_to_string(root.join("res-\u{20ac}")).unwrap(), "euro");
+    assert_eq!(dav.request("MKCOL", "/res", &[], "").status, 405);
+    assert_eq!(dav.request("DELETE", "/res", &[], "").status, 204);
+    assert_eq!(dav.request("DELETE", "/res", &[], "").status, 404);
+    assert_eq!(dav.request("DELETE", "/res-%e2%82%ac", &[], "").status, 204);
+
+    assert_eq!(dav.request("MKCOL", "/coll/", &[], "").status, 201);
+    assert_eq!(dav.request("MKCOL", "/coll/", &[], "").status, 405);
+    assert_eq!(dav.request("PUT", "/coll/member", &[], "x").status, 201);
+    assert_eq!(dav.request("DELETE", "/coll/", &[], "").status, 204);
+    assert_eq!(dav.request("MKCOL", "/noparent/coll/", &[], "").status, 409);
+    assert_eq!(dav.request("PUT", "/noparent/res", &[], "x").status, 409);
+}
+
+fn copymove(dav: &Dav, root: &Path) {
+    assert_eq!(dav.request("PUT", "/src", &[], "source").status, 201);
+    assert_eq!(dav.request("MKCOL", "/coll/", &[], "").status, 201);
+
+    assert_eq!(dav.request("COPY", "/src", &[("Destination", &dav.url("/dest"))], "").status, 201);
+    assert_eq!(
+        dav.request("COPY", "/src", &[("Destination", "/dest"), ("Overwrite", "F")], "").status,
+        412
+    );
+    assert_eq!(dav.request("COPY", "/src", &[("Destination", "/dest"), ("Overwrite", "T")], "").status, 204);
+    assert_eq!(dav.request("COPY", "/src", &[("Destination", "/coll")], "").status, 204);
+    assert!(root.join("coll").is_file());
+    assert_eq!(dav.request("COPY", "/src", &[("Destination", "/nonesuch/dest")], "").status, 409);
+    assert_eq!(dav.request("COPY", "/src", &[("Destination", "/src")], "").status, 403);
+
+    assert_eq!(dav.request("DELETE", "/coll", &[], "").status, 204);
+    assert_eq!(dav.request("MKCOL", "/coll/", &[], "").status, 201);
+    assert_eq!(dav.request("PUT", "/coll/a", &[], "a").status, 201);
+    assert_eq!(dav.request("MKCOL", "/coll/sub/", &[], "").status, 201);
+    assert_eq!(dav.request("PUT", "/coll/sub/b", &[], "b").status, 201);
+    assert_eq!(dav.request("COPY", "/coll/", &[("Destination", "/copy/")], "").status, 201);
+    assert_eq!(fs::read_to_string(root.join("copy/sub/b")).unwrap(), "b");
+    assert_eq!(dav.request("COPY", "/coll/", &[("Destination", "/shallow/"), ("Depth", "0")], "").status, 201);
+    assert_eq!(fs::read_dir(root.join("shallow")).unwrap().count(), 0);
+    assert_eq!(dav.request("COPY", "/coll/", &[("Destination", "/coll/sub/x/")], "").status, 403);
+
+    assert_eq!(dav.request("MOVE", "/src", &[("Destination", "/moved")], "").status, 201);
+    assert_eq!(dav.request("GET", "/src", &[], "").status, 404);
+    assert_eq!(dav.request("MOVE", "/dest", &[("Destination", "/moved"), ("Overwrite", "F")], "").status, 412);
+    assert_eq!(dav.request("MOVE", "/dest", &[("Destination", "/moved")], "").status, 204);
+    assert_eq!(dav.request("MOVE", "/copy/", &[("Destination", "/coll/")], "").status, 204);
+    assert_eq!(dav.request("MOVE", "/coll/", &[("Destination", "/../outside")], "").status, 403);
+
+    for path in &["/moved", "/coll/", "/shallow/"] {
+        assert_eq!(dav.request("DELETE", path, &[], "").status, 204);
+    }
+}
+
+fn props(dav: &Dav, root: &Path) {
+    assert_eq!(dav.request("MKCOL", "/props/", &[], "").status, 201);
+    assert_eq!(dav.request("PUT", "/props/file.txt", &[], "12345").status, 201);
+    assert_eq!(dav.request("PUT", "/props/.hidden", &[], "").status, 403);
+    fs::write(root.join("props/.hidden"), "").unwrap();
+
+    let res = dav.request("PROPFIND", "/props/", &[("Depth", "0")], "");
+    assert_eq!(res.status, 207);
+    assert!(res.body.contains("<D:collection/>"));
+    assert!(!res.body.contains("file.txt"));
+
+    let res = dav.request("PROPFIND", "/props/", &[("Depth", "1")], "");
+    assert_eq!(res.status, 207);
+    assert!(res.body.contains("<D:href>/props/file.txt</D:href>"));
+    assert!(res.body.contains("<D:getcontentlength>5</D:getcontentlength>"));
+    assert!(res.body.contains("<D:getcontenttype>text/plain</D:getcontenttype>"));
+    // `Config::hide_entry`
+    assert!(!res.body.contains(".hidden"));
+
+    let propfind = "<?xml version=\"1.0\"?><propfind xmlns=\"DAV:\"><prop>\
+                    <getcontentlength/><foo xmlns=\"http://example.com/neon/litmus/\"/></prop></propfind>";
+    let res = dav.request("PROPFIND", "/props/file.txt", &[("Depth", "0")], propfind);
+    assert_eq!(res.status, 207);
+    assert!(res.body.contains("<D:getcontentlength>5</D:getcontentlength>"));
+    assert!(res.body.contains("<foo xmlns=\"http://example.com/neon/litmus/\"/>"));
+    assert!(res.body.contains("404 Not Found"));
+
+    let propname = "<propfind xmlns=\"DAV:\"><propname/></propfind>";
+    let res = dav.request("PROPFIND", "/props/file.txt", &[("Depth", "0")], propname);
+    assert!(res.body.contains("<D:getetag/>"));
+
+    assert_eq!(dav.request("PROPFIND", "/props/", &[("Depth", "0")], "<propfind").status, 400);
+    assert_eq!(dav.request("PROPFIND", "/props/", &[("Depth", "infinity")], "").status, 403);
+    assert_eq!(dav.request("PROPFIND", "/nonesuch", &[("Depth", "0")], "").status, 404);
+
+    let proppatch = "<?xml version=\"1.0\"?><D:propertyupdate xmlns:D=\"DAV:\"><D:set><D:prop>\
+                     <t:prop0 xmlns:t=\"http://example.com/neon/litmus/\">value0</t:prop0></D:prop></D:set></D:propertyupdate>";
+    let res = dav.request("PROPPATCH", "/props/file.txt", &[], proppatch);
+    assert_eq!(res.status, 207);
+    assert!(res.body.contains("403 Forbidden"));
+
+    assert_eq!(dav.request("DELETE", "/props/", &[], "").status, 204);
+}
+
+// the members are not listed without `Config::show_index`
+fn listing(dav: &Dav) {
+    assert_eq!(dav.request("MKCOL", "/unlisted/", &[], "").status, 201);
+    assert_eq!(dav.request("PUT", "/unlisted/file", &[], "").status, 201);
+    assert_eq!(dav.request("PROPFIND", "/unlisted/", &[("Depth", "1")], "").status, 403);
+    assert_eq!(dav.request("PROPFIND", "/unlisted/", &[("Depth", "0")], "").status, 207);
+    assert_eq!(dav.request("PROPFIND", "/unlisted/file", &[("Depth", "1")], "").status, 207);
+    assert_eq!(dav.request("DELETE", "/unlisted/", &[], "").status, 204);
+}
+
+fn locks(dav: &Dav, root: &Path) {
+    let lockinfo = |scope: &str| {
+        format!(
+            "<?xml version=\"1.0\"?><lockinfo xmlns=\"DAV:\"><lockscope><{}/></lockscope>\
+             <locktype><write/></locktype><owner>litmus test suite</owner></lockinfo>",
+            scope
+        )
+    };
+    assert_eq!(dav.request("PUT", "/lockme", &[], "x").status, 201);
+
+    let res = dav.request("LOCK", "/lockme", &[("Timeout", "Second-60")], &lockinfo("exclusive"));
+    assert_eq!(res.status, 200);
+    let token = res.header("Lock-Token").unwrap();
+    assert!(res.body.contains("litmus test suite"));
+    let cond = format!("({})", token);
+
+    assert_eq!(dav.request("PUT", "/lockme", &[], "y").status, 423);
+    assert_eq!(dav.request("DELETE", "/lockme", &[], "").status, 423);
+    assert_eq!(dav.request("LOCK", "/lockme", &[], &lockinfo("shared")).status, 423);
+    assert_eq!(dav.request("PUT", "/lockme", &[("If", "(<opaquelocktoken:bogus>)")], "y").status, 412);
+    assert_eq!(dav.request("PUT", "/lockme", &[("If", &cond)], "y").status, 204);
+    let tagged = format!("<{}> {}", dav.url("/lockme"), cond);
+    assert_eq!(dav.request("PUT", "/lockme", &[("If", &tagged)], "z").status, 204);
+
+    let res = dav.request("PROPFIND", "/lockme", &[("Depth", "0")], "");
+    assert!(res.body.contains(&token[1..token.len() - 1]));
+    let res = dav.request("LOCK", "/lockme", &[("If", &cond), ("Timeout", "Infinite")], "");
+    assert_eq!(res.status, 200);
+    assert!(res.body.contains("Infinite"));
+
+    assert_eq!(dav.request("UNLOCK", "/lockme", &[("Lock-Token", "<opaquelocktoken:bogus>")], "").status, 409);
+    assert_eq!(dav.request("UNLOCK", "/lockme", &[("Lock-Token", &token)], "").status, 204);
+    assert_eq!(dav.request("PUT", "/lockme", &[], "w").status, 204);
+
+    // shared
+    let res = dav.request("LOCK", "/lockme", &[], &lockinfo("shared"));
+    assert_eq!(res.status, 200);
+    let res2 = dav.request("LOCK", "/lockme", &[], &lockinfo("shared"));
+    assert_eq!(res2.status, 200);
+    let cond2 = format!("({})", res2.header("Lock-Token").unwrap());
+    assert_eq!(dav.request("PUT", "/lockme", &[("If", &cond2)], "v").status, 204);
+    assert_eq!(dav.request("DELETE", "/lockme", &[("If", &cond2)], "").status, 204);
+
+    // the unmapped URL
+    let res = dav.request("LOCK", "/locknull", &[], &lockinfo("exclusive"));
+    assert_eq!(res.status, 201);
+    assert!(root.join("locknull").is_file());
+    let cond = format!("({})", res.header("Lock-Token").unwrap());
+    assert_eq!(dav.request("DELETE", "/locknull", &[("If", &cond)], "").status, 204);
+
+    // depth infinity on the collection
+    assert_eq!(dav.request("MKCOL", "/lockcoll/", &[], "").status, 201);
+    let res = dav.request("LOCK", "/lockcoll/", &[("Depth", "infinity")], &lockinfo("exclusive"));
+    assert_eq!(res.status, 200);
+    let cond = format!("({})", res.header("Lock-Token").unwrap());
+    assert_eq!(dav.request("PUT", "/lockcoll/member", &[], "x").status, 423);
+    assert_eq!(dav.request("PUT", "/lockcoll/member", &[("If", &cond)], "x").status, 201);
+    assert_eq!(dav.request("MOVE", "/lockcoll/member", &[("Destination", "/free")], "").status, 423);
+    assert_eq!(dav.request("DELETE", "/lockcoll/", &[], "").status, 423);
+    assert_eq!(dav.request("DELETE", "/lockcoll/", &[("If", &cond)], "").status, 204);
+}
+
+// the writes can not escape the root by the symlinks or touch the hidden entries
+fn confine(dav: &Dav, root: &Path) {
+    let outside = root.with_extension("outside");
+    fs::create_dir(&outside).unwrap();
+    #[cfg(unix)]
+    ::std::os::unix::fs::symlink(&outside, root.join("out")).unwrap();
+
+    fs::write(outside.join("keep"), "").unwrap();
+
+    assert_eq!(dav.request("PUT", "/out/x", &[], "x").status, 403);
+    assert_eq!(dav.request("PUT", "/out/../x", &[], "x").status, 201);
+    assert_eq!(dav.request("PUT", "/.git/config", &[], "x").status, 403);
+
+    assert_eq!(dav.request("MKCOL", "/out/d/", &[], "").status, 403);
+    assert_eq!(dav.request("MKCOL", "/.git/", &[], "").status, 403);
+    assert_eq!(dav.request("MOVE", "/x", &[("Destination", "/out/x")], "").status, 403);
+    assert_eq!(dav.request("MOVE", "/x", &[("Destination", "/.x")], "").status, 403);
+    assert_eq!(dav.request("DELETE", "/out/keep", &[], "").status, 403);
+    assert_eq!(dav.request("DELETE", "/", &[], "").status, 403);
+    assert_eq!(dav.request("MKCOL", "/self/", &[], "").status, 201);
+    assert_eq!(dav.request("MOVE", "/self/", &[("Destination", "/self/sub/")], "").status, 403);
+    assert_eq!(dav.request("PUT", "/y", &[], "y").status, 201);
+    assert_eq!(dav.request("MOVE", "/x", &[("Destination", "/y"), ("Overwrite", "F")], "").status, 412);
+
+    assert_eq!(dav.request("COPY", "/y", &[("Destination", "/out/y")], "").status, 403);
+    assert_eq!(dav.request("COPY", "/out/keep", &[("Destination", "/keep")], "").status, 403);
+    assert_eq!(dav.request("COPY", "/y", &[("Destination", "/.y")], "").status, 403);
+    assert_eq!(dav.request("LOCK", "/out/z", &[], "").status, 403);
+    assert_eq!(dav.request("LOCK", "/.z", &[], "").status, 403);
+    assert_eq!(dav.request("PROPPATCH", "/out/keep", &[], "").status, 403);
+    // the link is copied, not the tree it points to
+    assert_eq!(dav.request("COPY", "/out", &[("Destination", "/out2")], "").status, 201);
+    assert!(fs::symlink_metadata(root.join("out2")).unwrap().file_type().is_symlink());
+
+    let names: Vec<_> = fs::read_dir(&outside).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(names, vec!["keep"]);
+
+    fs::remove_dir_all(&outside).ok();
+}
+
+// the multiple ranges are sent as `multipart/byteranges`
+fn ranges(dav: &Dav) {
+    let content = "0123456789".repeat(10);
+    assert_eq!(dav.request("PUT", "/ranges.txt", &[], &content).status, 201);
+    let res = dav.request("GET", "/ranges.txt", &[("Range", "bytes=50-51,0-1,1-2")], "");
+    assert_eq!(res.status, 206);
+    assert!(res.header("Content-Range").is_none());
+    let content_type = res.header("Content-Type").unwrap();
+    let boundary = content_type.trim_start_matches("multipart/byteranges; boundary=");
+    let part = |range: &str| format!("--{}\r\nContent-Type: text/plain\r\nContent-Range: bytes {}/100\r\n\r\n", boundary, range);
+    let body = format!("{}012\r\n{}01\r\n--{}--\r\n", part("0-2"), part("50-51"), boundary);
+    assert_eq!(res.body, body);
+    assert_eq!(res.header("Content-Length").unwrap(), body.len().to_string());
+    assert_eq!(dav.request("DELETE", "/ranges.txt", &[], "").status, 204);
+}
+
+// the trash is shared by the roots, the items are listed and restored only in the root they are deleted from
+fn trash(root: &Path, config: Config) {
+    let bin = root.with_extension("trash");
+    let config = config.trash(Arc::new(Trash::new(&bin)));
+    for tree in &["a", "b"] {
+        fs::create_dir(root.join(tree)).unwrap();
+    }
+    let (a, b) = (start(&root.join("a"), config.clone()), start(&root.join("b"), config.clone()));
+    let readonly = start(&root.join("a"), config.write(false));
+
+    assert_eq!(a.request("PUT", "/f", &[], "a").status, 201);
+    assert_eq!(a.request("DELETE", "/f", &[], "").status, 204);
+    assert_eq!(b.request("GET", "/?trash", &[], "").body, "");
+    let list = a.request("GET", "/?trash", &[], "").body;
+    assert!(list.ends_with("\t/f\n"));
+    let id = list.split('\t').next().unwrap().to_owned();
+    assert!(!readonly.request("GET", "/?trash", &[], "").body.contains(&id));
+
+    let restore = format!("/?restore={}", id);
+    assert_eq!(b.request("POST", &restore, &[], "").status, 404);
+    assert_eq!(readonly.request("POST", &restore, &[], "").status, 405);
+    assert_eq!(a.request("POST", &restore, &[], "").status, 201);
+    assert_eq!(a.request("GET", "/f", &[], "").body, "a");
+
+    // the parent is replaced by a link out of the root after the delete
+    assert_eq!(a.request("MKCOL", "/d/", &[], "").status, 201);
+    assert_eq!(a.request("PUT", "/d/x", &[], "x").status, 201);
+    assert_eq!(a.request("DELETE", "/d/x", &[], "").status, 204);
+    let id = a.request("GET", "/?trash", &[], "").body.split('\t').next().unwrap().to_owned();
+    fs::remove_dir(root.join("a/d")).unwrap();
+    #[cfg(unix)]
+    ::std::os::unix::fs::symlink(root.join("b"), root.join("a/d")).unwrap();
+    assert_eq!(a.request("POST", &format!("/?restore={}", id), &[], "").status, 403);
+    assert!(!root.join("b/x").exists());
+
+    fs::remove_dir_all(&bin).ok();
+}
+
+// the resumable uploads by `PUT` with `Content-Range`
+fn resume(dav: &Dav) {
+    let res = dav.request("PUT", "/up", &[("Content-Range", "bytes 0-2/*")], "abc");
+    assert_eq!(res.status, 202);
+    assert_eq!(res.header("Upload-Offset").unwrap(), "3");
+    assert_eq!(dav.request("HEAD", "/up", &[], "").header("Upload-Offset").unwrap(), "3");
+    // not at the offset
+    assert_eq!(dav.request("PUT", "/up", &[("Content-Range", "bytes 5-6/*")], "fg").status, 409);
+    assert_eq!(dav.request("PUT", "/up", &[("Content-Range", "bytes 3-5/*")], "def").status, 202);
+    assert_eq!(dav.request("PUT", "/up", &[("Content-Range", "bytes */7")], "").status, 409);
+    assert_eq!(dav.request("PUT", "/up", &[("Content-Range", "bytes */6")], "").status, 201);
+    assert_eq!(dav.request("GET", "/up", &[], "").body, "abcdef");
+    assert!(dav.request("HEAD", "/up", &[], "").header("Upload-Offset").is_none());
+
+    assert_eq!(dav.request("PUT", "/up2", &[("Content-Range", "bytes 0-1/4")], "ab").status, 202);
+    assert_eq!(dav.request("PUT", "/up2", &[("Content-Range", "bytes 2-3/4")], "cd").status, 201);
+    assert_eq!(dav.request("GET", "/up2", &[], "").body, "abcd");
+}
+
+struct Response {
+    status: u16,
+    head: String,
+    body: String,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<String> {
+        self.head.lines().skip(1).find_map(|line| {
+            let mut kv = line.splitn(2, ':');
+            match (kv.next(), kv.next()) {
+                (Some(k), Some(v)) if k.eq_ignore_ascii_case(name) => Some(v.trim().to_owned()),
+                _ => None,
+            }
+        })
+    }
+}
+
+struct Dav(SocketAddr);
+
+// the server of the `root` on a thread
+fn start(root: &Path, config: Config) -> Dav {
+    let (mp, sc) = channel();
+    let root = root.to_path_buf();
+    ThreadBuilder::new().spawn(move || serve(root, config, &mp)).unwrap();
+    Dav(sc.recv().unwrap())
+}
+
+impl Dav {
+    fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.0, path)
+    }
+    fn request(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &str) -> Response {
+        let mut stream = TcpStream::connect(self.0).unwrap();
+        let mut req = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            method,
+            path,
+            self.0,
+            body.len()
+        );
+        for &(k, v) in headers {
+            req.push_str(&format!("{}: {}\r\n", k, v));
+        }
+        req.push_str("\r\n");
+        req.push_str(body);
+        stream.write_all(req.as_bytes()).unwrap();
+        let mut res = String::new();
+        stream.read_to_string(&mut res).unwrap();
+        let idx = res.find("\r\n\r\n").expect("incomplete response");
+        let (head, body) = (res[..idx].to_owned(), res[idx + 4..].to_owned());
+        let status = head[9..12].parse().unwrap();
+        Response {
+            status,
+            head,
+            body,
+        }
+    }
+}
+
+// `bind_connection` as the tests of `StaticFs`
+#[allow(deprecated)]
+fn serve(root: PathBuf, config: Config, mp: &Sender<SocketAddr>) {
+    let pool = Builder::new().pool_size(3).name_prefix("hyper-fs").create();
+
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+    mp.send(listener.local_addr().unwrap()).unwrap();
+
+    let fs_server = Rc::new(StaticFs::new(handle.clone(), pool, "/", root, config));
+    let http = Http::new();
+    let server = listener.incoming().for_each(|(socket, addr)| {
+        http.bind_connection(&handle, socket, addr, fs_server.clone());
+        Ok(())
+    });
+    core.run(server).unwrap();
+}